@@ -34,7 +34,7 @@ fn main() {
     println!("{comp:?}");
 
     for _ in 0..20 {
-        comp.until_yield();
+        comp.until_yield().unwrap();
         println!("{}", comp.get_mem(0x0000));
 
         // comp.tick();
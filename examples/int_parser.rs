@@ -92,18 +92,18 @@ fn main() {
     std::io::stdin().read_line(&mut input).unwrap();
 
     // input all the data
-    comp.until_yield();
+    comp.until_yield().unwrap();
     println!("{comp:?}");
     for k in input.chars() {
         comp.set_mem(0x0000, k as u16);
-        comp.debug_until_yield();
+        comp.debug_until_yield().unwrap();
         println!("{k}\n{comp:?}");
     }
 
     // add null terminator
     println!("Adding Null Terminator");
     comp.set_mem(0x0000, 0x0000);
-    comp.debug_until_yield();
+    comp.debug_until_yield().unwrap();
     println!("{comp:?}");
 
     // get the output
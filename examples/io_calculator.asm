@@ -1,9 +1,13 @@
+// print the string at `text`, then carry on at `then`
+.macro print_then text then;
+MOV text &string_location;
+MOV then &callback_location;
+JMP #print;
+.endm;
 MOV #first_number_buffer &number_buffer;
 MOV #second_input &second_input_buf;
 :get_input
-MOV &number_buffer &string_location;
-MOV #read_input &callback_location;
-JMP #print;
+print_then &number_buffer #read_input;
 :read_input
 MOV #2 &12;
 MOV #input_buffer r3;
@@ -35,9 +39,7 @@ MOV #second_number_buffer &number_buffer;
 JMP #get_input;
 :math
 MOV r0 rE;
-MOV #operation_buffer &string_location;
-MOV #math_loop &callback_location;
-JMP #print;
+print_then #operation_buffer #math_loop;
 :math_loop
 MOV #2 &12;
 YIELD;
@@ -1,4 +1,4 @@
-use computer::{Computer, ComputerDebug, ComputerIO, CPU};
+use computer::{Computer, ComputerIO, CPU};
 
 const PROGRAM_LOCATION: u16 = 0x8000;
 const PRINT_LOCATION: u16 = 0x8800;
@@ -284,6 +284,6 @@ fn program() -> ComputerIO<CPU> {
 fn main() {
     let mut comp = program();
     // comp.debug_until_yield();
-    comp.until_yield();
+    comp.until_yield().unwrap();
     // println!("{}", comp.get_mem(0));
 }
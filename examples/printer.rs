@@ -27,7 +27,7 @@ fn prog() -> CPU {
 fn comp_println(comp: &mut CPU) {
     let mut str_buf = String::new();
     loop {
-        comp.until_yield();
+        comp.until_yield().unwrap();
         let char = comp.get_mem(0xF).try_into().unwrap_or(b'\n') as char;
         if char == '\n' {
             break;
@@ -149,7 +149,7 @@ fn prog() -> CPU {
 fn comp_println(comp: &mut CPU) {
     let mut str_buf = String::new();
     loop {
-        comp.until_yield();
+        comp.until_yield().unwrap();
         println!("{str_buf}");
         println!("{comp:?}");
         let char = char::from_u32(comp.get_mem(0x0).into()).unwrap_or_default();
@@ -170,17 +170,17 @@ fn main() {
     std::io::stdin().read_line(&mut input).unwrap();
 
     // input all the data
-    comp.until_yield();
+    comp.until_yield().unwrap();
     for k in input.chars() {
         comp.set_mem(0x0000, k as u16);
-        comp.until_yield();
+        comp.until_yield().unwrap();
         println!("{k}\n{comp:?}");
     }
 
     // add null terminator
     println!("Adding Null Terminator");
     comp.set_mem(0x0000, 0x0000);
-    comp.until_yield();
+    comp.until_yield().unwrap();
     println!("{comp:?}");
 
     // print the result
@@ -1,16 +1,256 @@
-use std::{rc::Rc, str::FromStr};
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 use strum::EnumString;
 
+use crate::{Image, Object};
+
+use include::Sources;
+
+mod decode;
+mod include;
 mod instruction;
+mod lexer;
+mod listing;
+mod macros;
+pub mod stack;
 mod syntax;
 
+pub use decode::decode;
 pub use instruction::{CmpOp, Instruction, Item, MathOp, Value};
-pub use syntax::{interpret_syntax, Syntax};
+pub use listing::{parse_symbol_map, symbol_map, Listing};
+pub use syntax::{
+    interpret_syntax, interpret_syntax_listed, interpret_syntax_object, Labels, Syntax,
+};
+
+/// where something is in the source: a 1-based line and column, and a length, in characters.
+/// `file` is `0` for the source being assembled, or counts up through the files it includes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    pub file: usize,
+}
+
+impl Span {
+    /// a span covering both `self` and `other`, or just `self` if they're on different lines
+    #[must_use]
+    pub const fn to(self, other: Self) -> Self {
+        if other.file == self.file && other.line == self.line && other.column >= self.column {
+            Self {
+                len: other.column + other.len - self.column,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    /// the text this span covers in `src`
+    #[must_use]
+    pub fn text(self, src: &str) -> &str {
+        let line = src.lines().nth(self.line - 1).unwrap_or_default();
+        let start = line
+            .char_indices()
+            .nth(self.column - 1)
+            .map_or(line.len(), |(idx, _)| idx);
+        let end = line[start..]
+            .char_indices()
+            .nth(self.len)
+            .map_or(line.len(), |(idx, _)| start + idx);
+        &line[start..end]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// a word that isn't a keyword, literal, address or label
+    BadToken(String),
+    /// a name that would be read as a number or a register, so can't be a label
+    ReservedName(Rc<str>),
+    /// an escape sequence in a string or character that doesn't mean anything
+    BadEscape(String),
+    /// a statement that doesn't fit its instruction; `found` is `None` at the end of the input
+    Expected {
+        expected: String,
+        found: Option<Rc<str>>,
+    },
+    /// a ternary math operation with two literal sources, which has no encoding
+    TwoLiterals,
+    UndefinedLabel(Rc<str>),
+    DuplicateLabel(Rc<str>),
+    /// a label or constant used to say how many words to reserve before it's defined
+    DefinedAfter(Rc<str>),
+    /// code placed over the registers, where labels would be confused with them
+    LowOrigin(u16),
+    /// a section used for the first time without an origin
+    NoOrigin(Rc<str>),
+    /// two segments that both use an address
+    Overlap(u16),
+    /// a segment that runs past `0xFFFF`
+    OutOfMemory,
+    /// a segment before the load address of a flat program
+    BeforeOrigin(u16),
+    /// constants or origins that depend on themselves, so never stop changing
+    Unsettled,
+    /// an expression that isn't a number plus the address of one thing, so can't be linked, or is
+    /// used somewhere that needs to be known before linking
+    Relocatable(Rc<str>),
+    /// an origin in an object, which the linker decides
+    ObjectOrigin,
+    DuplicateMacro(Rc<str>),
+    /// a macro used with the wrong number of arguments
+    MacroArgs {
+        name: Rc<str>,
+        expected: usize,
+        found: usize,
+    },
+    /// a macro that expands itself, directly or not
+    MacroRecursion(Rc<str>),
+    /// a file that couldn't be included
+    Include {
+        path: Rc<str>,
+        reason: Rc<str>,
+    },
+    /// a file that includes itself, directly or not
+    IncludeCycle(Rc<str>),
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadToken(token) => write!(f, "unrecognized token `{token}`"),
+            Self::ReservedName(name) => write!(
+                f,
+                "`{name}` reads as a number or a register, so it can't be a name"
+            ),
+            Self::BadEscape(escape) => write!(f, "unknown escape sequence `{escape}`"),
+            Self::Expected {
+                expected,
+                found: Some(found),
+            } => write!(f, "expected {expected}, found `{found}`"),
+            Self::Expected {
+                expected,
+                found: None,
+            } => write!(f, "expected {expected}, found the end of the input"),
+            Self::TwoLiterals => write!(f, "ternary math operations can't have two literals"),
+            Self::UndefinedLabel(label) => write!(f, "undefined label `{label}`"),
+            Self::DuplicateLabel(label) => write!(f, "label `{label}` is already defined"),
+            Self::DefinedAfter(label) => write!(
+                f,
+                "`{label}` has to be defined before it's used to say how many words to reserve"
+            ),
+            Self::LowOrigin(origin) => write!(
+                f,
+                "can't put code at `{origin:0>4X}`, which is a register; use `0010` or later"
+            ),
+            Self::NoOrigin(section) => write!(
+                f,
+                "section `{section}` needs an origin the first time it's used, like `.section {section} #A000;`"
+            ),
+            Self::Overlap(address) => write!(f, "two segments both use `{address:0>4X}`"),
+            Self::OutOfMemory => write!(f, "the program runs past the end of memory"),
+            Self::BeforeOrigin(origin) => write!(
+                f,
+                "a segment starts at `{origin:0>4X}`, before the load address `{:0>4X}`",
+                Image::DEFAULT_ORIGIN
+            ),
+            Self::Unsettled => write!(
+                f,
+                "the labels never settle down, because a constant or `.org` depends on itself"
+            ),
+            Self::Relocatable(expr) => write!(
+                f,
+                "`{expr}` depends on where the program is linked, so it can't be worked out here"
+            ),
+            Self::ObjectOrigin => write!(
+                f,
+                "objects can't choose their own addresses; the linker places their sections"
+            ),
+            Self::DuplicateMacro(name) => write!(f, "macro `{name}` is already defined"),
+            Self::MacroArgs {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "macro `{name}` takes {expected} argument{}, but was given {found}",
+                if *expected == 1 { "" } else { "s" }
+            ),
+            Self::MacroRecursion(name) => write!(f, "macro `{name}` expands itself forever"),
+            Self::Include { path, reason } => write!(f, "can't include `{path}`: {reason}"),
+            Self::IncludeCycle(path) => write!(f, "`{path}` ends up including itself"),
+        }
+    }
+}
 
-#[derive(Debug)]
-pub enum ASMError {
-    TokenError,
-    SyntaxError(Vec<Token>),
+/// An assembler error, and where it happened if that's known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ASMError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+    /// the file the error is in, if it isn't the source that was passed in
+    pub file: Option<Rc<str>>,
+    /// the text of `file`
+    pub source: Option<Rc<str>>,
+}
+
+impl ASMError {
+    #[must_use]
+    pub const fn new(kind: ErrorKind, span: Option<Span>) -> Self {
+        Self {
+            kind,
+            span,
+            file: None,
+            source: None,
+        }
+    }
+
+    /// show the error with the offending source underlined, like rustc does; `src` is the source
+    /// that was assembled, named `file`
+    #[must_use]
+    pub fn render(&self, file: &str, src: &str) -> String {
+        let file = self.file.as_deref().unwrap_or(file);
+        let src = self.source.as_deref().unwrap_or(src);
+        render(&format!("error: {}", self.kind), self.span, file, src)
+    }
+}
+
+/// `heading`, then where `span` is in `src`, named `file`, with the line it's on and the span
+/// underlined
+pub fn render(heading: &str, span: Option<Span>, file: &str, src: &str) -> String {
+    let Some(span) = span else {
+        return format!("{heading}\n --> {file}");
+    };
+    let line = src.lines().nth(span.line - 1).unwrap_or_default();
+    let gutter = " ".repeat(span.line.to_string().len());
+    let indent: String = line
+        .chars()
+        .take(span.column - 1)
+        .map(|char| if char == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "{heading}\n{gutter}--> {file}:{line_number}:{column}\n{gutter} |\n{line_number} | {line}\n{gutter} | {indent}{carets}",
+        line_number = span.line,
+        column = span.column,
+        carets = "^".repeat(span.len.max(1)),
+    )
+}
+
+impl Display for ASMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        if let Some(span) = self.span {
+            write!(f, "{}:{}: ", span.line, span.column)?;
+        }
+        write!(f, "{}", self.kind)
+    }
 }
 
 #[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
@@ -42,17 +282,41 @@ pub enum Keyword {
     Shl,
     Shr,
     Yield,
+    Halt,
+    Reti,
     Ptrread,
     Ptrwrite,
     Reserve,
+    Push,
+    Pop,
+    Call,
+    Ret,
+}
+
+#[derive(EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum Directive {
+    Org,
+    Equ,
+    Align,
+    Section,
+    Macro,
+    Endm,
+    Include,
+    Global,
 }
 
 #[derive(Debug, Clone)]
 pub enum Token {
     Keyword(Keyword),
+    Directive(Directive),
+    /// a bare name, like a constant or section
+    Name(Rc<str>),
     Literal(Value),
     Address(Value),
     Label(Rc<str>),
+    /// a string, which is data unless it's the path of an include
+    Str(Rc<str>),
     SemiColon,
 }
 
@@ -65,96 +329,117 @@ pub fn string_literal(src: &str) -> Vec<Syntax> {
     toks
 }
 
-fn lex(src: &str) -> Option<Vec<Token>> {
-    // apply string literals
-    let mut stringparts = src.split('"');
-    let mut string = String::with_capacity(src.len());
-    loop {
-        let Some(code) = stringparts.next() else { break };
-        string.push_str(code);
-        let Some(lit) = stringparts.next() else { break };
-        for char in lit.chars() {
-            string.push('#');
-            string.push_str(&format!(
-                "{:X}",
-                u16::try_from(char as u32).unwrap_or(0xFFFE)
-            ));
-            string.push(';');
-            string.push(' ');
-        }
-        string.push_str("#0; ");
-    }
-    // get actual tokens
-    string
-        .split_whitespace()
-        .map(|str| {
-            let (str, is_semicolon) = str
-                .strip_suffix(';')
-                .map_or((str, false), |str| (str, true));
-            str.strip_prefix(':')
-                .map_or_else(
-                    || {
-                        str.strip_prefix('&').map_or_else(
-                            || {
-                                str.strip_prefix('r').map_or_else(
-                                    || {
-                                        str.strip_prefix('#').map_or_else(
-                                            || Keyword::from_str(str).ok().map(Token::Keyword),
-                                            |str| {
-                                                u16::from_str_radix(str, 16)
-                                                    .map(Value::Given)
-                                                    .map(Token::Literal)
-                                                    .map_or_else(
-                                                        |_| {
-                                                            Some(Token::Literal(Value::Label(
-                                                                Rc::from(str),
-                                                            )))
-                                                        },
-                                                        Some,
-                                                    )
-                                            },
-                                        )
-                                    },
-                                    |str| {
-                                        u16::from_str_radix(str, 16)
-                                            .map(Value::Given)
-                                            .map(Token::Address)
-                                            .ok()
-                                    },
-                                )
-                            },
-                            |str| {
-                                u16::from_str_radix(str, 16)
-                                    .map(Value::Given)
-                                    .map(Token::Address)
-                                    .map_or_else(
-                                        |_| Some(Token::Address(Value::Label(Rc::from(str)))),
-                                        Some,
-                                    )
-                            },
-                        )
-                    },
-                    |str| Some(Token::Label(Rc::from(str))),
-                )
-                .map(|tok| {
-                    if is_semicolon {
-                        vec![tok, Token::SemiColon]
-                    } else {
-                        vec![tok]
-                    }
-                })
-        })
-        .collect::<Option<Vec<Vec<_>>>>()
-        .map(Vec::into_iter)
-        .map(Iterator::flatten)
-        .map(Iterator::collect)
-}
-
 #[allow(clippy::module_name_repetitions)]
 /// # Errors
 /// if the asm syntax is bad
 pub fn compile_asm(src: &str) -> Result<Vec<u16>, ASMError> {
-    let toks = lex(src).ok_or(ASMError::TokenError)?;
-    // println!("{toks:?}");
-    syntax::interpret(&toks).map_err(ASMError::SyntaxError)
+    compile_asm_with_labels(src).map(|(machine_code, _)| machine_code)
+}
+
+/// like `compile_asm`, but also returns the address of each label
+/// # Errors
+/// if the asm syntax is bad, or a segment is before `Image::DEFAULT_ORIGIN`
+pub fn compile_asm_with_labels(src: &str) -> Result<(Vec<u16>, Labels), ASMError> {
+    let (image, labels) = compile_asm_image(src)?;
+    Ok((syntax::flatten(&image)?, labels))
+}
+
+/// assemble a program that may be made of several segments, returning it and the address of each
+/// label. Includes are looked for in the current directory.
+/// # Errors
+/// if the asm syntax is bad, an include can't be read, or the segments don't fit in memory
+pub fn compile_asm_image(src: &str) -> Result<(Image, Labels), ASMError> {
+    assemble("<input>", src, None, &[], |_, statements| {
+        syntax::assemble(statements)
+    })
+}
+
+/// assemble a program into an object for the linker to place. Labels that aren't defined are
+/// looked for in the other objects, and `.global` ones can be used by them.
+/// # Errors
+/// if the asm syntax is bad, an include can't be read, or an expression can't be linked
+pub fn compile_asm_object(src: &str) -> Result<Object, ASMError> {
+    assemble("<input>", src, None, &[], |_, statements| {
+        syntax::assemble_object(statements)
+    })
+}
+
+/// like `compile_asm_image`, but reads the source from `path`. Includes are looked for next to the
+/// file that includes them, then in each of `include_paths`.
+/// # Errors
+/// if `path` or an include can't be read, the asm syntax is bad, or the segments don't fit in
+/// memory
+pub fn compile_asm_file(
+    path: &Path,
+    include_paths: &[PathBuf],
+) -> Result<(Image, Labels), ASMError> {
+    let src = read(path)?;
+    assemble(
+        &path.display().to_string(),
+        &src,
+        Some(path),
+        include_paths,
+        |_, statements| syntax::assemble(statements),
+    )
+}
+
+/// like `compile_asm_file`, but also lists where each line of the source went
+/// # Errors
+/// if `path` or an include can't be read, the asm syntax is bad, or the segments don't fit in
+/// memory
+pub fn compile_asm_file_listed(
+    path: &Path,
+    include_paths: &[PathBuf],
+) -> Result<(Image, Labels, Listing), ASMError> {
+    let src = read(path)?;
+    assemble(
+        &path.display().to_string(),
+        &src,
+        Some(path),
+        include_paths,
+        |sources, statements| syntax::assemble_listed(statements, Some(sources)),
+    )
+}
+
+/// like `compile_asm_object`, but reads the source from `path`, like `compile_asm_file`
+/// # Errors
+/// if `path` or an include can't be read, the asm syntax is bad, or an expression can't be linked
+pub fn compile_asm_object_file(path: &Path, include_paths: &[PathBuf]) -> Result<Object, ASMError> {
+    let src = read(path)?;
+    assemble(
+        &path.display().to_string(),
+        &src,
+        Some(path),
+        include_paths,
+        |_, statements| syntax::assemble_object(statements),
+    )
+}
+
+fn read(path: &Path) -> Result<String, ASMError> {
+    fs::read_to_string(path).map_err(|err| {
+        ASMError::new(
+            ErrorKind::Include {
+                path: Rc::from(path.display().to_string()),
+                reason: Rc::from(err.to_string()),
+            },
+            None,
+        )
+    })
+}
+
+/// lex, expand and parse `src`, then `finish` the statements
+fn assemble<T>(
+    name: &str,
+    src: &str,
+    path: Option<&Path>,
+    include_paths: &[PathBuf],
+    finish: impl FnOnce(&Sources, &[(Syntax, Option<Span>)]) -> Result<T, ASMError>,
+) -> Result<T, ASMError> {
+    let (tokens, sources) = include::load(name, src, path, include_paths);
+    tokens
+        .and_then(|(tokens, spans)| {
+            let (tokens, spans) = macros::expand(&sources, &tokens, &spans)?;
+            finish(&sources, &syntax::parse(&sources, &tokens, &spans)?)
+        })
+        .map_err(|err| sources.locate(err))
 }
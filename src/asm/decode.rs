@@ -0,0 +1,119 @@
+use crate::{cpu::validate, CPU};
+
+use super::instruction::{CmpOp, Instruction, Item, MathOp, Value};
+
+/// decode the instruction at the start of `code`, returning it and how many words it used.
+/// Words the CPU would fault on, or that are missing their extension words, decode as
+/// `Instruction::Data`.
+/// # Panics
+/// if `code` is empty
+#[must_use]
+#[allow(clippy::too_many_lines)]
+pub fn decode(code: &[u16]) -> (Instruction, usize) {
+    let word = code[0];
+    let data = (Instruction::Data(word), 1);
+    match word {
+        CPU::YIELD_INSTRUCTION => return (Instruction::Yield, 1),
+        CPU::HALT_INSTRUCTION => return (Instruction::Halt, 1),
+        CPU::RETI_INSTRUCTION => return (Instruction::Reti, 1),
+        _ => {}
+    }
+    if validate(0, word).is_err() {
+        return data;
+    }
+    let (opcode, form, high, low) = (word >> 12, word >> 8 & 0xF, word >> 4 & 0xF, word & 0xF);
+    // the mode, up to three arguments, and how many extension words they take
+    let (mode, args, extension_len): (u16, [Option<u16>; 3], usize) = match opcode {
+        // MOV/JMP and PTR
+        0 | 0xA => match form {
+            0xD => (high, [Some(low), None, None], 1),
+            0xE => (high, [None, Some(low), None], 1),
+            0xF => (high, [None, None, None], 2),
+            mode => (mode, [Some(high), Some(low), None], 0),
+        },
+        // comparisons
+        4..=9 => match form {
+            0xC => (high, [Some(low), None, None], 2),
+            0xD => (high, [None, Some(low), None], 2),
+            0xE => (high, [None, None, Some(low)], 2),
+            0xF => (high, [None, None, None], 3),
+            mode => (mode, [Some(high), Some(low), None], 1),
+        },
+        // math, where only the ternary modes have a third argument
+        _ => {
+            let ternary = usize::from(if form >= 0xC { high } else { form } >= 2);
+            match form {
+                0xC => (high, [Some(low), None, None], 1 + ternary),
+                0xD => (high, [None, Some(low), None], 1 + ternary),
+                0xE => (high, [None, None, Some(low)], 2),
+                0xF => (high, [None, None, None], 2 + ternary),
+                mode => (mode, [Some(high), Some(low), None], ternary),
+            }
+        }
+    };
+    let Some(extension) = code.get(1..=extension_len) else {
+        return data;
+    };
+    let mut extension = extension.iter().copied();
+    let [a, b, c] = args.map(|arg| arg.or_else(|| extension.next()).unwrap_or_default());
+    let addr = |value| Item::Address(Value::Given(value));
+    let lit = |value| Item::Literal(Value::Given(value));
+    let (a_value, b_value, c_value) = (Value::Given(a), Value::Given(b), Value::Given(c));
+    let instruction = match opcode {
+        0 => match mode {
+            0 => Instruction::Mov(addr(a), b_value),
+            1 => Instruction::Mov(lit(a), b_value),
+            2 => Instruction::Swp(a_value, b_value),
+            3 => Instruction::Jmp(addr(a)),
+            4 => Instruction::Jmp(lit(a)),
+            5 => Instruction::Jcmpz(true, a_value, addr(b)),
+            6 => Instruction::Jcmpz(true, a_value, lit(b)),
+            7 => Instruction::Jcmpz(false, a_value, addr(b)),
+            _ => Instruction::Jcmpz(false, a_value, lit(b)),
+        },
+        0xA => match mode {
+            0 => Instruction::Ptrread(a_value.clone(), a_value),
+            1 => Instruction::Ptrread(a_value, b_value),
+            2 => Instruction::Ptrwrite(addr(a), b_value),
+            _ => Instruction::Ptrwrite(lit(a), b_value),
+        },
+        4..=9 => {
+            let cmp_op = [
+                CmpOp::Eq,
+                CmpOp::Ne,
+                CmpOp::Lt,
+                CmpOp::Le,
+                CmpOp::Gt,
+                CmpOp::Ge,
+            ][usize::from(opcode - 4)];
+            match mode {
+                0 => Instruction::JmpCmp(cmp_op, a_value, addr(b), addr(c)),
+                1 => Instruction::JmpCmp(cmp_op, a_value, addr(b), lit(c)),
+                2 => Instruction::JmpCmp(cmp_op, a_value, lit(b), addr(c)),
+                3 => Instruction::JmpCmp(cmp_op, a_value, lit(b), lit(c)),
+                4 => Instruction::Cmp(cmp_op, a_value, addr(b), c_value),
+                _ => Instruction::Cmp(cmp_op, a_value, lit(b), c_value),
+            }
+        }
+        _ => {
+            let math_op = match opcode {
+                1 => MathOp::Add,
+                2 => MathOp::Sub,
+                3 => MathOp::Mul,
+                0xB => MathOp::And,
+                0xC => MathOp::Or,
+                0xD => MathOp::Xor,
+                0xE => MathOp::Shl,
+                _ => MathOp::Shr,
+            };
+            match mode {
+                0 => Instruction::MathBinary(math_op, addr(a), b_value),
+                1 => Instruction::MathBinary(math_op, lit(a), b_value),
+                2 => Instruction::MathTernary(math_op, addr(a), addr(b), c_value),
+                3 => Instruction::MathTernary(math_op, lit(a), addr(b), c_value),
+                _ => Instruction::MathTernary(math_op, addr(a), lit(b), c_value),
+            }
+        }
+    };
+    (instruction, 1 + extension_len)
+}
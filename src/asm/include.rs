@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{
+    lexer::{self, Lexed},
+    ASMError, Directive, ErrorKind, Span, Token,
+};
+
+/// The name and text of each file in a program; the first is the one being assembled
+pub struct Sources {
+    files: Vec<(Rc<str>, Rc<str>)>,
+}
+
+impl Sources {
+    /// the text `span` covers, in whichever file it's in
+    pub fn text(&self, span: Span) -> &str {
+        span.text(&self.files[span.file].1)
+    }
+
+    /// the whole line `span` is on
+    pub fn line(&self, span: Span) -> &str {
+        self.files[span.file]
+            .1
+            .lines()
+            .nth(span.line - 1)
+            .unwrap_or_default()
+    }
+
+    /// the line number of `span`, with the file's name if it isn't the first one
+    pub fn location(&self, span: Span) -> String {
+        if span.file == 0 {
+            span.line.to_string()
+        } else {
+            format!("{}:{}", self.files[span.file].0, span.line)
+        }
+    }
+
+    /// point `err` at the file it happened in, if that isn't the first one
+    pub fn locate(&self, mut err: ASMError) -> ASMError {
+        if let Some(span) = err.span.filter(|span| span.file != 0) {
+            let (name, text) = &self.files[span.file];
+            err.file = Some(name.clone());
+            err.source = Some(text.clone());
+        }
+        err
+    }
+}
+
+/// Reads included files, keeping track of the ones being included to catch cycles
+struct Includer<'a> {
+    sources: Sources,
+    include_paths: &'a [PathBuf],
+    /// the files currently being read, outermost first
+    stack: Vec<PathBuf>,
+}
+
+/// lex `src` and every file it includes, along with the sources of all of them. Includes are
+/// looked for next to the file doing the including, then in each of `include_paths`. `path` is
+/// where `src` came from; without it, includes are looked for in the current directory.
+pub fn load(
+    name: &str,
+    src: &str,
+    path: Option<&Path>,
+    include_paths: &[PathBuf],
+) -> (Result<Lexed, ASMError>, Sources) {
+    let mut includer = Includer {
+        sources: Sources {
+            files: vec![(Rc::from(name), Rc::from(src))],
+        },
+        include_paths,
+        stack: path
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()))
+            .into_iter()
+            .collect(),
+    };
+    let dir = path
+        .and_then(Path::parent)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let result = includer.file(0, dir);
+    (result, includer.sources)
+}
+
+impl Includer<'_> {
+    /// lex file `idx`, which is in `dir`, replacing its includes with their tokens
+    fn file(&mut self, idx: usize, dir: &Path) -> Result<Lexed, ASMError> {
+        let src = self.sources.files[idx].1.clone();
+        let (tokens, spans) = lexer::lex(&src, idx)?;
+        let mut out_tokens = Vec::new();
+        let mut out_spans = Vec::new();
+        let mut rest = 0;
+        while rest < tokens.len() {
+            match &tokens[rest..] {
+                [Token::Directive(Directive::Include), Token::Str(path), Token::SemiColon, ..] => {
+                    let span = spans[rest].to(spans[rest + 2]);
+                    let (included, included_spans) = self.include(path, dir, span)?;
+                    out_tokens.extend(included);
+                    out_spans.extend(included_spans);
+                    rest += 3;
+                }
+                [Token::Directive(Directive::Include), ..] => {
+                    let span = spans[rest];
+                    return Err(ASMError::new(
+                        ErrorKind::Expected {
+                            expected: String::from("`.include \"path\";`"),
+                            found: Some(Rc::from(self.sources.text(span))),
+                        },
+                        Some(span),
+                    ));
+                }
+                [token, ..] => {
+                    out_tokens.push(token.clone());
+                    out_spans.push(spans[rest]);
+                    rest += 1;
+                }
+                [] => unreachable!(),
+            }
+        }
+        Ok((out_tokens, out_spans))
+    }
+
+    /// find `path` and lex it
+    fn include(&mut self, path: &str, dir: &Path, span: Span) -> Result<Lexed, ASMError> {
+        let error = |kind| ASMError::new(kind, Some(span));
+        let found = std::iter::once(dir)
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| {
+                error(ErrorKind::Include {
+                    path: Rc::from(path),
+                    reason: Rc::from("it isn't in any of the include paths"),
+                })
+            })?;
+        let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+        if self.stack.contains(&canonical) {
+            return Err(error(ErrorKind::IncludeCycle(Rc::from(path))));
+        }
+        let text = fs::read_to_string(&found).map_err(|err| {
+            error(ErrorKind::Include {
+                path: Rc::from(path),
+                reason: Rc::from(err.to_string()),
+            })
+        })?;
+        self.sources
+            .files
+            .push((Rc::from(found.display().to_string()), Rc::from(text)));
+        let idx = self.sources.files.len() - 1;
+        self.stack.push(canonical);
+        let result = self.file(idx, found.parent().unwrap_or_else(|| Path::new(".")));
+        self.stack.pop();
+        result
+    }
+}
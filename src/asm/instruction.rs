@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, fmt::Display, rc::Rc};
+use std::{collections::BTreeMap, convert::Infallible, fmt::Display, rc::Rc};
 
 use strum::AsRefStr;
 
@@ -6,9 +6,13 @@ use crate::CPU;
 
 use super::{Keyword, Token};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
+    /// a word that isn't an instruction
+    Data(u16),
     Yield,
+    Halt,
+    Reti,
     Mov(Item, Value),
     Swp(Value, Value),
     Jmp(Item),
@@ -24,7 +28,10 @@ pub enum Instruction {
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Data(word) => write!(f, "#{word:0>4X};"),
             Self::Yield => write!(f, "YIELD;"),
+            Self::Halt => write!(f, "HALT;"),
+            Self::Reti => write!(f, "RETI;"),
             Self::Mov(src, dst) => write!(f, "MOV {src} &{dst};"),
             Self::Swp(src, dst) => write!(f, "SWP &{src} &{dst};"),
             Self::Jmp(dst) => write!(f, "JMP {dst};"),
@@ -35,7 +42,7 @@ impl Display for Instruction {
             Self::Ptrwrite(src, dst) => write!(f, "PTRWRITE {src} &{dst};"),
             Self::MathBinary(math_op, src, dst) => write!(f, "{} {src} &{dst};", math_op.as_ref()),
             Self::MathTernary(math_op, src, srca, dst) => {
-                write!(f, "{} {src} {srca} &{dst}", math_op.as_ref())
+                write!(f, "{} {src} {srca} &{dst};", math_op.as_ref())
             }
             Self::JmpCmp(cmp_op, src, srca, jmp) => {
                 write!(f, "J{} &{src} {srca} {jmp};", cmp_op.as_ref())
@@ -47,7 +54,7 @@ impl Display for Instruction {
     }
 }
 
-#[derive(Debug, Clone, Copy, AsRefStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum MathOp {
     Add,
@@ -78,6 +85,7 @@ impl TryFrom<Keyword> for MathOp {
 }
 
 impl MathOp {
+    #[must_use]
     pub const fn first_nibble(self) -> u16 {
         match self {
             Self::Add => 0x1000,
@@ -90,9 +98,45 @@ impl MathOp {
             Self::Shr => 0xF000,
         }
     }
+
+    /// work out `lhs op rhs` the way the CPU does
+    #[must_use]
+    pub const fn apply(self, lhs: u16, rhs: u16) -> u16 {
+        match self {
+            Self::Add => lhs.wrapping_add(rhs),
+            Self::Sub => lhs.wrapping_sub(rhs),
+            Self::Mul => lhs.wrapping_mul(rhs),
+            Self::And => lhs & rhs,
+            Self::Or => lhs | rhs,
+            Self::Xor => lhs ^ rhs,
+            Self::Shl => match lhs.checked_shl(rhs as u32) {
+                Some(value) => value,
+                None => 0,
+            },
+            Self::Shr => match lhs.checked_shr(rhs as u32) {
+                Some(value) => value,
+                None => 0,
+            },
+        }
+    }
+
+    /// how the operation is written in an expression
+    #[must_use]
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::And => "&",
+            Self::Or => "|",
+            Self::Xor => "^",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, AsRefStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum CmpOp {
     Eq,
@@ -119,6 +163,7 @@ impl TryFrom<Keyword> for CmpOp {
 }
 
 impl CmpOp {
+    #[must_use]
     pub const fn first_nibble(self) -> u16 {
         match self {
             Self::Eq => 0x4000,
@@ -130,6 +175,7 @@ impl CmpOp {
         }
     }
 
+    #[must_use]
     pub const fn inverse(self) -> Self {
         match self {
             Self::Eq => Self::Ne,
@@ -158,13 +204,23 @@ impl Display for Item {
 }
 
 impl Item {
-    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>) -> Self {
-        match self {
-            Self::Address(addr) => Self::Address(addr.with_labels(labels)),
-            Self::Literal(lit) => Self::Literal(lit.with_labels(labels)),
-        }
+    /// # Errors
+    /// the name of the label, if it isn't defined
+    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>, here: u16) -> Result<Self, Rc<str>> {
+        self.try_map(|value| value.with_labels(labels, here))
     }
 
+    /// the item with its value replaced by `f`
+    /// # Errors
+    /// the error from `f`
+    pub fn try_map<E>(self, f: impl FnOnce(Value) -> Result<Value, E>) -> Result<Self, E> {
+        Ok(match self {
+            Self::Address(addr) => Self::Address(f(addr)?),
+            Self::Literal(lit) => Self::Literal(f(lit)?),
+        })
+    }
+
+    #[must_use]
     pub const fn to_number(&self) -> u16 {
         match self {
             Self::Address(addr) => addr.to_number(),
@@ -188,6 +244,10 @@ impl TryFrom<Token> for Item {
 pub enum Value {
     Given(u16),
     Label(Rc<str>),
+    /// `$`, the address of the instruction it's in
+    Here,
+    /// two values combined, worked out once the labels are known
+    Expr(MathOp, Box<Self>, Box<Self>),
 }
 
 impl Display for Value {
@@ -195,6 +255,8 @@ impl Display for Value {
         match self {
             Self::Given(v) => write!(f, "{v:0>4X}"),
             Self::Label(v) => write!(f, "{v}"),
+            Self::Here => write!(f, "$"),
+            Self::Expr(op, lhs, rhs) => write!(f, "({lhs}{}{rhs})", op.symbol()),
         }
     }
 }
@@ -206,62 +268,94 @@ impl Default for Value {
 }
 
 impl Value {
+    #[must_use]
     pub const fn to_number(&self) -> u16 {
         match self {
             Self::Given(num) => *num,
-            Self::Label(_) => u16::MAX,
+            Self::Label(_) | Self::Here | Self::Expr(..) => u16::MAX,
+        }
+    }
+
+    /// # Errors
+    /// the name of the label, if it isn't defined
+    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>, here: u16) -> Result<Self, Rc<str>> {
+        self.evaluate(labels, here).map(Self::Given)
+    }
+
+    /// the number this stands for, where `here` is the address of the instruction it's in
+    /// # Errors
+    /// the name of the label, if it isn't defined
+    pub fn evaluate(&self, labels: &BTreeMap<Rc<str>, u16>, here: u16) -> Result<u16, Rc<str>> {
+        match self {
+            Self::Given(num) => Ok(*num),
+            Self::Label(label) => labels.get(label).copied().ok_or_else(|| label.clone()),
+            Self::Here => Ok(here),
+            Self::Expr(op, lhs, rhs) => {
+                Ok(op.apply(lhs.evaluate(labels, here)?, rhs.evaluate(labels, here)?))
+            }
         }
     }
 
-    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>) -> Self {
+    /// the value with every label replaced by what `replace` gives for it
+    #[must_use]
+    pub fn replace_labels(&self, replace: &mut impl FnMut(&Rc<str>) -> Self) -> Self {
         match self {
-            Self::Given(num) => Self::Given(num),
-            Self::Label(label) => Self::Given(
-                labels
-                    .get(&label)
-                    .copied()
-                    .unwrap_or_else(|| panic!("No valid label found for {label}")),
+            Self::Label(label) => replace(label),
+            Self::Expr(op, lhs, rhs) => Self::Expr(
+                *op,
+                Box::new(lhs.replace_labels(replace)),
+                Box::new(rhs.replace_labels(replace)),
             ),
+            value => value.clone(),
         }
     }
 }
 
 impl Instruction {
-    #[allow(clippy::too_many_lines)]
+    /// # Panics
+    /// for a ternary math operation with two literals, which has no encoding
+    #[must_use]
     pub fn to_machine_code(&self) -> Vec<u16> {
+        let mut operands = Vec::new();
+        let Ok(_) = self.clone().try_map(|value| {
+            operands.push(value.to_number());
+            Ok::<_, Infallible>(value)
+        });
+        let (first, rest) = self.layout();
+        std::iter::once(first)
+            .chain(rest.into_iter().map(|idx| operands[idx]))
+            .collect()
+    }
+
+    /// the first word of the machine code for `self`, and which operand goes in each word after
+    /// it, counting operands in the order `try_map` visits them
+    /// # Panics
+    /// for a ternary math operation with two literals, which has no encoding
+    #[allow(clippy::too_many_lines)]
+    #[must_use]
+    pub fn layout(&self) -> (u16, Vec<usize>) {
         match self {
-            Self::Yield => vec![CPU::YIELD_INSTRUCTION],
+            Self::Data(word) => (*word, vec![]),
+            Self::Yield => (CPU::YIELD_INSTRUCTION, vec![]),
+            Self::Halt => (CPU::HALT_INSTRUCTION, vec![]),
+            Self::Reti => (CPU::RETI_INSTRUCTION, vec![]),
             Self::Mov(src, dst) => {
                 let mode = match src {
                     Item::Address(_) => 0,
                     Item::Literal(_) => 1,
                 };
                 match (src.to_number(), dst.to_number()) {
-                    (lit @ 0..=0xF, dst @ 0..=0xF) => {
-                        vec![mode << 8 | (lit << 4) | dst]
-                    }
-                    (lit, dst @ 0..=0xF) => {
-                        vec![0x0E00 | mode << 4 | dst, lit]
-                    }
-                    (lit @ 0..=0xF, dst) => {
-                        vec![0x0D00 | mode << 4 | lit, dst]
-                    }
-                    (lit, dst) => vec![0x0F00 | mode << 4, lit, dst],
+                    (lit @ 0..=0xF, dst @ 0..=0xF) => (mode << 8 | (lit << 4) | dst, vec![]),
+                    (_, dst @ 0..=0xF) => (0x0E00 | mode << 4 | dst, vec![0]),
+                    (lit @ 0..=0xF, _) => (0x0D00 | mode << 4 | lit, vec![1]),
+                    _ => (0x0F00 | mode << 4, vec![0, 1]),
                 }
             }
             Self::Swp(src, dst) => match (src.to_number(), dst.to_number()) {
-                (src @ 0..=0xF, dst @ 0..=0xF) => {
-                    vec![0x0200 | src << 4 | dst]
-                }
-                (src @ 0..=0xF, dst) => {
-                    vec![0x0D20 | src, dst]
-                }
-                (src, dst @ 0..=0xF) => {
-                    vec![0x0E20 | dst, src]
-                }
-                (src, dst) => {
-                    vec![0x0F20, src, dst]
-                }
+                (src @ 0..=0xF, dst @ 0..=0xF) => (0x0200 | src << 4 | dst, vec![]),
+                (src @ 0..=0xF, _) => (0x0D20 | src, vec![1]),
+                (_, dst @ 0..=0xF) => (0x0E20 | dst, vec![0]),
+                _ => (0x0F20, vec![0, 1]),
             },
             Self::Jmp(jmp) => {
                 let mode = match jmp {
@@ -270,9 +364,9 @@ impl Instruction {
                 };
                 let jmp = jmp.to_number();
                 if jmp <= 0xF {
-                    vec![mode << 8 | (jmp << 4)]
+                    (mode << 8 | (jmp << 4), vec![])
                 } else {
-                    vec![0x0E00 | mode << 4, jmp]
+                    (0x0E00 | mode << 4, vec![0])
                 }
             }
             Self::Jcmpz(cmp, cnd, jump) => {
@@ -283,18 +377,10 @@ impl Instruction {
                     (false, Item::Literal(_)) => 8,
                 };
                 match (cnd.to_number(), jump.to_number()) {
-                    (cnd @ 0..=0xF, jump @ 0..=0xF) => {
-                        vec![mode << 8 | cnd << 4 | jump]
-                    }
-                    (cnd @ 0..=0xF, jump) => {
-                        vec![0x0D00 | mode << 4 | cnd, jump]
-                    }
-                    (cnd, jump @ 0..=0xF) => {
-                        vec![0x0E00 | mode << 4 | jump, cnd]
-                    }
-                    (cnd, jump) => {
-                        vec![0x0F00 | mode << 4, cnd, jump]
-                    }
+                    (cnd @ 0..=0xF, jump @ 0..=0xF) => (mode << 8 | cnd << 4 | jump, vec![]),
+                    (cnd @ 0..=0xF, _) => (0x0D00 | mode << 4 | cnd, vec![1]),
+                    (_, jump @ 0..=0xF) => (0x0E00 | mode << 4 | jump, vec![0]),
+                    _ => (0x0F00 | mode << 4, vec![0, 1]),
                 }
             }
             Self::Ptrread(src, dst) => {
@@ -302,27 +388,15 @@ impl Instruction {
                 let dst = dst.to_number();
                 if src == dst {
                     match src {
-                        src @ 0..=0xF => {
-                            vec![0xA000 | src << 4]
-                        }
-                        src => {
-                            vec![0xAE00, src]
-                        }
+                        src @ 0..=0xF => (0xA000 | src << 4, vec![]),
+                        _ => (0xAE00, vec![0]),
                     }
                 } else {
                     match (src, dst) {
-                        (src @ 0..=0xF, dst @ 0..=0xF) => {
-                            vec![0xA100 | src << 4 | dst]
-                        }
-                        (src @ 0..=0xF, dst) => {
-                            vec![0xAD10 | src, dst]
-                        }
-                        (src, dst @ 0..=0xF) => {
-                            vec![0xAE10 | dst, src]
-                        }
-                        (src, dst) => {
-                            vec![0xAF10, src, dst]
-                        }
+                        (src @ 0..=0xF, dst @ 0..=0xF) => (0xA100 | src << 4 | dst, vec![]),
+                        (src @ 0..=0xF, _) => (0xAD10 | src, vec![1]),
+                        (_, dst @ 0..=0xF) => (0xAE10 | dst, vec![0]),
+                        _ => (0xAF10, vec![0, 1]),
                     }
                 }
             }
@@ -332,18 +406,10 @@ impl Instruction {
                     Item::Literal(_) => 3,
                 };
                 match (src.to_number(), dst.to_number()) {
-                    (src @ 0..=0xF, dst @ 0..=0xF) => {
-                        vec![0xA000 | mode << 8 | src << 4 | dst]
-                    }
-                    (src @ 0..=0xF, dst) => {
-                        vec![0xAD00 | mode << 4 | src, dst]
-                    }
-                    (src, dst @ 0..=0xF) => {
-                        vec![0xAE00 | mode << 4 | dst, src]
-                    }
-                    (src, dst) => {
-                        vec![0xAF00 | mode << 4, src, dst]
-                    }
+                    (src @ 0..=0xF, dst @ 0..=0xF) => (0xA000 | mode << 8 | src << 4 | dst, vec![]),
+                    (src @ 0..=0xF, _) => (0xAD00 | mode << 4 | src, vec![1]),
+                    (_, dst @ 0..=0xF) => (0xAE00 | mode << 4 | dst, vec![0]),
+                    _ => (0xAF00 | mode << 4, vec![0, 1]),
                 }
             }
             Self::MathBinary(math_op, src, dst) => {
@@ -351,21 +417,12 @@ impl Instruction {
                     Item::Address(_) => 0,
                     Item::Literal(_) => 1,
                 };
-                let src = src.to_number();
-                let dst = dst.to_number();
-                match (src, dst) {
-                    (src @ 0..=0xF, dst @ 0..=0xF) => {
-                        vec![math_op.first_nibble() | mode << 8 | src << 4 | dst]
-                    }
-                    (src @ 0..=0xF, dst) => {
-                        vec![math_op.first_nibble() | 0x0C00 | mode << 4 | src, dst]
-                    }
-                    (src, dst @ 0..=0xF) => {
-                        vec![math_op.first_nibble() | 0x0D00 | mode << 4 | dst, src]
-                    }
-                    (src, dst) => {
-                        vec![math_op.first_nibble() | 0x0E00 | mode << 4, src, dst]
-                    }
+                let op = math_op.first_nibble();
+                match (src.to_number(), dst.to_number()) {
+                    (src @ 0..=0xF, dst @ 0..=0xF) => (op | mode << 8 | src << 4 | dst, vec![]),
+                    (src @ 0..=0xF, _) => (op | 0x0C00 | mode << 4 | src, vec![1]),
+                    (_, dst @ 0..=0xF) => (op | 0x0D00 | mode << 4 | dst, vec![0]),
+                    _ => (op | 0x0E00 | mode << 4, vec![0, 1]),
                 }
             }
             Self::MathTernary(math_op, src_a, src, dst) => {
@@ -375,34 +432,15 @@ impl Instruction {
                     (Item::Address(_), Item::Literal(_)) => 4,
                     _ => panic!("Invalid use of ternary math op like `ADD #LIT #LIT &DST`"),
                 };
+                let op = math_op.first_nibble();
                 match (src_a.to_number(), src.to_number(), dst.to_number()) {
-                    (src_a @ 0..=0xF, src @ 0..=0xF, dst) => {
-                        vec![math_op.first_nibble() | mode << 8 | src_a << 4 | src, dst]
-                    }
-                    (src_a @ 0..=0xF, src, dst) => {
-                        vec![
-                            math_op.first_nibble() | 0x0C00 | mode << 4 | src_a,
-                            src,
-                            dst,
-                        ]
-                    }
-                    (src_a, src @ 0..=0xF, dst) => {
-                        vec![
-                            math_op.first_nibble() | 0x0D00 | mode << 4 | src,
-                            src_a,
-                            dst,
-                        ]
-                    }
-                    (src_a, src, dst @ 0..=0xF) => {
-                        vec![
-                            math_op.first_nibble() | 0x0E00 | mode << 4 | dst,
-                            src,
-                            src_a,
-                        ]
-                    }
-                    (src_a, src, dst) => {
-                        vec![math_op.first_nibble() | 0x0F00 | mode << 4, src_a, src, dst]
+                    (src_a @ 0..=0xF, src @ 0..=0xF, _) => {
+                        (op | mode << 8 | src_a << 4 | src, vec![2])
                     }
+                    (src_a @ 0..=0xF, _, _) => (op | 0x0C00 | mode << 4 | src_a, vec![1, 2]),
+                    (_, src @ 0..=0xF, _) => (op | 0x0D00 | mode << 4 | src, vec![0, 2]),
+                    (_, _, dst @ 0..=0xF) => (op | 0x0E00 | mode << 4 | dst, vec![0, 1]),
+                    _ => (op | 0x0F00 | mode << 4, vec![0, 1, 2]),
                 }
             }
             Self::JmpCmp(cmp_op, src, src_a, jmp) => {
@@ -412,22 +450,15 @@ impl Instruction {
                     (Item::Literal(_), Item::Address(_)) => 2,
                     (Item::Literal(_), Item::Literal(_)) => 3,
                 };
+                let op = cmp_op.first_nibble();
                 match (src.to_number(), src_a.to_number(), jmp.to_number()) {
-                    (src @ 0..=0xF, src_a @ 0..=0xF, jmp) => {
-                        vec![cmp_op.first_nibble() | (mode << 8) | (src << 4) | src_a, jmp]
-                    }
-                    (src @ 0..=0xF, src_a, jmp) => {
-                        vec![cmp_op.first_nibble() | 0x0C00 | (mode << 4) | src, src_a, jmp]
-                    }
-                    (src, src_a @ 0..=0xF, jmp) => {
-                        vec![cmp_op.first_nibble() | 0x0D00 | (mode << 4) | src_a, src, jmp]
-                    }
-                    (src, src_a, jmp @ 0..=0xF) => {
-                        vec![cmp_op.first_nibble() | 0x0E00 | (mode << 4) | jmp, src, src_a]
-                    }
-                    (src, src_a, jmp) => {
-                        vec![cmp_op.first_nibble() | 0x0F00 | (mode << 4), src, src_a, jmp]
+                    (src @ 0..=0xF, src_a @ 0..=0xF, _) => {
+                        (op | (mode << 8) | (src << 4) | src_a, vec![2])
                     }
+                    (src @ 0..=0xF, _, _) => (op | 0x0C00 | (mode << 4) | src, vec![1, 2]),
+                    (_, src_a @ 0..=0xF, _) => (op | 0x0D00 | (mode << 4) | src_a, vec![0, 2]),
+                    (_, _, jmp @ 0..=0xF) => (op | 0x0E00 | (mode << 4) | jmp, vec![0, 1]),
+                    _ => (op | 0x0F00 | (mode << 4), vec![0, 1, 2]),
                 }
             }
             Self::Cmp(cmp_op, src, src_a, dst) => {
@@ -435,57 +466,49 @@ impl Instruction {
                     Item::Address(_) => 4,
                     Item::Literal(_) => 5,
                 };
+                let op = cmp_op.first_nibble();
                 match (src.to_number(), src_a.to_number(), dst.to_number()) {
-                    (src @ 0..=0xF, src_a @ 0..=0xF, dst) => {
-                        vec![cmp_op.first_nibble() | mode << 8 | src << 4 | src_a, dst]
-                    }
-                    (src @ 0..=0xF, src_a, dst) => {
-                        vec![cmp_op.first_nibble() | 0x0C00 | mode << 4 | src, src_a, dst]
-                    }
-                    (src, src_a @ 0..=0xF, dst) => {
-                        vec![cmp_op.first_nibble() | 0x0D00 | mode << 4 | src_a, src, dst]
-                    }
-                    (src, src_a, dst @ 0..=0xF) => {
-                        vec![cmp_op.first_nibble() | 0x0E00 | mode << 4 | dst, src, src_a]
-                    }
-                    (src, src_a, dst) => {
-                        vec![cmp_op.first_nibble() | 0x0F00 | mode << 4, src, src_a, dst]
+                    (src @ 0..=0xF, src_a @ 0..=0xF, _) => {
+                        (op | mode << 8 | src << 4 | src_a, vec![2])
                     }
+                    (src @ 0..=0xF, _, _) => (op | 0x0C00 | mode << 4 | src, vec![1, 2]),
+                    (_, src_a @ 0..=0xF, _) => (op | 0x0D00 | mode << 4 | src_a, vec![0, 2]),
+                    (_, _, dst @ 0..=0xF) => (op | 0x0E00 | mode << 4 | dst, vec![0, 1]),
+                    _ => (op | 0x0F00 | mode << 4, vec![0, 1, 2]),
                 }
             }
         }
     }
 
-    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>) -> Self {
-        match self {
+    /// # Errors
+    /// the name of the first label that isn't defined; `here` is the address of the instruction
+    pub fn with_labels(self, labels: &BTreeMap<Rc<str>, u16>, here: u16) -> Result<Self, Rc<str>> {
+        self.try_map(|value| value.with_labels(labels, here))
+    }
+
+    /// the instruction with the value of each operand replaced by `f`
+    /// # Errors
+    /// the first error from `f`
+    pub fn try_map<E>(self, mut f: impl FnMut(Value) -> Result<Value, E>) -> Result<Self, E> {
+        Ok(match self {
+            Self::Data(word) => Self::Data(word),
             Self::Yield => Self::Yield,
-            Self::Mov(a, b) => Self::Mov(a.with_labels(labels), b.with_labels(labels)),
-            Self::Swp(a, b) => Self::Swp(a.with_labels(labels), b.with_labels(labels)),
-            Self::Jmp(a) => Self::Jmp(a.with_labels(labels)),
-            Self::Jcmpz(a, b, c) => Self::Jcmpz(a, b.with_labels(labels), c.with_labels(labels)),
-            Self::Ptrread(a, b) => Self::Ptrread(a.with_labels(labels), b.with_labels(labels)),
-            Self::Ptrwrite(a, b) => Self::Ptrwrite(a.with_labels(labels), b.with_labels(labels)),
-            Self::MathBinary(op, a, b) => {
-                Self::MathBinary(op, a.with_labels(labels), b.with_labels(labels))
+            Self::Halt => Self::Halt,
+            Self::Reti => Self::Reti,
+            Self::Mov(a, b) => Self::Mov(a.try_map(&mut f)?, f(b)?),
+            Self::Swp(a, b) => Self::Swp(f(a)?, f(b)?),
+            Self::Jmp(a) => Self::Jmp(a.try_map(&mut f)?),
+            Self::Jcmpz(a, b, c) => Self::Jcmpz(a, f(b)?, c.try_map(&mut f)?),
+            Self::Ptrread(a, b) => Self::Ptrread(f(a)?, f(b)?),
+            Self::Ptrwrite(a, b) => Self::Ptrwrite(a.try_map(&mut f)?, f(b)?),
+            Self::MathBinary(op, a, b) => Self::MathBinary(op, a.try_map(&mut f)?, f(b)?),
+            Self::MathTernary(op, a, b, c) => {
+                Self::MathTernary(op, a.try_map(&mut f)?, b.try_map(&mut f)?, f(c)?)
             }
-            Self::MathTernary(op, a, b, c) => Self::MathTernary(
-                op,
-                a.with_labels(labels),
-                b.with_labels(labels),
-                c.with_labels(labels),
-            ),
-            Self::JmpCmp(op, a, b, c) => Self::JmpCmp(
-                op,
-                a.with_labels(labels),
-                b.with_labels(labels),
-                c.with_labels(labels),
-            ),
-            Self::Cmp(op, a, b, c) => Self::Cmp(
-                op,
-                a.with_labels(labels),
-                b.with_labels(labels),
-                c.with_labels(labels),
-            ),
-        }
+            Self::JmpCmp(op, a, b, c) => {
+                Self::JmpCmp(op, f(a)?, b.try_map(&mut f)?, c.try_map(&mut f)?)
+            }
+            Self::Cmp(op, a, b, c) => Self::Cmp(op, f(a)?, b.try_map(&mut f)?, f(c)?),
+        })
     }
 }
@@ -0,0 +1,421 @@
+use std::{rc::Rc, str::Chars, str::FromStr};
+
+use super::{ASMError, Directive, ErrorKind, Keyword, MathOp, Span, Token, Value};
+
+/// tokens, and where each one is
+pub type Lexed = (Vec<Token>, Vec<Span>);
+
+/// Walks through the source a character at a time, keeping track of the line and column
+struct Cursor<'a> {
+    chars: Chars<'a>,
+    line: usize,
+    column: usize,
+    file: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let char = self.chars.next()?;
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(char)
+    }
+
+    /// a zero-length span at the cursor
+    const fn here(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            len: 0,
+            file: self.file,
+        }
+    }
+
+    /// the span from `start` up to the cursor, or just the first character if it's on a later line
+    const fn since(&self, start: Span) -> Span {
+        Span {
+            len: if self.line == start.line {
+                self.column - start.column
+            } else {
+                1
+            },
+            ..start
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while self.peek().is_some_and(|char| char != '\n') {
+            self.next();
+        }
+    }
+
+    /// read a run of characters that can make up a keyword, register, number or label
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(char) = self
+            .peek()
+            .filter(|&char| char.is_alphanumeric() || matches!(char, '_' | '.' | '%' | '+' | '-'))
+        {
+            self.next();
+            word.push(char);
+        }
+        word
+    }
+
+    /// read a number or label
+    fn value_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(char) = self
+            .peek()
+            .filter(|&char| char.is_alphanumeric() || matches!(char, '_' | '.' | '%'))
+        {
+            self.next();
+            word.push(char);
+        }
+        word
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    /// the operator at the cursor, and how many characters it is
+    fn operator(&self) -> Option<(MathOp, usize)> {
+        match (self.peek()?, self.peek_second()) {
+            ('+', _) => Some((MathOp::Add, 1)),
+            ('-', _) => Some((MathOp::Sub, 1)),
+            ('*', _) => Some((MathOp::Mul, 1)),
+            ('&', _) => Some((MathOp::And, 1)),
+            ('|', _) => Some((MathOp::Or, 1)),
+            ('^', _) => Some((MathOp::Xor, 1)),
+            ('<', Some('<')) => Some((MathOp::Shl, 2)),
+            ('>', Some('>')) => Some((MathOp::Shr, 2)),
+            _ => None,
+        }
+    }
+
+    /// read the value of an operand that started at `start`, made of terms joined by operators that
+    /// bind at least as tightly as `power`. Spaces are only allowed inside parentheses.
+    fn expression(
+        &mut self,
+        src: &str,
+        start: Span,
+        nested: bool,
+        power: u8,
+    ) -> Result<Value, ASMError> {
+        let mut lhs = self.term(src, start)?;
+        loop {
+            if nested {
+                self.skip_spaces();
+            }
+            let Some((op, len)) = self.operator().filter(|&(op, _)| precedence(op) >= power) else {
+                return Ok(lhs);
+            };
+            for _ in 0..len {
+                self.next();
+            }
+            if nested {
+                self.skip_spaces();
+            }
+            let rhs = self.expression(src, start, nested, precedence(op) + 1)?;
+            lhs = Value::Expr(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    /// read a number, label, character, `$`, negated term or parenthesised expression
+    fn term(&mut self, src: &str, start: Span) -> Result<Value, ASMError> {
+        let term = self.here();
+        match self.peek() {
+            Some('(') => {
+                self.next();
+                self.skip_spaces();
+                let value = self.expression(src, start, true, 0)?;
+                self.skip_spaces();
+                if self.peek() != Some(')') {
+                    let found = self.here();
+                    self.next();
+                    let found = self.since(found);
+                    return Err(ASMError::new(
+                        ErrorKind::Expected {
+                            expected: String::from("`)`"),
+                            found: (found.len > 0).then(|| Rc::from(found.text(src))),
+                        },
+                        Some(found),
+                    ));
+                }
+                self.next();
+                Ok(value)
+            }
+            Some('$') => {
+                self.next();
+                Ok(Value::Here)
+            }
+            Some('\'') => {
+                self.next();
+                let (Some(char), Some('\'')) = (self.char('\'', start)?, self.next()) else {
+                    let span = self.since(start);
+                    return Err(bad_token(span.text(src).to_string(), span));
+                };
+                Ok(Value::Given(encode(char)))
+            }
+            Some('-') => {
+                self.next();
+                Ok(match self.term(src, start)? {
+                    Value::Given(number) => Value::Given(number.wrapping_neg()),
+                    value => Value::Expr(MathOp::Sub, Box::new(Value::Given(0)), Box::new(value)),
+                })
+            }
+            _ => {
+                let word = self.value_word();
+                if register(&word).is_some() {
+                    return Err(reserved(&word, self.since(term)));
+                }
+                value(&word).ok_or_else(|| {
+                    // show a bad operand on its own, or a missing term, with the rest of the operand
+                    let first = term.line == start.line && term.column == start.column + 1;
+                    let span = self.since(if first || word.is_empty() {
+                        start
+                    } else {
+                        term
+                    });
+                    bad_token(span.text(src).to_string(), span)
+                })
+            }
+        }
+    }
+
+    /// read a character that might be an escape sequence, ending at `quote`
+    fn char(&mut self, quote: char, start: Span) -> Result<Option<char>, ASMError> {
+        let unterminated = |cursor: &Self| {
+            ASMError::new(
+                ErrorKind::Expected {
+                    expected: format!("`{quote}`"),
+                    found: None,
+                },
+                Some(cursor.since(start)),
+            )
+        };
+        let escape = self.here();
+        match self.next() {
+            None => Err(unterminated(self)),
+            Some(char) if char == quote => Ok(None),
+            Some('\\') => {
+                let char = match self.next() {
+                    None => return Err(unterminated(self)),
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some(char @ ('\\' | '"' | '\'')) => char,
+                    Some('u') if self.peek() == Some('{') => {
+                        self.next();
+                        let mut digits = String::new();
+                        while let Some(char) = self.next().filter(|&char| char != '}') {
+                            digits.push(char);
+                        }
+                        u32::from_str_radix(&digits, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| {
+                                ASMError::new(
+                                    ErrorKind::BadEscape(format!("\\u{{{digits}}}")),
+                                    Some(self.since(escape)),
+                                )
+                            })?
+                    }
+                    Some(char) => {
+                        return Err(ASMError::new(
+                            ErrorKind::BadEscape(format!("\\{char}")),
+                            Some(self.since(escape)),
+                        ))
+                    }
+                };
+                Ok(Some(char))
+            }
+            Some(char) => Ok(Some(char)),
+        }
+    }
+}
+
+/// split `src` into tokens, along with where each one is in file number `file`
+pub fn lex(src: &str, file: usize) -> Result<Lexed, ASMError> {
+    let mut cursor = Cursor {
+        chars: src.chars(),
+        line: 1,
+        column: 1,
+        file,
+    };
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    while let Some(char) = cursor.peek() {
+        let start = cursor.here();
+        let token = match char {
+            _ if char.is_whitespace() => {
+                cursor.next();
+                continue;
+            }
+            // comments
+            '/' if cursor.peek_second() == Some('/') => {
+                cursor.skip_line();
+                continue;
+            }
+            '#' if cursor.peek_second() == Some('!') => {
+                cursor.skip_line();
+                continue;
+            }
+            ';' => {
+                cursor.next();
+                Token::SemiColon
+            }
+            '"' => {
+                cursor.next();
+                let mut string = String::new();
+                while let Some(char) = cursor.char('"', start)? {
+                    string.push(char);
+                }
+                Token::Str(Rc::from(string))
+            }
+            ':' => {
+                cursor.next();
+                let word = cursor.word();
+                if is_reserved(&word) {
+                    return Err(reserved(&word, cursor.since(start)));
+                }
+                if !is_label(&word) {
+                    return Err(bad_token(format!(":{word}"), cursor.since(start)));
+                }
+                Token::Label(Rc::from(word))
+            }
+            '#' | '&' => {
+                cursor.next();
+                let value = cursor.expression(src, start, false, 0)?;
+                if char == '#' {
+                    Token::Literal(value)
+                } else {
+                    Token::Address(value)
+                }
+            }
+            _ => {
+                let mut word = cursor.word();
+                if word.is_empty() {
+                    word.extend(cursor.next());
+                }
+                word_token(&word).ok_or_else(|| {
+                    if is_reserved(&word) {
+                        reserved(&word, cursor.since(start))
+                    } else {
+                        bad_token(word, cursor.since(start))
+                    }
+                })?
+            }
+        };
+        tokens.push(token);
+        spans.push(cursor.since(start));
+    }
+    Ok((tokens, spans))
+}
+
+const fn bad_token(token: String, span: Span) -> ASMError {
+    ASMError::new(ErrorKind::BadToken(token), Some(span))
+}
+
+fn reserved(word: &str, span: Span) -> ASMError {
+    ASMError::new(ErrorKind::ReservedName(Rc::from(word)), Some(span))
+}
+
+/// the word for a character, or `0xFFFE` if it doesn't fit in one
+fn encode(char: char) -> u16 {
+    u16::try_from(u32::from(char)).unwrap_or(0xFFFE)
+}
+
+/// how tightly an operator binds in an expression, like in C
+const fn precedence(op: MathOp) -> u8 {
+    match op {
+        MathOp::Or => 1,
+        MathOp::Xor => 2,
+        MathOp::And => 3,
+        MathOp::Shl | MathOp::Shr => 4,
+        MathOp::Add | MathOp::Sub => 5,
+        MathOp::Mul => 6,
+    }
+}
+
+/// a directive, keyword, register or name
+fn word_token(word: &str) -> Option<Token> {
+    if let Some(directive) = word.strip_prefix('.') {
+        return Directive::from_str(directive).ok().map(Token::Directive);
+    }
+    if let Ok(keyword) = Keyword::from_str(word) {
+        return Some(Token::Keyword(keyword));
+    }
+    if let Some(register) = register(word) {
+        return Some(Token::Address(Value::Given(register)));
+    }
+    is_label(word).then(|| Token::Name(Rc::from(word)))
+}
+
+/// `r0` - `rF`, or `r10` - `r15`
+fn register(word: &str) -> Option<u16> {
+    let number = word.strip_prefix(['r', 'R'])?;
+    match number.len() {
+        1 => digits(number, 16),
+        2 => number
+            .parse()
+            .ok()
+            .filter(|register| (10..=15).contains(register)),
+        _ => None,
+    }
+}
+
+/// a number or a label; numbers are hex unless they start with `0n` (decimal) or `%` (binary),
+/// and can start with `0x` to make it clear they're hex
+fn value(word: &str) -> Option<Value> {
+    let number = match word.as_bytes() {
+        [b'0', b'x', ..] => digits(&word[2..], 16),
+        [b'0', b'n', ..] => digits(&word[2..], 10),
+        [b'%', ..] => digits(&word[1..], 2),
+        _ => digits(word, 16),
+    };
+    number
+        .map(Value::Given)
+        .or_else(|| is_label(word).then(|| Value::Label(Rc::from(word))))
+}
+
+/// parse `str` if it's nothing but digits in `radix`
+fn digits(str: &str, radix: u32) -> Option<u16> {
+    if str.is_empty() || !str.chars().all(|char| char.is_digit(radix)) {
+        return None;
+    }
+    u16::from_str_radix(str, radix).ok()
+}
+
+/// a name that can't be a label, because it would be read as a number or a register
+fn is_reserved(word: &str) -> bool {
+    !word.is_empty()
+        && (word.chars().all(|char| char.is_ascii_hexdigit()) || register(word).is_some())
+}
+
+fn is_label(word: &str) -> bool {
+    !is_reserved(word)
+        && word
+            .chars()
+            .next()
+            .is_some_and(|char| char.is_alphabetic() || matches!(char, '_' | '.'))
+        && word
+            .chars()
+            .all(|char| char.is_alphanumeric() || matches!(char, '_' | '.'))
+}
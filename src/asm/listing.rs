@@ -0,0 +1,95 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    rc::Rc,
+};
+
+use super::Labels;
+
+/// how many words of a statement a listing shows
+const SHOWN_WORDS: usize = 4;
+
+/// A statement in a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    address: u16,
+    words: Vec<u16>,
+    /// the line it's on, named by file if it's not in the one being assembled
+    location: String,
+    source: String,
+}
+
+/// Where each statement of a program went, what it assembled to, and its source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listing {
+    entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub(super) fn push(&mut self, address: u16, words: Vec<u16>, location: String, source: String) {
+        self.entries.push(Entry {
+            address,
+            words,
+            location,
+            source,
+        });
+    }
+
+    /// add words to the statement pushed last
+    pub(super) fn extend_last(&mut self, words: &[u16]) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.words.extend(words);
+        }
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            let mut words: Vec<String> = entry
+                .words
+                .iter()
+                .take(SHOWN_WORDS)
+                .map(|word| format!("{word:0>4X}"))
+                .collect();
+            if entry.words.len() > SHOWN_WORDS {
+                words.push(String::from("..."));
+            }
+            let line = format!(
+                "{:0>4X}  {:<24} {:>5}  {}",
+                entry.address,
+                words.join(" "),
+                entry.location,
+                entry.source
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// a line for each label with its address in hex and its name, in address order
+#[must_use]
+pub fn symbol_map(labels: &Labels) -> String {
+    let mut symbols: Vec<(&Rc<str>, &u16)> = labels.iter().collect();
+    symbols.sort_by_key(|&(_, address)| address);
+    let mut map = String::new();
+    for (label, address) in symbols {
+        let _ = writeln!(map, "{address:0>4X} {label}");
+    }
+    map
+}
+
+/// read a map written by `symbol_map`, or `None` if a line isn't an address and a name
+#[must_use]
+pub fn parse_symbol_map(map: &str) -> Option<Labels> {
+    let mut labels = BTreeMap::new();
+    for line in map.lines().filter(|line| !line.trim().is_empty()) {
+        let (address, label) = line.trim().split_once(' ')?;
+        labels.insert(
+            Rc::from(label.trim()),
+            u16::from_str_radix(address, 16).ok()?,
+        );
+    }
+    Some(labels)
+}
@@ -0,0 +1,250 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use super::{include::Sources, ASMError, Directive, ErrorKind, Span, Token, Value};
+
+/// how deep macros can expand inside each other before we assume they're recursive
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<Rc<str>>,
+    body: Vec<Token>,
+    spans: Vec<Span>,
+}
+
+/// Expands macros, keeping track of the definitions so far
+struct Expander<'a> {
+    sources: &'a Sources,
+    macros: BTreeMap<Rc<str>, Macro>,
+    /// how many expansions there have been, used to make local labels unique
+    expansions: usize,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+}
+
+/// define every `.macro` and replace each use with its body. Macros have to be defined before
+/// they're used.
+pub fn expand(
+    sources: &Sources,
+    tokens: &[Token],
+    spans: &[Span],
+) -> Result<(Vec<Token>, Vec<Span>), ASMError> {
+    let mut expander = Expander {
+        sources,
+        macros: BTreeMap::new(),
+        expansions: 0,
+        tokens: Vec::new(),
+        spans: Vec::new(),
+    };
+    expander.expand(tokens, spans, 0)?;
+    Ok((expander.tokens, expander.spans))
+}
+
+impl Expander<'_> {
+    fn expand(&mut self, tokens: &[Token], spans: &[Span], depth: usize) -> Result<(), ASMError> {
+        let mut idx = 0;
+        while idx < tokens.len() {
+            let at_statement_start =
+                idx == 0 || matches!(tokens[idx - 1], Token::SemiColon | Token::Label(_));
+            match &tokens[idx] {
+                Token::Directive(Directive::Macro) => {
+                    idx = self.define(tokens, spans, idx)?;
+                }
+                Token::Directive(Directive::Endm) => {
+                    return Err(ASMError::new(
+                        ErrorKind::Expected {
+                            expected: String::from("`.macro` before `.endm`"),
+                            found: Some(Rc::from(".endm")),
+                        },
+                        Some(spans[idx]),
+                    ));
+                }
+                Token::Name(name) if at_statement_start && self.macros.contains_key(name) => {
+                    let end = tokens[idx..]
+                        .iter()
+                        .position(|token| matches!(token, Token::SemiColon))
+                        .map_or(tokens.len(), |len| idx + len);
+                    let span = spans[idx].to(spans[end.min(tokens.len() - 1)]);
+                    if depth == MAX_DEPTH {
+                        return Err(ASMError::new(
+                            ErrorKind::MacroRecursion(name.clone()),
+                            Some(span),
+                        ));
+                    }
+                    let (body, body_spans) =
+                        self.instantiate(name, &tokens[idx + 1..end], &spans[idx + 1..end], span)?;
+                    self.expand(&body, &body_spans, depth + 1)?;
+                    idx = end + 1;
+                }
+                token => {
+                    self.tokens.push(token.clone());
+                    self.spans.push(spans[idx]);
+                    idx += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// read the `.macro` at `start`, returning the index after its `.endm;`
+    fn define(
+        &mut self,
+        tokens: &[Token],
+        spans: &[Span],
+        start: usize,
+    ) -> Result<usize, ASMError> {
+        let header_end = tokens[start..]
+            .iter()
+            .position(|token| matches!(token, Token::SemiColon))
+            .map_or(tokens.len(), |len| start + len);
+        let header = &tokens[start + 1..header_end];
+        let params: Option<Vec<Rc<str>>> = header
+            .iter()
+            .map(|token| match token {
+                Token::Name(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let (name, params) = match params.as_deref() {
+            Some([name, params @ ..]) if header_end < tokens.len() => {
+                (name.clone(), params.to_vec())
+            }
+            _ => {
+                let span = spans[start].to(spans[header_end.min(tokens.len() - 1)]);
+                return Err(ASMError::new(
+                    ErrorKind::Expected {
+                        expected: String::from("`.macro name [parameter ...];`"),
+                        found: Some(Rc::from(self.sources.text(span))),
+                    },
+                    Some(span),
+                ));
+            }
+        };
+        let mut idx = header_end + 1;
+        loop {
+            match &tokens.get(idx..idx + 2) {
+                Some([Token::Directive(Directive::Endm), Token::SemiColon]) => break,
+                Some([Token::Directive(Directive::Macro), _]) => {
+                    return Err(ASMError::new(
+                        ErrorKind::Expected {
+                            expected: String::from("`.endm;`"),
+                            found: Some(Rc::from(".macro")),
+                        },
+                        Some(spans[idx]),
+                    ))
+                }
+                Some(_) => idx += 1,
+                None => {
+                    return Err(ASMError::new(
+                        ErrorKind::Expected {
+                            expected: String::from("`.endm;`"),
+                            found: None,
+                        },
+                        Some(spans[start]),
+                    ))
+                }
+            }
+        }
+        let definition = Macro {
+            params,
+            body: tokens[header_end + 1..idx].to_vec(),
+            spans: spans[header_end + 1..idx].to_vec(),
+        };
+        if self.macros.insert(name.clone(), definition).is_some() {
+            return Err(ASMError::new(
+                ErrorKind::DuplicateMacro(name),
+                Some(spans[start]),
+            ));
+        }
+        Ok(idx + 2)
+    }
+
+    /// the body of macro `name` with `args` substituted and its labels made unique
+    fn instantiate(
+        &mut self,
+        name: &Rc<str>,
+        args: &[Token],
+        arg_spans: &[Span],
+        span: Span,
+    ) -> Result<(Vec<Token>, Vec<Span>), ASMError> {
+        let definition = &self.macros[name];
+        if args.len() != definition.params.len() {
+            return Err(ASMError::new(
+                ErrorKind::MacroArgs {
+                    name: name.clone(),
+                    expected: definition.params.len(),
+                    found: args.len(),
+                },
+                Some(span),
+            ));
+        }
+        self.expansions += 1;
+        let locals: Vec<&Rc<str>> = definition
+            .body
+            .iter()
+            .filter_map(|token| match token {
+                Token::Label(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+        // parameters used in an operand are replaced by what their argument stands for there
+        let values: Vec<Option<Value>> = args
+            .iter()
+            .map(|arg| match arg {
+                Token::Name(name) => Some(Value::Label(name.clone())),
+                Token::Literal(value) | Token::Address(value) => Some(value.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut unusable = None;
+        let mut operand = |value: &Value| {
+            value.replace_labels(&mut |label| {
+                if let Some(idx) = definition.params.iter().position(|param| param == label) {
+                    values[idx].clone().unwrap_or_else(|| {
+                        unusable = Some(idx);
+                        Value::Label(label.clone())
+                    })
+                } else if locals.contains(&label) {
+                    Value::Label(Rc::from(format!("{label}${}", self.expansions)))
+                } else {
+                    Value::Label(label.clone())
+                }
+            })
+        };
+        let mut body = Vec::new();
+        let mut spans = Vec::new();
+        for (token, &token_span) in definition.body.iter().zip(&definition.spans) {
+            let (token, token_span) = match token {
+                Token::Name(param) => definition
+                    .params
+                    .iter()
+                    .position(|name| name == param)
+                    .map_or_else(
+                        || (token.clone(), token_span),
+                        |idx| (args[idx].clone(), arg_spans[idx]),
+                    ),
+                Token::Label(label) => (
+                    Token::Label(Rc::from(format!("{label}${}", self.expansions))),
+                    token_span,
+                ),
+                Token::Literal(value) => (Token::Literal(operand(value)), token_span),
+                Token::Address(value) => (Token::Address(operand(value)), token_span),
+                token => (token.clone(), token_span),
+            };
+            body.push(token);
+            spans.push(token_span);
+        }
+        if let Some(idx) = unusable {
+            return Err(ASMError::new(
+                ErrorKind::Expected {
+                    expected: format!(
+                        "a name, literal or address for `{}`, which is used in an operand",
+                        definition.params[idx]
+                    ),
+                    found: Some(Rc::from(self.sources.text(arg_spans[idx]))),
+                },
+                Some(arg_spans[idx]),
+            ));
+        }
+        Ok((body, spans))
+    }
+}
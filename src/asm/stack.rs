@@ -0,0 +1,60 @@
+use std::rc::Rc;
+
+use super::{Instruction, Item, MathOp, Syntax, Value};
+
+/// the register holding the address of the top of the stack. It starts at `0`, so the stack grows
+/// down from the end of memory.
+pub const STACK_POINTER: u16 = 0xF;
+/// the register `ret` jumps through, so it isn't kept across calls
+pub const LINK_REGISTER: u16 = 0xE;
+
+const fn sp() -> Value {
+    Value::Given(STACK_POINTER)
+}
+
+/// make room on the stack and write `src` there
+#[must_use]
+pub fn push(src: Item) -> Vec<Syntax> {
+    vec![
+        Syntax::Instruction(Instruction::MathBinary(
+            MathOp::Sub,
+            Item::Literal(Value::Given(1)),
+            sp(),
+        )),
+        Syntax::Instruction(Instruction::Ptrwrite(src, sp())),
+    ]
+}
+
+/// read the top of the stack into `dst` and remove it
+#[must_use]
+pub fn pop(dst: Value) -> Vec<Syntax> {
+    vec![
+        Syntax::Instruction(Instruction::Ptrread(sp(), dst)),
+        Syntax::Instruction(Instruction::MathBinary(
+            MathOp::Add,
+            Item::Literal(Value::Given(1)),
+            sp(),
+        )),
+    ]
+}
+
+/// push the address after the call, which is labelled `ret`, then jump to `target`
+#[must_use]
+pub fn call(target: Item, ret: Rc<str>) -> Vec<Syntax> {
+    let mut code = push(Item::Literal(Value::Label(ret.clone())));
+    code.extend([
+        Syntax::Instruction(Instruction::Jmp(target)),
+        Syntax::Label(ret),
+    ]);
+    code
+}
+
+/// pop the address `call` pushed and jump to it
+#[must_use]
+pub fn ret() -> Vec<Syntax> {
+    let mut code = pop(Value::Given(LINK_REGISTER));
+    code.push(Syntax::Instruction(Instruction::Jmp(Item::Address(
+        Value::Given(LINK_REGISTER),
+    ))));
+    code
+}
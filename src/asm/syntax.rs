@@ -1,18 +1,45 @@
-use std::{collections::BTreeMap, fmt::Display, rc::Rc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    fmt::Display,
+    rc::Rc,
+};
 
-use crate::{asm::instruction::CmpOp, utils::print_and_ret};
+use crate::{
+    asm::instruction::CmpOp,
+    object::{self, Export, Object, Relocation, Target},
+    Image, Segment,
+};
 
 use super::{
-    instruction::{Instruction, Item, MathOp},
-    Keyword, Token,
+    include::Sources,
+    instruction::{Instruction, Item, MathOp, Value},
+    listing::Listing,
+    stack, ASMError, Directive, ErrorKind, Keyword, Span, Token,
 };
 
-#[derive(Debug)]
+/// the address of each label
+pub type Labels = BTreeMap<Rc<str>, u16>;
+
+#[derive(Debug, Clone)]
 pub enum Syntax {
     Label(Rc<str>),
     Instruction(Instruction),
     Literal(u16),
-    Reserve(u16),
+    /// a word of data worked out once the labels are known
+    Word(Value),
+    /// leave a number of words empty
+    Reserve(Value),
+    /// carry on the current section at an address
+    Org(Value),
+    /// name a constant
+    Equ(Rc<str>, Value),
+    /// pad with zeros to a multiple of a number of words
+    Align(Value),
+    /// switch to a section, giving its origin if it's new
+    Section(Rc<str>, Option<Value>),
+    /// let other objects use a label or constant
+    Global(Rc<str>),
 }
 
 impl Display for Syntax {
@@ -21,121 +48,731 @@ impl Display for Syntax {
             Self::Label(label) => write!(f, ":{label}"),
             Self::Instruction(instr) => write!(f, "{instr}"),
             Self::Literal(lit) => write!(f, "#{lit:0>4X};"),
-            Self::Reserve(lit) => write!(f, "RESERVE #{lit:0>4X};"),
+            Self::Word(value) => write!(f, "#{value};"),
+            Self::Reserve(len) => write!(f, "RESERVE #{len};"),
+            Self::Org(origin) => write!(f, ".org #{origin};"),
+            Self::Equ(name, value) => write!(f, ".equ {name} #{value};"),
+            Self::Align(align) => write!(f, ".align #{align};"),
+            Self::Section(name, None) => write!(f, ".section {name};"),
+            Self::Section(name, Some(origin)) => write!(f, ".section {name} #{origin};"),
+            Self::Global(name) => write!(f, ".global {name};"),
         }
     }
 }
 
-pub fn interpret(src: &[Token]) -> Result<Vec<u16>, Vec<Token>> {
+/// parse `tokens`, which were lexed from `sources` at `spans`, into statements and where they are
+pub fn parse(
+    sources: &Sources,
+    tokens: &[Token],
+    spans: &[Span],
+) -> Result<Vec<(Syntax, Option<Span>)>, ASMError> {
+    // each character of a string is a literal, followed by a null terminator
+    let mut expanded = Vec::new();
+    let mut expanded_spans = Vec::new();
+    for (token, &span) in tokens.iter().zip(spans) {
+        if let Token::Str(string) = token {
+            for char in string.chars().chain(['\0']) {
+                let char = u16::try_from(u32::from(char)).unwrap_or(0xFFFE);
+                expanded.extend([Token::Literal(Value::Given(char)), Token::SemiColon]);
+                expanded_spans.extend([span, span]);
+            }
+        } else {
+            expanded.push(token.clone());
+            expanded_spans.push(span);
+        }
+    }
+    let (tokens, spans) = (&expanded[..], &expanded_spans[..]);
+
     // get the syntax
     let mut statements = Vec::new();
-    interpret_tokens(src, &mut statements)?;
-    println!("{statements:?}");
-    Ok(interpret_syntax(statements))
+    let mut calls = 0;
+    let mut rest = tokens;
+    while !rest.is_empty() {
+        let start = tokens.len() - rest.len();
+        if let Some((pseudo, next)) = interpret_pseudo(rest, &mut calls) {
+            let end = tokens.len() - next.len();
+            let span = spans[start].to(spans[end - 1]);
+            statements.extend(pseudo.into_iter().map(|statement| (statement, Some(span))));
+            rest = next;
+            continue;
+        }
+        let Some((statement, next)) = interpret_statement(rest) else {
+            return Err(diagnose(sources, rest, &spans[start..]));
+        };
+        let end = tokens.len() - next.len();
+        statements.push((statement, Some(spans[start].to(spans[end - 1]))));
+        rest = next;
+    }
+    Ok(statements)
 }
 
+/// # Errors
+/// if a label is undefined or defined more than once, or the segments don't fit in memory
 #[allow(clippy::module_name_repetitions)]
-pub fn interpret_syntax(src: Vec<Syntax>) -> Vec<u16> {
-    // first pass to get location of all the labels
-    let mut byte_location: u16 = 0x8000;
+pub fn interpret_syntax(src: Vec<Syntax>) -> Result<Vec<u16>, ASMError> {
+    let src: Vec<_> = src.into_iter().map(|statement| (statement, None)).collect();
+    let (image, _) = assemble(&src)?;
+    flatten(&image)
+}
+
+/// like `interpret_syntax`, but also return the address of each label and a listing of the program
+/// # Errors
+/// if a label is undefined or defined more than once, or the segments don't fit in memory
+pub fn interpret_syntax_listed(src: Vec<Syntax>) -> Result<(Vec<u16>, Labels, Listing), ASMError> {
+    let src: Vec<_> = src.into_iter().map(|statement| (statement, None)).collect();
+    let (image, labels, listing) = assemble_listed(&src, None)?;
+    Ok((flatten(&image)?, labels, listing))
+}
+
+/// like `interpret_syntax`, but leave placing the program to the linker
+/// # Errors
+/// if a label is defined more than once, or an expression can't be linked
+pub fn interpret_syntax_object(src: Vec<Syntax>) -> Result<Object, ASMError> {
+    let src: Vec<_> = src.into_iter().map(|statement| (statement, None)).collect();
+    assemble_object(&src)
+}
+
+/// the words of `image` as a block loaded at `Image::DEFAULT_ORIGIN`
+pub fn flatten(image: &Image) -> Result<Vec<u16>, ASMError> {
+    image.flatten(Image::DEFAULT_ORIGIN).ok_or_else(|| {
+        let origin = image.segments.iter().map(|segment| segment.origin).min();
+        ASMError::new(ErrorKind::BeforeOrigin(origin.unwrap_or_default()), None)
+    })
+}
+
+/// Where each section is up to while laying out a program
+struct Layout {
+    segments: Vec<Segment>,
+    /// the section each segment belongs to
+    owners: Vec<usize>,
+    /// each section's name, the segment it's adding to, and the most it's been aligned to
+    sections: Vec<(Rc<str>, usize, u16)>,
+    current: usize,
+    /// whether the sections start at `0` for the linker to place, rather than having origins
+    relocatable: bool,
+}
+
+impl Layout {
+    fn new(relocatable: bool) -> Self {
+        Self {
+            segments: vec![Segment {
+                origin: if relocatable {
+                    0
+                } else {
+                    Image::DEFAULT_ORIGIN
+                },
+                words: Vec::new(),
+            }],
+            owners: vec![0],
+            sections: vec![(Rc::from("code"), 0, 1)],
+            current: 0,
+            relocatable,
+        }
+    }
+
+    fn segment(&mut self) -> &mut Segment {
+        &mut self.segments[self.sections[self.current].1]
+    }
+
+    /// the address the next word goes at, or where it is in the section if it's relocatable
+    fn location(&self) -> Result<u16, ErrorKind> {
+        u16::try_from(self.segments[self.sections[self.current].1].end())
+            .map_err(|_| ErrorKind::OutOfMemory)
+    }
+
+    /// `value` as an address in the current section
+    fn here(&self, value: u16) -> Reloc {
+        Reloc {
+            value,
+            base: self.relocatable.then_some(Base::Section(self.current)),
+        }
+    }
+
+    fn org(&mut self, origin: u16) -> Result<(), ErrorKind> {
+        if self.relocatable {
+            return Err(ErrorKind::ObjectOrigin);
+        }
+        if origin < 0x10 {
+            return Err(ErrorKind::LowOrigin(origin));
+        }
+        self.segments.push(Segment {
+            origin,
+            words: Vec::new(),
+        });
+        self.owners.push(self.current);
+        self.sections[self.current].1 = self.segments.len() - 1;
+        Ok(())
+    }
+
+    fn section(&mut self, name: &Rc<str>, origin: Option<u16>) -> Result<(), ErrorKind> {
+        if let Some(idx) = self
+            .sections
+            .iter()
+            .position(|(section, ..)| section == name)
+        {
+            self.current = idx;
+        } else if self.relocatable && origin.is_none() {
+            self.segments.push(Segment {
+                origin: 0,
+                words: Vec::new(),
+            });
+            self.owners.push(self.sections.len());
+            self.sections
+                .push((name.clone(), self.segments.len() - 1, 1));
+            self.current = self.sections.len() - 1;
+        } else if origin.is_some() {
+            self.sections.push((name.clone(), 0, 1));
+            self.current = self.sections.len() - 1;
+        } else {
+            return Err(ErrorKind::NoOrigin(name.clone()));
+        }
+        origin.map_or(Ok(()), |origin| self.org(origin))
+    }
+
+    fn align(&mut self, align: u16) -> Result<(), ErrorKind> {
+        let location = self.location()?;
+        let padding = location
+            .checked_next_multiple_of(align.max(1))
+            .ok_or(ErrorKind::OutOfMemory)?
+            - location;
+        let most = &mut self.sections[self.current].2;
+        *most = (*most).max(align);
+        self.push(&vec![0; padding.into()])
+    }
+
+    fn push(&mut self, words: &[u16]) -> Result<(), ErrorKind> {
+        let segment = self.segment();
+        segment.words.extend(words);
+        if segment.end() > 0x10000 {
+            return Err(ErrorKind::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    /// the non-empty segments in address order, starting at the first bit of code
+    fn finish(self) -> Result<Image, ErrorKind> {
+        let entry = self
+            .segments
+            .iter()
+            .zip(&self.owners)
+            .find(|(segment, &owner)| owner == 0 && !segment.words.is_empty())
+            .map_or(Image::DEFAULT_ORIGIN, |(segment, _)| segment.origin);
+        let mut segments: Vec<Segment> = self
+            .segments
+            .into_iter()
+            .filter(|segment| !segment.words.is_empty())
+            .collect();
+        segments.sort_by_key(|segment| segment.origin);
+        for pair in segments.windows(2) {
+            if pair[0].end() > usize::from(pair[1].origin) {
+                return Err(ErrorKind::Overlap(pair[1].origin));
+            }
+        }
+        Ok(Image { entry, segments })
+    }
+
+    /// the sections of a relocatable layout, which each have one segment
+    fn finish_object(
+        mut self,
+        relocations: Vec<(usize, u16, Base)>,
+        exports: Vec<Export>,
+    ) -> Object {
+        let mut sections: Vec<object::Section> = self
+            .sections
+            .iter()
+            .map(|(name, segment, align)| object::Section {
+                name: name.clone(),
+                align: *align,
+                words: std::mem::take(&mut self.segments[*segment].words),
+                relocations: Vec::new(),
+            })
+            .collect();
+        for (section, offset, base) in relocations {
+            let target = match base {
+                Base::Section(idx) => Target::Section(idx),
+                Base::Import(symbol) => Target::Symbol(symbol),
+            };
+            sections[section]
+                .relocations
+                .push(Relocation { offset, target });
+        }
+        Object { sections, exports }
+    }
+}
+
+/// what a value is relative to, if it depends on where the linker puts things
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Base {
+    /// the start of a section of the object
+    Section(usize),
+    /// a symbol from another object
+    Import(Rc<str>),
+}
+
+/// a value that's `base` plus `value` once the program is linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reloc {
+    value: u16,
+    base: Option<Base>,
+}
+
+impl Reloc {
+    const fn absolute(value: u16) -> Self {
+        Self { value, base: None }
+    }
+}
+
+/// the value of each label and constant
+type Symbols = BTreeMap<Rc<str>, Reloc>;
+
+/// work out `value`. Undefined labels are imports if `imports`, and only adding to or subtracting
+/// from something relocatable, or subtracting two things relative to the same base, can be linked.
+fn relocate(
+    value: &Value,
+    symbols: &Symbols,
+    here: &Reloc,
+    imports: bool,
+) -> Result<Reloc, ErrorKind> {
+    match value {
+        Value::Given(num) => Ok(Reloc::absolute(*num)),
+        Value::Label(label) => match symbols.get(label) {
+            Some(symbol) => Ok(symbol.clone()),
+            None if imports => Ok(Reloc {
+                value: 0,
+                base: Some(Base::Import(label.clone())),
+            }),
+            None => Err(ErrorKind::UndefinedLabel(label.clone())),
+        },
+        Value::Here => Ok(here.clone()),
+        Value::Expr(op, lhs, rhs) => {
+            let lhs = relocate(lhs, symbols, here, imports)?;
+            let rhs = relocate(rhs, symbols, here, imports)?;
+            let base = match (op, lhs.base, rhs.base) {
+                (_, None, None) => None,
+                (MathOp::Add | MathOp::Sub, base @ Some(_), None)
+                | (MathOp::Add, None, base @ Some(_)) => base,
+                (MathOp::Sub, Some(lhs), Some(rhs)) if lhs == rhs => None,
+                _ => return Err(ErrorKind::Relocatable(Rc::from(value.to_string()))),
+            };
+            Ok(Reloc {
+                value: op.apply(lhs.value, rhs.value),
+                base,
+            })
+        }
+    }
+}
+
+/// how many times the program is laid out without an operand growing before giving up on the
+/// labels settling down
+const MAX_PASSES: usize = 16;
+
+/// resolve labels and produce machine code for statements, which may know where they came from
+pub fn assemble(src: &[(Syntax, Option<Span>)]) -> Result<(Image, Labels), ASMError> {
+    finish(settle(src, false)?)
+}
+
+/// the program and the address of each label
+fn finish(laid_out: LaidOut) -> Result<(Image, Labels), ASMError> {
+    let image = laid_out
+        .layout
+        .finish()
+        .map_err(|kind| ASMError::new(kind, None))?;
+    let labels = laid_out
+        .labels
+        .into_iter()
+        .map(|(label, address)| (label, address.value))
+        .collect();
+    Ok((image, labels))
+}
+
+/// like `assemble`, but also list where each statement went. Statements are shown as they are in
+/// `sources`, once for each line, or as they'd be written if there are no sources.
+pub fn assemble_listed(
+    src: &[(Syntax, Option<Span>)],
+    sources: Option<&Sources>,
+) -> Result<(Image, Labels, Listing), ASMError> {
+    let laid_out = settle(src, false)?;
+    let mut listing = Listing::default();
+    let mut last_line = None;
+    let mut last_span = None;
+    for ((statement, span), (address, words)) in src.iter().zip(&laid_out.placed) {
+        // a string is a word statement for each character, all from the same span
+        if span.is_some() && *span == last_span {
+            listing.extend_last(words);
+            continue;
+        }
+        last_span = *span;
+        let (location, source) = match (span, sources) {
+            (Some(span), Some(sources)) => {
+                let line = (span.file, span.line);
+                let source = if last_line == Some(line) {
+                    String::new()
+                } else {
+                    sources.line(*span).trim().to_string()
+                };
+                last_line = Some(line);
+                (sources.location(*span), source)
+            }
+            _ => (String::new(), statement.to_string()),
+        };
+        listing.push(*address, words.clone(), location, source);
+    }
+    let (image, labels) = finish(laid_out)?;
+    Ok((image, labels, listing))
+}
+
+/// like `assemble`, but leave placing the sections to the linker
+pub fn assemble_object(src: &[(Syntax, Option<Span>)]) -> Result<Object, ASMError> {
+    let laid_out = settle(src, true)?;
+    let mut exports = Vec::new();
+    for (name, span) in laid_out.globals {
+        let (section, value) = match laid_out.symbols.get(&name) {
+            Some(Reloc {
+                value,
+                base: Some(Base::Section(section)),
+            }) => (Some(*section), *value),
+            Some(Reloc { value, base: None }) => (None, *value),
+            _ => return Err(ASMError::new(ErrorKind::UndefinedLabel(name), span)),
+        };
+        exports.push(Export {
+            name,
+            section,
+            value,
+        });
+    }
+    Ok(laid_out.layout.finish_object(laid_out.relocations, exports))
+}
+
+/// A program placed in memory
+struct LaidOut {
+    layout: Layout,
+    labels: Symbols,
+    /// the labels and constants
+    symbols: Symbols,
+    /// the section, offset and base of each word to be relocated
+    relocations: Vec<(usize, u16, Base)>,
+    /// the names given to `.global`, and where
+    globals: Vec<(Rc<str>, Option<Span>)>,
+    /// where each statement went and the words it became
+    placed: Vec<(u16, Vec<u16>)>,
+    /// whether each operand of each statement was too big for a nibble
+    widths: Vec<Vec<bool>>,
+}
+
+/// the statement and operand numbers of operands that get a word of their own, however small
+type Pinned = BTreeSet<(usize, usize)>;
+
+/// lay the program out until the labels stop moving. Operands using labels take a word of their
+/// own until we know where the labels are, then shrink to a nibble if they fit, moving the labels
+/// after them. Shrinking can make another operand grow, so an operand that grows keeps its word
+/// from then on, which means the sizes stop changing.
+fn settle(src: &[(Syntax, Option<Span>)], relocatable: bool) -> Result<LaidOut, ASMError> {
+    let mut symbols = symbols(src)?;
+    let mut pinned = Pinned::new();
+    let mut widths: Vec<Vec<bool>> = Vec::new();
+    let mut passes = 0;
+    while passes < MAX_PASSES {
+        let laid_out = lay_out(src, &symbols, &pinned, relocatable)?;
+        if laid_out.symbols == symbols {
+            return Ok(laid_out);
+        }
+        let mut grew = false;
+        for (statement, (before, after)) in widths.iter().zip(&laid_out.widths).enumerate() {
+            for (operand, (before, after)) in before.iter().zip(after).enumerate() {
+                if after > before {
+                    grew |= pinned.insert((statement, operand));
+                }
+            }
+        }
+        passes = if grew { 0 } else { passes + 1 };
+        symbols = laid_out.symbols;
+        widths = laid_out.widths;
+    }
+    Err(ASMError::new(ErrorKind::Unsettled, None))
+}
+
+/// a placeholder for each constant and label
+fn symbols(src: &[(Syntax, Option<Span>)]) -> Result<Symbols, ASMError> {
+    let mut symbols = BTreeMap::new();
+    for (statement, span) in src {
+        let (Syntax::Label(name) | Syntax::Equ(name, _)) = statement else {
+            continue;
+        };
+        if symbols
+            .insert(name.clone(), Reloc::absolute(u16::MAX))
+            .is_some()
+        {
+            return Err(ASMError::new(
+                ErrorKind::DuplicateLabel(name.clone()),
+                *span,
+            ));
+        }
+    }
+    Ok(symbols)
+}
+
+/// place the statements in memory using the `symbols` from the last time
+fn lay_out(
+    src: &[(Syntax, Option<Span>)],
+    symbols: &Symbols,
+    pinned: &Pinned,
+    relocatable: bool,
+) -> Result<LaidOut, ASMError> {
+    let mut layout = Layout::new(relocatable);
     let mut labels = BTreeMap::new();
-    for statement in &src {
+    let mut relocations = Vec::new();
+    let mut globals = Vec::new();
+    let mut placed = Vec::new();
+    let mut widths = Vec::new();
+    // constants are worked out in order, so later ones can use them straight away
+    let mut symbols = symbols.clone();
+    let mut constants = BTreeSet::new();
+    for (idx, (statement, span)) in src.iter().enumerate() {
+        let error = |kind| ASMError::new(kind, *span);
+        // running out of memory is only a problem if something goes here
+        let location = layout.location();
+        let here = layout.here(location.clone().unwrap_or(u16::MAX));
+        let segment = layout.sections[layout.current].1;
+        let start = layout.segments[segment].words.len();
+        let evaluate = |value: &Value, symbols: &Symbols| {
+            relocate(value, symbols, &here, relocatable).map_err(error)
+        };
+        // things that have to be known before linking
+        let absolute = |value: &Value, symbols: &Symbols| match evaluate(value, symbols)? {
+            Reloc { value, base: None } => Ok(value),
+            _ => Err(error(ErrorKind::Relocatable(Rc::from(value.to_string())))),
+        };
         match statement {
             Syntax::Label(label) => {
-                labels.insert(label.clone(), byte_location);
+                location.map_err(error)?;
+                labels.insert(label.clone(), here.clone());
             }
-            Syntax::Instruction(instruction) => {
-                byte_location += instruction.to_machine_code().len() as u16;
+            Syntax::Equ(name, value) => {
+                let value = evaluate(value, &symbols)?;
+                symbols.insert(name.clone(), value);
+                constants.insert(name.clone());
             }
-            Syntax::Literal(_) => byte_location += 1,
-            Syntax::Reserve(len) => byte_location += len,
+            Syntax::Global(name) => globals.push((name.clone(), *span)),
+            Syntax::Literal(lit) => layout.push(&[*lit]).map_err(error)?,
+            Syntax::Word(value) => {
+                let Reloc { value, base } = evaluate(value, &symbols)?;
+                if let Some(base) = base {
+                    relocations.push((layout.current, here.value, base));
+                }
+                layout.push(&[value]).map_err(error)?;
+            }
+            Syntax::Reserve(len) => {
+                // where everything after it goes depends on the length, so it can only use what's
+                // been defined already
+                let known: Symbols = symbols
+                    .iter()
+                    .filter(|(name, _)| constants.contains(*name))
+                    .chain(&labels)
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                let len = match relocate(len, &known, &here, false) {
+                    Ok(Reloc { value, base: None }) => value,
+                    Ok(_) => return Err(error(ErrorKind::Relocatable(Rc::from(len.to_string())))),
+                    Err(ErrorKind::UndefinedLabel(label)) if symbols.contains_key(&label) => {
+                        return Err(error(ErrorKind::DefinedAfter(label)))
+                    }
+                    Err(kind) => return Err(error(kind)),
+                };
+                layout.push(&vec![0; len.into()]).map_err(error)?;
+            }
+            Syntax::Org(origin) => layout.org(absolute(origin, &symbols)?).map_err(error)?,
+            Syntax::Align(align) => layout.align(absolute(align, &symbols)?).map_err(error)?,
+            Syntax::Section(name, origin) => {
+                let origin = origin
+                    .as_ref()
+                    .map(|origin| absolute(origin, &symbols))
+                    .transpose()?;
+                layout.section(name, origin).map_err(error)?;
+            }
+            Syntax::Instruction(instr) => {
+                let pinned = |operand| pinned.contains(&(idx, operand));
+                let encoded = encode(instr, pinned, |value| evaluate(value, &symbols))?;
+                for (offset, base) in encoded.relocated {
+                    relocations.push((layout.current, here.value + offset, base));
+                }
+                layout.push(&encoded.words).map_err(error)?;
+                widths.push(encoded.widths);
+            }
+        }
+        if !matches!(statement, Syntax::Instruction(_)) {
+            widths.push(Vec::new());
         }
+        let words = if layout.sections[layout.current].1 == segment {
+            layout.segments[segment].words[start..].to_vec()
+        } else {
+            Vec::new()
+        };
+        placed.push((here.value, words));
     }
-    src.into_iter()
-        .flat_map(|syn| match syn {
-            Syntax::Label(_) => Vec::new(),
-            Syntax::Literal(lit) => vec![lit],
-            Syntax::Reserve(lit) => vec![0; lit.into()],
-            Syntax::Instruction(instr) => instr.with_labels(&labels).to_machine_code(),
+    symbols.extend(labels.clone());
+    Ok(LaidOut {
+        layout,
+        labels,
+        symbols,
+        relocations,
+        globals,
+        placed,
+        widths,
+    })
+}
+
+/// where words in an instruction are, and what to relocate them against
+type Relocated = Vec<(u16, Base)>;
+
+/// An instruction's machine code
+struct Encoded {
+    words: Vec<u16>,
+    relocated: Relocated,
+    /// whether each operand was too big for a nibble, or relocatable
+    widths: Vec<bool>,
+}
+
+/// the machine code for `instr`, with its operands worked out by `evaluate`. Operands that are
+/// `pinned` get a word of their own even if they'd fit in a nibble.
+#[allow(clippy::cast_possible_truncation)]
+fn encode(
+    instr: &Instruction,
+    pinned: impl Fn(usize) -> bool,
+    evaluate: impl Fn(&Value) -> Result<Reloc, ASMError>,
+) -> Result<Encoded, ASMError> {
+    let mut operands = Vec::new();
+    instr.clone().try_map(|value| {
+        operands.push(evaluate(&value)?);
+        Ok::<_, ASMError>(value)
+    })?;
+    let widths = operands
+        .iter()
+        .map(|reloc| reloc.base.is_some() || reloc.value > 0xF)
+        .collect();
+    // relocatable and pinned operands are laid out as placeholders that don't fit in a nibble and
+    // don't equal any other operand, so they get a word of their own
+    let fixed: Vec<Option<u16>> = operands
+        .iter()
+        .enumerate()
+        .map(|(idx, reloc)| {
+            (reloc.base.is_none() && (reloc.value > 0xF || !pinned(idx))).then_some(reloc.value)
         })
-        .collect()
+        .collect();
+    let mut placeholders = (0x10..=u16::MAX).filter(|value| !fixed.contains(&Some(*value)));
+    let mut values = fixed
+        .iter()
+        .map(|value| value.or_else(|| placeholders.next()).unwrap_or_default());
+    let Ok(laid_out) = instr
+        .clone()
+        .try_map(|_| Ok::<_, Infallible>(Value::Given(values.next().unwrap_or_default())));
+    let (first, rest) = laid_out.layout();
+    let mut relocated = Vec::new();
+    let words = std::iter::once(first)
+        .chain(rest.into_iter().enumerate().map(|(offset, idx)| {
+            let Reloc { value, base } = &operands[idx];
+            if let Some(base) = base {
+                relocated.push((offset as u16 + 1, base.clone()));
+            }
+            *value
+        }))
+        .collect();
+    Ok(Encoded {
+        words,
+        relocated,
+        widths,
+    })
+}
+
+/// parse the pseudo-instruction at the start of `src` into the instructions it stands for,
+/// returning them and the tokens after it. `calls` counts the `CALL`s so far, to label where each
+/// one returns to.
+fn interpret_pseudo<'a>(src: &'a [Token], calls: &mut usize) -> Option<(Vec<Syntax>, &'a [Token])> {
+    match src {
+        [Token::Keyword(Keyword::Push), item @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] => {
+            Some((stack::push(Item::try_from(item.clone()).unwrap()), rest))
+        }
+        [Token::Keyword(Keyword::Pop), Token::Address(dst), Token::SemiColon, rest @ ..] => {
+            Some((stack::pop(dst.clone()), rest))
+        }
+        [Token::Keyword(Keyword::Call), target @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] =>
+        {
+            *calls += 1;
+            let label = Rc::from(format!("call${calls}"));
+            Some((
+                stack::call(Item::try_from(target.clone()).unwrap(), label),
+                rest,
+            ))
+        }
+        [Token::Keyword(Keyword::Ret), Token::SemiColon, rest @ ..] => Some((stack::ret(), rest)),
+        _ => None,
+    }
 }
 
 #[allow(clippy::too_many_lines)]
-fn interpret_tokens(src: &[Token], output: &mut Vec<Syntax>) -> Result<(), Vec<Token>> {
+/// parse the statement at the start of `src`, returning it and the tokens after it
+fn interpret_statement(src: &[Token]) -> Option<(Syntax, &[Token])> {
     match src {
-        [] => Ok(()),
         [Token::Keyword(Keyword::Yield), Token::SemiColon, rest @ ..] => {
-            output.push(Syntax::Instruction(Instruction::Yield));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((Syntax::Instruction(Instruction::Yield), rest))
         }
-        [Token::Label(label), rest @ ..] => {
-            output.push(Syntax::Label(label.clone()));
-            interpret_tokens(rest, output)?;
-            Ok(())
+        [Token::Keyword(Keyword::Halt), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Instruction(Instruction::Halt), rest))
         }
-        [Token::Keyword(Keyword::Mov), src @ (Token::Literal(_) | Token::Address(_)), Token::Address(addr), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(print_and_ret(Syntax::Instruction(Instruction::Mov(
-                Item::try_from(src.clone()).unwrap(),
-                addr.clone(),
-            ))));
-            interpret_tokens(rest, output)?;
-            Ok(())
-        }
-        [Token::Keyword(Keyword::Swp), Token::Address(src), Token::Address(dst), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(print_and_ret(Syntax::Instruction(Instruction::Swp(
-                src.clone(),
-                dst.clone(),
-            ))));
-            interpret_tokens(rest, output)?;
-            Ok(())
-        }
-        [Token::Keyword(Keyword::Jmp), jmp @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(print_and_ret(Syntax::Instruction(Instruction::Jmp(
-                Item::try_from(jmp.clone()).unwrap(),
-            ))));
-            interpret_tokens(rest, output)?;
-            Ok(())
+        [Token::Keyword(Keyword::Reti), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Instruction(Instruction::Reti), rest))
         }
-        [Token::Keyword(cmp @ (Keyword::Jez | Keyword::Jnz)), Token::Address(cnd), jump @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(Syntax::Instruction(Instruction::Jcmpz(
-                *cmp == Keyword::Jez,
-                cnd.clone(),
-                Item::try_from(jump.clone()).unwrap(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
+        [Token::Label(label), rest @ ..] => Some((Syntax::Label(label.clone()), rest)),
+        [Token::Keyword(Keyword::Mov), src @ (Token::Literal(_) | Token::Address(_)), Token::Address(addr), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Mov(
+                    Item::try_from(src.clone()).unwrap(),
+                    addr.clone(),
+                )),
+                rest,
+            ))
+        }
+        [Token::Keyword(Keyword::Swp), Token::Address(src), Token::Address(dst), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Swp(src.clone(), dst.clone())),
+                rest,
+            ))
+        }
+        [Token::Keyword(Keyword::Jmp), jmp @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Jmp(Item::try_from(jmp.clone()).unwrap())),
+                rest,
+            ))
+        }
+        [Token::Keyword(cmp @ (Keyword::Jez | Keyword::Jnz)), Token::Address(cnd), jump @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Jcmpz(
+                    *cmp == Keyword::Jez,
+                    cnd.clone(),
+                    Item::try_from(jump.clone()).unwrap(),
+                )),
+                rest,
+            ))
         }
         [Token::Keyword(Keyword::Ptrread), Token::Address(src), Token::SemiColon, rest @ ..] => {
-            output.push(Syntax::Instruction(Instruction::Ptrread(
-                src.clone(),
-                src.clone(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
-        }
-        [Token::Keyword(Keyword::Ptrread), Token::Address(src), Token::Address(dst), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(Syntax::Instruction(Instruction::Ptrread(
-                src.clone(),
-                dst.clone(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
-        }
-        [Token::Keyword(Keyword::Ptrwrite), src @ (Token::Address(_) | Token::Literal(_)), Token::Address(dst), Token::SemiColon, rest @ ..] =>
-        {
-            output.push(Syntax::Instruction(Instruction::Ptrwrite(
-                Item::try_from(src.clone()).unwrap(),
-                dst.clone(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((
+                Syntax::Instruction(Instruction::Ptrread(src.clone(), src.clone())),
+                rest,
+            ))
+        }
+        [Token::Keyword(Keyword::Ptrread), Token::Address(src), Token::Address(dst), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Ptrread(src.clone(), dst.clone())),
+                rest,
+            ))
+        }
+        [Token::Keyword(Keyword::Ptrwrite), src @ (Token::Address(_) | Token::Literal(_)), Token::Address(dst), Token::SemiColon, rest @ ..] => {
+            Some((
+                Syntax::Instruction(Instruction::Ptrwrite(
+                    Item::try_from(src.clone()).unwrap(),
+                    dst.clone(),
+                )),
+                rest,
+            ))
         }
         [Token::Keyword(
             math_op @ (Keyword::Add
@@ -149,13 +786,37 @@ fn interpret_tokens(src: &[Token], output: &mut Vec<Syntax>) -> Result<(), Vec<T
         ), src @ (Token::Address(_) | Token::Literal(_)), Token::Address(dst), Token::SemiColon, rest @ ..] =>
         {
             let math_op = MathOp::try_from(*math_op).unwrap();
-            output.push(Syntax::Instruction(Instruction::MathBinary(
-                math_op,
-                Item::try_from(src.clone()).unwrap(),
-                dst.clone(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((
+                Syntax::Instruction(Instruction::MathBinary(
+                    math_op,
+                    Item::try_from(src.clone()).unwrap(),
+                    dst.clone(),
+                )),
+                rest,
+            ))
+        }
+        [Token::Keyword(
+            math_op @ (Keyword::Add
+            | Keyword::Sub
+            | Keyword::Mul
+            | Keyword::And
+            | Keyword::Or
+            | Keyword::Xor
+            | Keyword::Shl
+            | Keyword::Shr),
+        ), src_a @ (Token::Address(_) | Token::Literal(_)), src @ (Token::Address(_) | Token::Literal(_)), Token::Address(dst), Token::SemiColon, rest @ ..]
+            if !matches!((src_a, src), (Token::Literal(_), Token::Literal(_))) =>
+        {
+            let math_op = MathOp::try_from(*math_op).unwrap();
+            Some((
+                Syntax::Instruction(Instruction::MathTernary(
+                    math_op,
+                    Item::try_from(src_a.clone()).unwrap(),
+                    Item::try_from(src.clone()).unwrap(),
+                    dst.clone(),
+                )),
+                rest,
+            ))
         }
         [Token::Keyword(
             cmp_op @ (Keyword::Jeq
@@ -167,14 +828,15 @@ fn interpret_tokens(src: &[Token], output: &mut Vec<Syntax>) -> Result<(), Vec<T
         ), Token::Address(src), src_a @ (Token::Address(_) | Token::Literal(_)), jmp @ (Token::Address(_) | Token::Literal(_)), Token::SemiColon, rest @ ..] =>
         {
             let cmp_op = CmpOp::try_from(*cmp_op).unwrap();
-            output.push(Syntax::Instruction(Instruction::JmpCmp(
-                cmp_op,
-                src.clone(),
-                Item::try_from(src_a.clone()).unwrap(),
-                Item::try_from(jmp.clone()).unwrap(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((
+                Syntax::Instruction(Instruction::JmpCmp(
+                    cmp_op,
+                    src.clone(),
+                    Item::try_from(src_a.clone()).unwrap(),
+                    Item::try_from(jmp.clone()).unwrap(),
+                )),
+                rest,
+            ))
         }
         [Token::Keyword(
             cmp_op @ (Keyword::Ceq
@@ -186,25 +848,168 @@ fn interpret_tokens(src: &[Token], output: &mut Vec<Syntax>) -> Result<(), Vec<T
         ), Token::Address(src), src_a @ (Token::Address(_) | Token::Literal(_)), Token::Address(dst), Token::SemiColon, rest @ ..] =>
         {
             let cmp_op = CmpOp::try_from(*cmp_op).unwrap();
-            output.push(Syntax::Instruction(Instruction::Cmp(
-                cmp_op,
-                src.clone(),
-                Item::try_from(src_a.clone()).unwrap(),
-                dst.clone(),
-            )));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((
+                Syntax::Instruction(Instruction::Cmp(
+                    cmp_op,
+                    src.clone(),
+                    Item::try_from(src_a.clone()).unwrap(),
+                    dst.clone(),
+                )),
+                rest,
+            ))
         }
         [Token::Keyword(Keyword::Reserve), Token::Literal(lit), Token::SemiColon, rest @ ..] => {
-            output.push(Syntax::Reserve(lit.to_number()));
-            interpret_tokens(rest, output)?;
-            Ok(())
+            Some((Syntax::Reserve(lit.clone()), rest))
+        }
+        [Token::Literal(Value::Given(lit)), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Literal(*lit), rest))
+        }
+        [Token::Literal(value), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Word(value.clone()), rest))
+        }
+        [Token::Directive(Directive::Org), Token::Literal(origin), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Org(origin.clone()), rest))
+        }
+        [Token::Directive(Directive::Equ), Token::Name(name), Token::Literal(value), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Equ(name.clone(), value.clone()), rest))
+        }
+        [Token::Directive(Directive::Align), Token::Literal(align), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Align(align.clone()), rest))
+        }
+        [Token::Directive(Directive::Global), Token::Name(name), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Global(name.clone()), rest))
+        }
+        [Token::Directive(Directive::Section), Token::Name(name), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Section(name.clone(), None), rest))
+        }
+        [Token::Directive(Directive::Section), Token::Name(name), Token::Literal(origin), Token::SemiColon, rest @ ..] => {
+            Some((Syntax::Section(name.clone(), Some(origin.clone())), rest))
+        }
+        _ => None,
+    }
+}
+
+const ADDRESS: u8 = 1;
+const LITERAL: u8 = 2;
+const ITEM: u8 = ADDRESS | LITERAL;
+const END: u8 = 4;
+
+/// the operands each keyword accepts
+const fn signatures(keyword: Keyword) -> &'static [&'static [u8]] {
+    match keyword {
+        Keyword::Yield | Keyword::Halt | Keyword::Reti | Keyword::Ret => &[&[]],
+        Keyword::Mov | Keyword::Ptrwrite => &[&[ITEM, ADDRESS]],
+        Keyword::Swp => &[&[ADDRESS, ADDRESS]],
+        Keyword::Pop => &[&[ADDRESS]],
+        Keyword::Jmp | Keyword::Push | Keyword::Call => &[&[ITEM]],
+        Keyword::Jez | Keyword::Jnz => &[&[ADDRESS, ITEM]],
+        Keyword::Ptrread => &[&[ADDRESS], &[ADDRESS, ADDRESS]],
+        Keyword::Add
+        | Keyword::Sub
+        | Keyword::Mul
+        | Keyword::And
+        | Keyword::Or
+        | Keyword::Xor
+        | Keyword::Shl
+        | Keyword::Shr => &[&[ITEM, ADDRESS], &[ITEM, ITEM, ADDRESS]],
+        Keyword::Jeq | Keyword::Jne | Keyword::Jlt | Keyword::Jle | Keyword::Jgt | Keyword::Jge => {
+            &[&[ADDRESS, ITEM, ITEM]]
+        }
+        Keyword::Ceq | Keyword::Cne | Keyword::Clt | Keyword::Cle | Keyword::Cgt | Keyword::Cge => {
+            &[&[ADDRESS, ITEM, ADDRESS]]
+        }
+        Keyword::Reserve => &[&[LITERAL]],
+    }
+}
+
+const fn usage(directive: Directive) -> &'static str {
+    match directive {
+        Directive::Org => ".org #address;",
+        Directive::Equ => ".equ name #value;",
+        Directive::Align => ".align #words;",
+        Directive::Section => ".section name [#origin];",
+        Directive::Macro => ".macro name [parameter ...];",
+        Directive::Endm => ".endm;",
+        Directive::Include => ".include \"path\";",
+        Directive::Global => ".global name;",
+    }
+}
+
+fn describe(expected: u8) -> String {
+    let mut options = Vec::new();
+    if expected & ADDRESS != 0 {
+        options.push("an address");
+    }
+    if expected & LITERAL != 0 {
+        options.push("a literal");
+    }
+    if expected & END != 0 {
+        options.push("`;`");
+    }
+    options.join(" or ")
+}
+
+/// work out why the statement at the start of `tokens` doesn't parse
+fn diagnose(sources: &Sources, tokens: &[Token], spans: &[Span]) -> ASMError {
+    let expected = |idx: usize, expected: String| {
+        let span = spans.get(idx).copied();
+        ASMError::new(
+            ErrorKind::Expected {
+                expected,
+                found: span.map(|span| Rc::from(sources.text(span))),
+            },
+            span.or_else(|| spans.last().copied()),
+        )
+    };
+    let keyword = match tokens[0] {
+        Token::Keyword(keyword) => keyword,
+        Token::Directive(directive) => {
+            let end = tokens
+                .iter()
+                .position(|token| matches!(token, Token::SemiColon))
+                .unwrap_or(tokens.len() - 1);
+            let span = spans[0].to(spans[end]);
+            return ASMError::new(
+                ErrorKind::Expected {
+                    expected: format!("`{}`", usage(directive)),
+                    found: Some(Rc::from(sources.text(span))),
+                },
+                Some(span),
+            );
+        }
+        Token::Literal(_) => return expected(1, describe(END)),
+        _ => return expected(0, String::from("an instruction")),
+    };
+    let mut candidates = signatures(keyword).to_vec();
+    for (idx, token) in tokens
+        .iter()
+        .enumerate()
+        .skip(1)
+        .chain([(tokens.len(), &Token::SemiColon)])
+    {
+        let operand = idx - 1;
+        let allowed = candidates
+            .iter()
+            .map(|signature| signature.get(operand).copied().unwrap_or(END))
+            .fold(0, |acc, class| acc | class);
+        let class = match token {
+            Token::Address(_) => ADDRESS,
+            Token::Literal(_) => LITERAL,
+            Token::SemiColon if idx < tokens.len() => END,
+            _ => return expected(idx, describe(allowed)),
+        };
+        if class == END && allowed & END != 0 {
+            // the operands all fit, so the only thing left is ternary math with two literals
+            return ASMError::new(ErrorKind::TwoLiterals, Some(spans[0].to(spans[idx])));
         }
-        [Token::Literal(lit), Token::SemiColon, rest @ ..] => {
-            output.push(Syntax::Literal(lit.to_number()));
-            interpret_tokens(rest, output)?;
-            Ok(())
+        candidates.retain(|signature| {
+            signature
+                .get(operand)
+                .is_some_and(|&expected| expected & class != 0)
+        });
+        if candidates.is_empty() {
+            return expected(idx, describe(allowed));
         }
-        rest => Err(rest.to_vec()),
     }
+    unreachable!("the end of the tokens always fails to parse")
 }
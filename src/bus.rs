@@ -0,0 +1,244 @@
+use std::{any::Any, fmt::Debug, ops::RangeInclusive};
+
+use crate::{Computer, ComputerDebug, CpuFault, RunOutcome};
+
+/// A peripheral mapped onto a range of addresses.
+///
+/// The words in the range are the device's registers. Every read and write of them, by the program
+/// or the host, goes straight to the device.
+pub trait Device: Any {
+    /// read the register `offset` words into the device's range
+    fn read(&self, offset: u16) -> u16;
+    /// write the register `offset` words into the device's range
+    fn write(&mut self, offset: u16, value: u16, interrupts: &mut Interrupts);
+    /// service the program after it yields. `registers` are the general-purpose registers
+    /// `0x0000` - `0x000F`. Return `true` if the device handled the yield and the program should
+    /// keep running.
+    fn on_yield(&mut self, registers: &mut [u16], interrupts: &mut Interrupts) -> bool {
+        let _ = (registers, interrupts);
+        false
+    }
+}
+
+/// The interrupt lines devices raise while they handle a write or a yield
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupts(u16);
+
+impl Interrupts {
+    /// raise interrupt line `line` (0-15); there are no lines past 15, so raising one does nothing
+    pub fn raise(&mut self, line: u16) {
+        self.0 |= 1_u16.checked_shl(line.into()).unwrap_or(0);
+    }
+
+    /// the lines raised, with bit `n` set for line `n` like the interrupt pending register
+    #[must_use]
+    pub const fn lines(self) -> u16 {
+        self.0
+    }
+}
+
+struct Mapping {
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn offset(&self, idx: u16) -> Option<u16> {
+        self.range.contains(&idx).then(|| idx - self.range.start())
+    }
+}
+
+/// The devices mapped into a computer's address space
+#[derive(Default)]
+pub struct Devices {
+    mappings: Vec<Mapping>,
+}
+
+impl Devices {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+        }
+    }
+
+    /// map `device` onto `range`; devices mapped later take priority where ranges overlap
+    pub fn map(&mut self, range: RangeInclusive<u16>, device: impl Device) {
+        self.mappings.push(Mapping {
+            range,
+            device: Box::new(device),
+        });
+    }
+
+    /// what the device mapped at `idx` reads there, if there is one
+    #[must_use]
+    pub fn read(&self, idx: u16) -> Option<u16> {
+        let (mapping, offset) = self.mapping(idx)?;
+        Some(mapping.device.read(offset))
+    }
+
+    /// write `value` to the device mapped at `idx`, returning the interrupts it raised, or `None`
+    /// if there isn't one
+    pub fn write(&mut self, idx: u16, value: u16) -> Option<Interrupts> {
+        let mapping = self
+            .mappings
+            .iter_mut()
+            .rev()
+            .find(|m| m.range.contains(&idx))?;
+        let offset = idx - mapping.range.start();
+        let mut interrupts = Interrupts::default();
+        mapping.device.write(offset, value, &mut interrupts);
+        Some(interrupts)
+    }
+
+    /// the device mapped at `idx`, if it's a `D`
+    #[must_use]
+    pub fn device<D: Device>(&self, idx: u16) -> Option<&D> {
+        let (mapping, _) = self.mapping(idx)?;
+        (mapping.device.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    /// the device mapped at `idx`, if it's a `D`
+    pub fn device_mut<D: Device>(&mut self, idx: u16) -> Option<&mut D> {
+        let mapping = self
+            .mappings
+            .iter_mut()
+            .rev()
+            .find(|m| m.range.contains(&idx))?;
+        (mapping.device.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    fn mapping(&self, idx: u16) -> Option<(&Mapping, u16)> {
+        self.mappings
+            .iter()
+            .rev()
+            .find_map(|mapping| Some((mapping, mapping.offset(idx)?)))
+    }
+
+    /// hand a yield to every device, returning `true` if any of them handled it
+    fn on_yield(&mut self, registers: &mut [u16], interrupts: &mut Interrupts) -> bool {
+        let mut handled = false;
+        for mapping in &mut self.mappings {
+            handled |= mapping.device.on_yield(registers, interrupts);
+        }
+        handled
+    }
+}
+
+/// A `Computer` that sends its memory accesses in the ranges of its `Devices` to them
+pub trait Mapped: Computer {
+    fn devices(&self) -> &Devices;
+    fn devices_mut(&mut self) -> &mut Devices;
+}
+
+/// A `Computer` with devices mapped into its address space, which services them when the program
+/// yields
+pub struct Bus<CPU: Mapped> {
+    comp: CPU,
+}
+
+impl<CPU: ComputerDebug + Mapped> Debug for Bus<CPU> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.comp)
+    }
+}
+
+impl<CPU: Mapped + Default> Default for Bus<CPU> {
+    fn default() -> Self {
+        Self::new(CPU::default())
+    }
+}
+
+impl<CPU: Mapped> Bus<CPU> {
+    pub const fn new(comp: CPU) -> Self {
+        Self { comp }
+    }
+
+    /// map `device` onto `range`; devices mapped later take priority where ranges overlap
+    pub fn map(&mut self, range: RangeInclusive<u16>, device: impl Device) {
+        self.comp.devices_mut().map(range, device);
+    }
+
+    pub const fn inner(&self) -> &CPU {
+        &self.comp
+    }
+
+    pub const fn inner_mut(&mut self) -> &mut CPU {
+        &mut self.comp
+    }
+
+    /// the device mapped at `idx`, if it's a `D`
+    pub fn device<D: Device>(&self, idx: u16) -> Option<&D> {
+        self.comp.devices().device(idx)
+    }
+
+    /// the device mapped at `idx`, if it's a `D`
+    pub fn device_mut<D: Device>(&mut self, idx: u16) -> Option<&mut D> {
+        self.comp.devices_mut().device_mut(idx)
+    }
+
+    /// hand a yield to every device, returning `true` if any of them handled it
+    fn service(&mut self) -> bool {
+        let mut registers: Vec<u16> = (0..0x10).map(|idx| self.comp.get_mem(idx)).collect();
+        let mut interrupts = Interrupts::default();
+        let handled = self
+            .comp
+            .devices_mut()
+            .on_yield(&mut registers, &mut interrupts);
+        self.comp.insert_data(0x0000_u16, &registers);
+        let pending = self.comp.get_mem(crate::CPU::INTERRUPT_PENDING_REGISTER);
+        self.comp.set_mem(
+            crate::CPU::INTERRUPT_PENDING_REGISTER,
+            pending | interrupts.lines(),
+        );
+        handled
+    }
+
+    fn run_until(&mut self, deadline: u64) -> Result<RunOutcome, CpuFault> {
+        self.comp
+            .run_for(deadline.saturating_sub(self.comp.cycles()))
+    }
+}
+
+impl<CPU: Mapped> Computer for Bus<CPU> {
+    fn insert_data(&mut self, idx: impl Into<usize>, data: &[u16]) {
+        self.comp.insert_data(idx, data);
+    }
+
+    fn run_for(&mut self, max_cycles: u64) -> Result<RunOutcome, CpuFault> {
+        let deadline = self.comp.cycles().saturating_add(max_cycles);
+        loop {
+            let outcome = self.run_until(deadline)?;
+            if outcome != RunOutcome::Yielded || !self.service() {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    fn is_halted(&self) -> bool {
+        self.comp.is_halted()
+    }
+
+    fn cycles(&self) -> u64 {
+        self.comp.cycles()
+    }
+
+    fn set_mem(&mut self, idx: u16, value: u16) {
+        self.comp.set_mem(idx, value);
+    }
+
+    fn get_mem(&self, idx: u16) -> u16 {
+        self.comp.get_mem(idx)
+    }
+}
+
+impl<CPU: ComputerDebug + Mapped> ComputerDebug for Bus<CPU> {
+    fn debug_until_yield(&mut self) -> Result<(), CpuFault> {
+        loop {
+            self.comp.debug_until_yield()?;
+            if self.comp.is_halted() || !self.service() {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -1,8 +1,42 @@
 use std::fmt::Debug;
 
+use crate::{CpuFault, CPU};
+
+/// Why `Computer::run_for` stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// the program yielded
+    Yielded,
+    /// the cycle budget ran out before the program yielded
+    BudgetExhausted,
+    /// the program halted
+    Halted,
+}
+
 pub trait Computer {
     fn insert_data(&mut self, idx: impl Into<usize>, data: &[u16]);
-    fn until_yield(&mut self);
+    /// run until the program yields or halts, or `max_cycles` instructions have been executed
+    /// # Errors
+    /// if the CPU faults for any reason other than halting
+    fn run_for(&mut self, max_cycles: u64) -> Result<RunOutcome, CpuFault>;
+    /// run until the program yields or halts
+    /// # Errors
+    /// if the CPU faults before yielding
+    fn until_yield(&mut self) -> Result<(), CpuFault> {
+        self.run_for(u64::MAX).map(|_| ())
+    }
+    fn is_halted(&self) -> bool;
+    /// mark interrupt line `line` (0-15) as pending; it's handled once the program enables it.
+    /// There are no lines past 15, so raising one does nothing.
+    fn raise_interrupt(&mut self, line: u16) {
+        let Some(mask) = 1_u16.checked_shl(line.into()) else {
+            return;
+        };
+        let pending = self.get_mem(CPU::INTERRUPT_PENDING_REGISTER);
+        self.set_mem(CPU::INTERRUPT_PENDING_REGISTER, pending | mask);
+    }
+    /// the number of instructions executed so far
+    fn cycles(&self) -> u64;
     fn set_mem(&mut self, idx: u16, value: u16);
     fn get_mem(&self, idx: u16) -> u16;
     fn insert_string(&mut self, idx: impl Into<usize>, data: &str) {
@@ -15,5 +49,7 @@ pub trait Computer {
 
 #[allow(clippy::module_name_repetitions)]
 pub trait ComputerDebug: Computer + Debug {
-    fn debug_until_yield(&mut self);
+    /// # Errors
+    /// if the CPU faults before yielding
+    fn debug_until_yield(&mut self) -> Result<(), CpuFault>;
 }
@@ -1,9 +1,14 @@
 use std::{
-    fmt::Debug,
+    collections::BTreeMap,
+    fmt::{Debug, Display},
     ops::{BitAnd, BitOr, BitXor},
+    rc::Rc,
 };
 
-use crate::{Computer, ComputerDebug};
+use crate::{
+    bus::{Devices, Mapped},
+    Computer, ComputerDebug, RunOutcome,
+};
 
 /// # Memory Layout
 /// ## Instruction Pointer
@@ -11,14 +16,86 @@ use crate::{Computer, ComputerDebug};
 /// ## Yield
 /// 0x0011
 /// set this register to `0x0001` to yield execution when using `Computer::until_yield()`
+/// ## Fault
+/// 0x0013
+/// holds the code of the last `CpuFault`, or `0x0000` if the CPU hasn't faulted
+/// ## Exit Code
+/// 0x0014
+/// the status a program reports when it executes `HALT`
+/// ## Interrupt Enable
+/// 0x0015
+/// bit `n` enables interrupt line `n`; cleared while an interrupt is being handled
+/// ## Interrupt Pending
+/// 0x0016
+/// bit `n` is set when line `n` is raised, and cleared when its handler is entered
+/// ## Timer Period
+/// 0x0017
+/// raise line 0 every this many cycles; `0x0000` disables the timer
+/// ## Interrupt Return
+/// 0x0018 - 0x0019
+/// the instruction pointer and interrupt enable register to restore on `RETI`
+/// ## Interrupt Vector Table
+/// 0x0020 - 0x002F
+/// the handler address for each interrupt line
 /// ## General-Purpose Registers
 /// 0x0000 - 0x000F
+/// ## Devices
+/// reads and writes of an address a device is mapped onto go to the device instead of memory
 pub struct CPU {
     memory: [u16; 0x10000],
+    devices: Devices,
+    cycles: u64,
+    halted: bool,
+    timer: u16,
+    /// names for addresses, shown by the `Debug` dump
+    symbols: BTreeMap<Rc<str>, u16>,
+}
+
+/// An instruction the CPU refused to execute. The instruction pointer is left on the faulting
+/// instruction, so `address` is also available through `CPU::INSTRUCTION_PTR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFault {
+    /// the second nibble isn't a mode or an argument extension for this operation
+    IllegalOpcode { address: u16, instruction: u16 },
+    /// an argument extension (`C`-`F`) selected a mode that doesn't exist for this operation
+    BadMode { address: u16, instruction: u16 },
+    /// the CPU executed `HALT`, or the instruction pointer points at itself
+    Halted,
+}
+
+impl CpuFault {
+    /// the value stored in `CPU::FAULT_REGISTER` for this fault
+    #[must_use]
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::IllegalOpcode { .. } => 1,
+            Self::BadMode { .. } => 2,
+            Self::Halted => 3,
+        }
+    }
+}
+
+impl Display for CpuFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IllegalOpcode {
+                address,
+                instruction,
+            } => write!(f, "Illegal opcode {instruction:0>4X} at {address:0>4X}"),
+            Self::BadMode {
+                address,
+                instruction,
+            } => write!(f, "Bad mode in {instruction:0>4X} at {address:0>4X}"),
+            Self::Halted => write!(f, "CPU is halted"),
+        }
+    }
 }
 
 impl Debug for CPU {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ip) = self.symbolic(self.get_mem(Self::INSTRUCTION_PTR)) {
+            writeln!(f, "IP {ip}")?;
+        }
         let mut last_displayed = 0;
         for x in 0..0x1000 {
             if (0..0x10)
@@ -26,7 +103,7 @@ impl Debug for CPU {
                 .all(|b| b == 0)
             {
                 continue;
-            };
+            }
             if x - last_displayed > 1 {
                 writeln!(f, "...")?;
             }
@@ -37,6 +114,19 @@ impl Debug for CPU {
                 let mem = self.get_mem(idx);
                 write!(f, " {mem:0>4X}")?;
             }
+            let row = x << 4..(x << 4) + 0x10;
+            let mut symbols: Vec<_> = self
+                .symbols
+                .iter()
+                .filter(|(_, address)| row.contains(*address))
+                .collect();
+            symbols.sort_by_key(|&(_, address)| address);
+            if !symbols.is_empty() {
+                write!(f, "  ;")?;
+            }
+            for (symbol, address) in symbols {
+                write!(f, " {symbol}@{address:0>4X}")?;
+            }
             writeln!(f)?;
         }
         Ok(())
@@ -46,62 +136,170 @@ impl Debug for CPU {
 impl CPU {
     pub const INSTRUCTION_PTR: u16 = 0x0010;
     pub const YIELD_REGISTER: u16 = 0x0011;
+    pub const FAULT_REGISTER: u16 = 0x0013;
+    pub const EXIT_CODE_REGISTER: u16 = 0x0014;
+    pub const INTERRUPT_ENABLE_REGISTER: u16 = 0x0015;
+    pub const INTERRUPT_PENDING_REGISTER: u16 = 0x0016;
+    pub const TIMER_PERIOD_REGISTER: u16 = 0x0017;
+    pub const INTERRUPT_RETURN_REGISTER: u16 = 0x0018;
+    pub const INTERRUPT_SAVED_ENABLE_REGISTER: u16 = 0x0019;
+    pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0020;
+    pub const TIMER_INTERRUPT: u16 = 0;
     pub const YIELD_INSTRUCTION: u16 = 0x0A00;
+    pub const HALT_INSTRUCTION: u16 = 0x0900;
+    pub const RETI_INSTRUCTION: u16 = 0x0B00;
 
     #[must_use]
+    #[allow(clippy::large_stack_arrays)]
     pub const fn new() -> Self {
         Self {
             memory: [0; 0x10000],
+            devices: Devices::new(),
+            cycles: 0,
+            halted: false,
+            timer: 0,
+            symbols: BTreeMap::new(),
         }
     }
+
+    /// name addresses in the `Debug` dump with `symbols`, like the labels of the running program
+    pub fn set_symbols(&mut self, symbols: BTreeMap<Rc<str>, u16>) {
+        self.symbols = symbols;
+    }
+
+    /// `address` as the closest symbol at or before it and an offset, if there is one
+    fn symbolic(&self, address: u16) -> Option<String> {
+        let (symbol, &at) = self
+            .symbols
+            .iter()
+            .filter(|(_, &at)| at <= address)
+            .max_by_key(|(_, &at)| at)?;
+        Some(if at == address {
+            format!("{address:0>4X} ({symbol})")
+        } else {
+            format!("{address:0>4X} ({symbol}+{:X})", address - at)
+        })
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Computer for CPU {
     fn insert_data(&mut self, idx: impl Into<usize>, data: &[u16]) {
         let idx = idx.into();
         for (word_idx, word) in data.iter().enumerate() {
-            self.memory[idx + word_idx] = *word;
+            #[allow(clippy::cast_possible_truncation)]
+            self.set_mem((idx + word_idx) as u16, *word);
         }
     }
 
-    fn until_yield(&mut self) {
+    fn run_for(&mut self, max_cycles: u64) -> Result<RunOutcome, CpuFault> {
+        let deadline = self.cycles.saturating_add(max_cycles);
         while self.get_mem(Self::YIELD_REGISTER) == 0 {
-            self.tick();
+            if self.is_halted() {
+                return Ok(RunOutcome::Halted);
+            }
+            if self.cycles >= deadline {
+                return Ok(RunOutcome::BudgetExhausted);
+            }
+            self.try_tick()?;
         }
         self.set_mem(Self::YIELD_REGISTER, 0);
+        Ok(RunOutcome::Yielded)
     }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn is_halted(&self) -> bool {
+        self.halted || self.get_mem(Self::INSTRUCTION_PTR) == Self::INSTRUCTION_PTR
+    }
+
     fn get_mem(&self, idx: u16) -> u16 {
-        self.memory[idx as usize]
+        self.devices.read(idx).unwrap_or(self.memory[idx as usize])
     }
 
     fn set_mem(&mut self, idx: u16, value: u16) {
-        self.memory[idx as usize] = value;
+        match self.devices.write(idx, value) {
+            Some(interrupts) => {
+                self.memory[usize::from(Self::INTERRUPT_PENDING_REGISTER)] |= interrupts.lines();
+            }
+            None => self.memory[idx as usize] = value,
+        }
+    }
+}
+
+impl Mapped for CPU {
+    fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    fn devices_mut(&mut self) -> &mut Devices {
+        &mut self.devices
     }
 }
 
 impl ComputerDebug for CPU {
-    fn debug_until_yield(&mut self) {
-        while self.get_mem(Self::YIELD_REGISTER) == 0 {
+    fn debug_until_yield(&mut self) -> Result<(), CpuFault> {
+        while self.get_mem(Self::YIELD_REGISTER) == 0 && !self.is_halted() {
             println!("{self:?}");
-            self.tick();
+            self.try_tick()?;
         }
         self.set_mem(Self::YIELD_REGISTER, 0);
         println!("{self:?}");
+        Ok(())
     }
 }
 
 impl CPU {
+    /// execute a single instruction; any fault is still recorded in `CPU::FAULT_REGISTER`
     pub fn tick(&mut self) {
-        let instruction_ptr = self.get_mem(Self::INSTRUCTION_PTR);
-        if instruction_ptr == Self::INSTRUCTION_PTR {
-            return;
+        let _ = self.try_tick();
+    }
+
+    /// execute a single instruction, leaving the instruction pointer in place if it faults
+    /// # Errors
+    /// if the instruction is undefined or the CPU is halted
+    pub fn try_tick(&mut self) -> Result<(), CpuFault> {
+        if self.is_halted() {
+            return Err(self.fault(CpuFault::Halted));
         }
+        self.dispatch_interrupt();
+        let instruction_ptr = self.get_mem(Self::INSTRUCTION_PTR);
         let instruction = self.get_mem(instruction_ptr);
         if instruction == Self::YIELD_INSTRUCTION {
+            self.count_cycle();
             self.set_mem(Self::YIELD_REGISTER, 1);
             self.advance_instruction(1);
-            return;
+            return Ok(());
+        }
+        if instruction == Self::HALT_INSTRUCTION {
+            self.count_cycle();
+            self.halted = true;
+            self.advance_instruction(1);
+            return Ok(());
         }
+        if instruction == Self::RETI_INSTRUCTION {
+            self.count_cycle();
+            self.set_mem(
+                Self::INSTRUCTION_PTR,
+                self.get_mem(Self::INTERRUPT_RETURN_REGISTER),
+            );
+            self.set_mem(
+                Self::INTERRUPT_ENABLE_REGISTER,
+                self.get_mem(Self::INTERRUPT_SAVED_ENABLE_REGISTER),
+            );
+            return Ok(());
+        }
+        if let Err(fault) = validate(instruction_ptr, instruction) {
+            return Err(self.fault(fault));
+        }
+        self.count_cycle();
         let nibbles = u16_to_nibbles(instruction);
         if nibbles.0 == 0 {
             // MOV/JMP
@@ -180,6 +378,61 @@ impl CPU {
                 nibbles,
             );
         }
+        Ok(())
+    }
+
+    /// jump to the handler of the lowest pending, enabled interrupt line
+    fn dispatch_interrupt(&mut self) {
+        let ready = self.get_mem(Self::INTERRUPT_PENDING_REGISTER)
+            & self.get_mem(Self::INTERRUPT_ENABLE_REGISTER);
+        if ready == 0 {
+            return;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let line = ready.trailing_zeros() as u16;
+        self.map_mem(
+            Self::INTERRUPT_PENDING_REGISTER,
+            !(1 << line),
+            BitAnd::bitand,
+        );
+        self.set_mem(
+            Self::INTERRUPT_RETURN_REGISTER,
+            self.get_mem(Self::INSTRUCTION_PTR),
+        );
+        self.set_mem(
+            Self::INTERRUPT_SAVED_ENABLE_REGISTER,
+            self.get_mem(Self::INTERRUPT_ENABLE_REGISTER),
+        );
+        self.set_mem(Self::INTERRUPT_ENABLE_REGISTER, 0);
+        self.set_mem(
+            Self::INSTRUCTION_PTR,
+            self.get_mem(Self::INTERRUPT_VECTOR_TABLE + line),
+        );
+    }
+
+    /// count an executed instruction and step the timer
+    fn count_cycle(&mut self) {
+        self.cycles += 1;
+        let period = self.get_mem(Self::TIMER_PERIOD_REGISTER);
+        if period == 0 {
+            self.timer = 0;
+            return;
+        }
+        self.timer += 1;
+        if self.timer >= period {
+            self.timer = 0;
+            self.raise_interrupt(Self::TIMER_INTERRUPT);
+        }
+    }
+
+    /// let a halted CPU continue from the instruction after its `HALT`
+    pub const fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    fn fault(&mut self, fault: CpuFault) -> CpuFault {
+        self.set_mem(Self::FAULT_REGISTER, fault.code());
+        fault
     }
 
     fn mov_or_jmp(&mut self, mode: u16, first_arg: u16, second_arg: u16) {
@@ -291,7 +544,7 @@ impl CPU {
         nibbles: (u16, u16, u16, u16),
     ) {
         self.advance_instruction(2);
-        if nibbles.1 <= 4 {
+        if nibbles.1 <= 5 {
             // normal
             let third_arg = self.get_mem(instruction_ptr + 1);
             self.cmp_op(operation, nibbles.1, nibbles.2, nibbles.3, third_arg);
@@ -333,9 +586,18 @@ impl CPU {
         } else if mode == 1 {
             self.map_mem(second_arg, first_arg, operation);
         } else if mode == 2 {
+            // &SRCA, &SRC, &DST
             let source_a = self.get_mem(first_arg);
             let source = self.get_mem(second_arg);
             self.set_mem(third_arg, operation(source_a, source));
+        } else if mode == 3 {
+            // #LIT, &SRC, &DST
+            let source = self.get_mem(second_arg);
+            self.set_mem(third_arg, operation(first_arg, source));
+        } else if mode == 4 {
+            // &SRC, #LIT, &DST
+            let source = self.get_mem(first_arg);
+            self.set_mem(third_arg, operation(source, second_arg));
         }
     }
 
@@ -426,20 +688,21 @@ impl CPU {
         }
     }
 
-    pub fn mut_mem(&mut self, idx: u16) -> &mut u16 {
+    /// the word of memory at `idx`, even if a device is mapped there
+    pub const fn mut_mem(&mut self, idx: u16) -> &mut u16 {
         &mut self.memory[idx as usize]
     }
 
     pub fn add_mem(&mut self, idx: u16, value: u16) {
-        self.memory[idx as usize] = self.memory[idx as usize].wrapping_add(value);
+        self.map_mem(idx, value, u16::wrapping_add);
     }
 
     pub fn sub_mem(&mut self, idx: u16, value: u16) {
-        self.memory[idx as usize] = self.memory[idx as usize].wrapping_sub(value);
+        self.map_mem(idx, value, u16::wrapping_sub);
     }
 
     pub fn map_mem<F: Fn(u16, u16) -> u16>(&mut self, idx: u16, value: u16, func: F) {
-        self.memory[idx as usize] = func(self.memory[idx as usize], value);
+        self.set_mem(idx, func(self.get_mem(idx), value));
     }
 
     pub fn advance_instruction(&mut self, value: u16) {
@@ -447,6 +710,37 @@ impl CPU {
     }
 }
 
+/// make sure the mode nibbles of an instruction are defined for its operation
+pub const fn validate(address: u16, instruction: u16) -> Result<(), CpuFault> {
+    let nibbles = u16_to_nibbles(instruction);
+    // highest defined mode, and the lowest argument extension
+    let (max_mode, min_extension) = match nibbles.0 {
+        // MOV/JMP
+        0 => (0x8, 0xD),
+        // comparisons
+        4..=9 => (0x5, 0xC),
+        // PTR
+        0xA => (0x3, 0xD),
+        // math
+        _ => (0x4, 0xC),
+    };
+    if nibbles.1 <= max_mode {
+        Ok(())
+    } else if nibbles.1 < min_extension {
+        Err(CpuFault::IllegalOpcode {
+            address,
+            instruction,
+        })
+    } else if nibbles.2 > max_mode {
+        Err(CpuFault::BadMode {
+            address,
+            instruction,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 const fn u16_to_nibbles(instruction: u16) -> (u16, u16, u16, u16) {
     (
         instruction >> 12,
@@ -0,0 +1,413 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+    rc::Rc,
+};
+
+use crate::{decode, Computer, CpuFault, Instruction, RunOutcome, CPU};
+
+const HELP: &str = "\
+addresses and values are hex, a register like `r3`, `ip`, or a label; counts are decimal
+  s, step [count]         execute one or `count` instructions
+  c, continue             run until a breakpoint, a watchpoint, a fault or the program halts
+  b, break [addr]         set a breakpoint, or list them
+  d, delete <addr>        remove a breakpoint
+  w, watch [addr[..addr]] stop when memory in the range changes, or list watchpoints
+  u, unwatch <addr>       remove the watchpoints that include an address
+  r, regs                 show the registers
+  m, mem <addr>[..addr]   show memory; 16 words if there's no end address
+  l, list [count]         disassemble around the instruction pointer
+  set <addr> <value>      write a word to memory
+  q, quit                 stop debugging
+an empty line repeats the last command";
+
+/// how many instructions `list` shows before the instruction pointer
+const LIST_BEFORE: usize = 3;
+/// how far back `list` looks for a label to start decoding from
+const LABEL_REACH: u16 = 0x40;
+
+/// An interactive debugger that runs a program one instruction at a time
+pub struct Debugger<C: Computer> {
+    comp: C,
+    labels: BTreeMap<Rc<str>, u16>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    last_command: String,
+}
+
+struct Watchpoint {
+    range: RangeInclusive<u16>,
+    values: Vec<u16>,
+}
+
+/// why the debugger stopped running the program
+enum Stop {
+    Breakpoint(u16),
+    Watchpoint { address: u16, old: u16, new: u16 },
+    Halted,
+    Fault(CpuFault),
+}
+
+impl<C: Computer> Debugger<C> {
+    /// debug `comp`, using `labels` to name addresses
+    pub const fn new(comp: C, labels: BTreeMap<Rc<str>, u16>) -> Self {
+        Self {
+            comp,
+            labels,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub const fn computer(&self) -> &C {
+        &self.comp
+    }
+
+    pub const fn computer_mut(&mut self) -> &mut C {
+        &mut self.comp
+    }
+
+    /// read commands from `input` until it ends or the user quits
+    /// # Errors
+    /// if reading or writing fails
+    pub fn repl(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            write!(output, "(debug) ")?;
+            output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 || !self.command(&line, &mut output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// run a single command, returning `false` if the user quit
+    /// # Errors
+    /// if writing fails
+    pub fn command(&mut self, line: &str, output: &mut impl Write) -> io::Result<bool> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command.clone_from(&line);
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = words.collect();
+        match (command, &args[..]) {
+            ("s" | "step", []) => self.run(1, output)?,
+            ("s" | "step", [count]) => match count.parse() {
+                Ok(count) => self.run(count, output)?,
+                Err(_) => writeln!(output, "bad count `{count}`")?,
+            },
+            ("c" | "continue", []) => self.run(u64::MAX, output)?,
+            ("b" | "break", []) => {
+                for &address in &self.breakpoints {
+                    writeln!(output, "{}", self.describe(address))?;
+                }
+            }
+            ("b" | "break", [address]) => {
+                if let Some(address) = self.address(address, output)? {
+                    self.breakpoints.insert(address);
+                    writeln!(output, "breakpoint at {}", self.describe(address))?;
+                }
+            }
+            ("d" | "delete", [address]) => {
+                if let Some(address) = self.address(address, output)? {
+                    if !self.breakpoints.remove(&address) {
+                        writeln!(output, "no breakpoint at {}", self.describe(address))?;
+                    }
+                }
+            }
+            ("w" | "watch", []) => {
+                for watchpoint in &self.watchpoints {
+                    writeln!(
+                        output,
+                        "{}..{}",
+                        self.describe(*watchpoint.range.start()),
+                        self.describe(*watchpoint.range.end())
+                    )?;
+                }
+            }
+            ("w" | "watch", [range]) => {
+                if let Some(range) = self.range(range, 1, output)? {
+                    let values = range.clone().map(|idx| self.comp.get_mem(idx)).collect();
+                    self.watchpoints.push(Watchpoint { range, values });
+                }
+            }
+            ("u" | "unwatch", [address]) => {
+                if let Some(address) = self.address(address, output)? {
+                    self.watchpoints
+                        .retain(|watchpoint| !watchpoint.range.contains(&address));
+                }
+            }
+            ("r" | "regs", []) => self.registers(output)?,
+            ("m" | "mem", [range]) => {
+                if let Some(range) = self.range(range, 0x10, output)? {
+                    self.memory(range, output)?;
+                }
+            }
+            ("l" | "list", []) => self.list(LIST_BEFORE, 8, output)?,
+            ("l" | "list", [count]) => match count.parse() {
+                Ok(count) => self.list(LIST_BEFORE, count, output)?,
+                Err(_) => writeln!(output, "bad count `{count}`")?,
+            },
+            ("set", [address, value]) => {
+                if let (Some(address), Some(value)) =
+                    (self.address(address, output)?, self.address(value, output)?)
+                {
+                    self.comp.set_mem(address, value);
+                    // don't report the user's own change as a watchpoint hit
+                    for watchpoint in &mut self.watchpoints {
+                        if watchpoint.range.contains(&address) {
+                            let offset = address - watchpoint.range.start();
+                            watchpoint.values[usize::from(offset)] = value;
+                        }
+                    }
+                }
+            }
+            ("q" | "quit", []) => return Ok(false),
+            ("h" | "help", []) => writeln!(output, "{HELP}")?,
+            _ => writeln!(output, "unknown command `{line}`; try `help`")?,
+        }
+        Ok(true)
+    }
+
+    /// execute up to `count` instructions, stopping early at breakpoints and watchpoints
+    fn run(&mut self, count: u64, output: &mut impl Write) -> io::Result<()> {
+        for _ in 0..count {
+            if let Some(stop) = self.step() {
+                match stop {
+                    Stop::Breakpoint(address) => {
+                        writeln!(output, "breakpoint at {}", self.describe(address))?;
+                    }
+                    Stop::Watchpoint { address, old, new } => writeln!(
+                        output,
+                        "{} changed from {old:0>4X} to {new:0>4X}",
+                        self.describe(address)
+                    )?,
+                    Stop::Halted => writeln!(
+                        output,
+                        "halted with exit code {:0>4X}",
+                        self.comp.get_mem(CPU::EXIT_CODE_REGISTER)
+                    )?,
+                    Stop::Fault(fault) => writeln!(output, "{fault}")?,
+                }
+                break;
+            }
+        }
+        self.list(0, 1, output)
+    }
+
+    /// execute one instruction, reporting anything that should stop the program
+    fn step(&mut self) -> Option<Stop> {
+        match self.comp.run_for(1) {
+            Err(fault) => return Some(Stop::Fault(fault)),
+            Ok(RunOutcome::Halted) => return Some(Stop::Halted),
+            Ok(RunOutcome::Yielded | RunOutcome::BudgetExhausted) => {}
+        }
+        let mut stop = None;
+        for watchpoint in &mut self.watchpoints {
+            for (address, old) in watchpoint.range.clone().zip(&mut watchpoint.values) {
+                let new = self.comp.get_mem(address);
+                if new != *old {
+                    stop = stop.or(Some(Stop::Watchpoint {
+                        address,
+                        old: *old,
+                        new,
+                    }));
+                    *old = new;
+                }
+            }
+        }
+        let instruction_ptr = self.comp.get_mem(CPU::INSTRUCTION_PTR);
+        if self.comp.is_halted() {
+            Some(Stop::Halted)
+        } else if self.breakpoints.contains(&instruction_ptr) {
+            stop.or(Some(Stop::Breakpoint(instruction_ptr)))
+        } else {
+            stop
+        }
+    }
+
+    fn registers(&self, output: &mut impl Write) -> io::Result<()> {
+        for row in [0x0..=0x7, 0x8..=0xF] {
+            let row: Vec<String> = row
+                .map(|register| format!("r{register:X}={:0>4X}", self.comp.get_mem(register)))
+                .collect();
+            writeln!(output, "{}", row.join(" "))?;
+        }
+        writeln!(
+            output,
+            "ip={:0>4X} fault={:0>4X} exit={:0>4X} enable={:0>4X} pending={:0>4X} cycles={}",
+            self.comp.get_mem(CPU::INSTRUCTION_PTR),
+            self.comp.get_mem(CPU::FAULT_REGISTER),
+            self.comp.get_mem(CPU::EXIT_CODE_REGISTER),
+            self.comp.get_mem(CPU::INTERRUPT_ENABLE_REGISTER),
+            self.comp.get_mem(CPU::INTERRUPT_PENDING_REGISTER),
+            self.comp.cycles()
+        )
+    }
+
+    fn memory(&self, range: RangeInclusive<u16>, output: &mut impl Write) -> io::Result<()> {
+        let (start, end) = (*range.start(), *range.end());
+        let mut row = start;
+        loop {
+            write!(output, "{row:0>4X}")?;
+            let row_end = row.saturating_add(0xF).min(end);
+            for idx in row..=row_end {
+                write!(output, " {:0>4X}", self.comp.get_mem(idx))?;
+            }
+            writeln!(output)?;
+            if row_end == end {
+                return Ok(());
+            }
+            row = row_end + 1;
+        }
+    }
+
+    /// disassemble up to `before` instructions leading up to the instruction pointer, then `count`
+    /// starting at it
+    fn list(&self, before: usize, count: u16, output: &mut impl Write) -> io::Result<()> {
+        let instruction_ptr = self.comp.get_mem(CPU::INSTRUCTION_PTR);
+        for address in self.preceding(instruction_ptr, before) {
+            self.list_one(address, instruction_ptr, output)?;
+        }
+        let mut address = instruction_ptr;
+        for _ in 0..count {
+            let len = self.list_one(address, instruction_ptr, output)?;
+            address = address.wrapping_add(len);
+        }
+        Ok(())
+    }
+
+    /// disassemble the instruction at `address`, marking it if it's at the instruction pointer,
+    /// and return how many words it is
+    fn list_one(
+        &self,
+        address: u16,
+        instruction_ptr: u16,
+        output: &mut impl Write,
+    ) -> io::Result<u16> {
+        for (label, _) in self.labels.iter().filter(|(_, &idx)| idx == address) {
+            writeln!(output, ":{label}")?;
+        }
+        let (instruction, code) = self.decode(address);
+        let words: Vec<String> = code.iter().map(|word| format!("{word:0>4X}")).collect();
+        let marker = if address == instruction_ptr { '>' } else { ' ' };
+        writeln!(
+            output,
+            "{marker} {address:0>4X}: {:<19} {instruction}",
+            words.join(" ")
+        )?;
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(code.len() as u16)
+    }
+
+    /// the instruction at `address`, and the words it's made of
+    fn decode(&self, address: u16) -> (Instruction, Vec<u16>) {
+        let mut code: Vec<u16> = (0..4)
+            .map(|offset| self.comp.get_mem(address.wrapping_add(offset)))
+            .collect();
+        let (instruction, len) = decode(&code);
+        code.truncate(len);
+        (instruction, code)
+    }
+
+    /// the addresses of up to `before` instructions leading up to `address`. Instructions can't be
+    /// decoded backwards, so this decodes forwards from the closest label before `address`, or
+    /// failing that from the furthest address that still lands on it.
+    fn preceding(&self, address: u16, before: usize) -> Vec<u16> {
+        // no instruction is longer than 4 words
+        let window = u16::try_from(before * 4).unwrap_or(u16::MAX);
+        let label = self
+            .labels
+            .values()
+            .copied()
+            .filter(|&label| label < address && address - label <= LABEL_REACH)
+            .max();
+        let furthest = (1..=window)
+            .rev()
+            .filter_map(|back| address.checked_sub(back));
+        label
+            .into_iter()
+            .chain(furthest)
+            .find_map(|start| self.starts_between(start, address))
+            .map(|starts| starts[starts.len().saturating_sub(before)..].to_vec())
+            .unwrap_or_default()
+    }
+
+    /// the address of each instruction from `start`, if decoding from there lands on `end`
+    fn starts_between(&self, start: u16, end: u16) -> Option<Vec<u16>> {
+        let mut starts = Vec::new();
+        let mut address = start;
+        while address < end {
+            starts.push(address);
+            #[allow(clippy::cast_possible_truncation)]
+            let len = self.decode(address).1.len() as u16;
+            address = address.checked_add(len)?;
+        }
+        (address == end).then_some(starts)
+    }
+
+    /// an address along with its label, if it has one
+    fn describe(&self, address: u16) -> String {
+        self.labels
+            .iter()
+            .find(|(_, &idx)| idx == address)
+            .map_or_else(
+                || format!("{address:0>4X}"),
+                |(label, _)| format!("{address:0>4X} ({label})"),
+            )
+    }
+
+    fn address(&self, src: &str, output: &mut impl Write) -> io::Result<Option<u16>> {
+        let address = if src == "ip" {
+            Some(CPU::INSTRUCTION_PTR)
+        } else if let Some(&address) = self.labels.get(src) {
+            Some(address)
+        } else if let Some(register) = src.strip_prefix('r').filter(|register| register.len() == 1)
+        {
+            u16::from_str_radix(register, 16).ok()
+        } else {
+            u16::from_str_radix(src, 16).ok()
+        };
+        if address.is_none() {
+            writeln!(output, "bad address `{src}`")?;
+        }
+        Ok(address)
+    }
+
+    /// parse `start..end`, or `start` followed by `default_len` words
+    fn range(
+        &self,
+        src: &str,
+        default_len: u16,
+        output: &mut impl Write,
+    ) -> io::Result<Option<RangeInclusive<u16>>> {
+        let (start, end) = match src.split_once("..") {
+            Some((start, end)) => (start, Some(end)),
+            None => (src, None),
+        };
+        let Some(start) = self.address(start, output)? else {
+            return Ok(None);
+        };
+        let end = match end {
+            Some(end) => match self.address(end, output)? {
+                Some(end) => end,
+                None => return Ok(None),
+            },
+            None => start.saturating_add(default_len.saturating_sub(1)),
+        };
+        if end < start {
+            writeln!(output, "empty range `{src}`")?;
+            return Ok(None);
+        }
+        Ok(Some(start..=end))
+    }
+}
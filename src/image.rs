@@ -0,0 +1,108 @@
+use crate::{Computer, CPU};
+
+/// A run of words loaded at `origin`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Segment {
+    /// one past the last address, which may be `0x10000`
+    #[must_use]
+    pub fn end(&self) -> usize {
+        usize::from(self.origin) + self.words.len()
+    }
+}
+
+/// A program made up of segments, and where to start running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub entry: u16,
+    pub segments: Vec<Segment>,
+}
+
+impl Image {
+    /// where programs are loaded and start unless they say otherwise
+    pub const DEFAULT_ORIGIN: u16 = 0x8000;
+    /// the start of an image with more than one segment. `0x49` isn't a valid instruction, so plain
+    /// bytecode can't start with it.
+    pub const MAGIC: [u16; 2] = [0x494D, 0x4731];
+
+    /// a program of plain bytecode, loaded and started at `DEFAULT_ORIGIN`
+    #[must_use]
+    pub fn flat(words: Vec<u16>) -> Self {
+        Self {
+            entry: Self::DEFAULT_ORIGIN,
+            segments: vec![Segment {
+                origin: Self::DEFAULT_ORIGIN,
+                words,
+            }],
+        }
+    }
+
+    /// the words of the image as one block loaded at `origin`, with zeros in any gaps, or `None` if
+    /// a segment starts before `origin`
+    #[must_use]
+    pub fn flatten(&self, origin: u16) -> Option<Vec<u16>> {
+        let end = self.segments.iter().map(Segment::end).max().unwrap_or(0);
+        let mut words = vec![0; end.saturating_sub(origin.into())];
+        for segment in &self.segments {
+            let start = usize::from(segment.origin.checked_sub(origin)?);
+            words[start..start + segment.words.len()].copy_from_slice(&segment.words);
+        }
+        Some(words)
+    }
+
+    /// put the segments in memory and point the instruction pointer at the entry
+    pub fn load(&self, comp: &mut impl Computer) {
+        for segment in &self.segments {
+            comp.insert_data(segment.origin, &segment.words);
+        }
+        comp.set_mem(CPU::INSTRUCTION_PTR, self.entry);
+    }
+
+    /// encode the image. One segment at `DEFAULT_ORIGIN` is plain bytecode; anything else is
+    /// `MAGIC`, the entry, the number of segments, then each segment's origin, length and words.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_words(&self) -> Vec<u16> {
+        match &self.segments[..] {
+            [segment]
+                if segment.origin == Self::DEFAULT_ORIGIN && self.entry == Self::DEFAULT_ORIGIN =>
+            {
+                segment.words.clone()
+            }
+            segments => {
+                let mut words = Self::MAGIC.to_vec();
+                words.extend([self.entry, segments.len() as u16]);
+                for segment in segments {
+                    words.extend([segment.origin, segment.words.len() as u16]);
+                    words.extend(&segment.words);
+                }
+                words
+            }
+        }
+    }
+
+    /// decode an image written by `to_words`, or `None` if it's been cut short
+    #[must_use]
+    pub fn from_words(words: &[u16]) -> Option<Self> {
+        let Some(mut rest) = words.strip_prefix(&Self::MAGIC[..]) else {
+            return Some(Self::flat(words.to_vec()));
+        };
+        let (&[entry, count], after) = rest.split_first_chunk()?;
+        rest = after;
+        let mut segments = Vec::new();
+        for _ in 0..count {
+            let (&[origin, len], after) = rest.split_first_chunk()?;
+            let (segment, after) = after.split_at_checked(len.into())?;
+            segments.push(Segment {
+                origin,
+                words: segment.to_vec(),
+            });
+            rest = after;
+        }
+        Some(Self { entry, segments })
+    }
+}
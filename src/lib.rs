@@ -1,16 +1,33 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 
 mod asm;
+mod bus;
 mod computer;
 mod cpu;
+mod debugger;
+mod image;
+mod object;
+mod robin;
 mod stdio;
 #[cfg(test)]
 mod tests;
 mod utils;
-mod robin;
 
-pub use asm::compile_asm;
-pub use computer::{Computer, ComputerDebug};
-pub use cpu::CPU;
-pub use stdio::ComputerIO;
-pub use robin::pipe;
+pub use asm::{
+    compile_asm, compile_asm_file, compile_asm_file_listed, compile_asm_image, compile_asm_object,
+    compile_asm_object_file, compile_asm_with_labels, decode, parse_symbol_map, stack, symbol_map,
+    ASMError, CmpOp, ErrorKind, Instruction, Item, Labels, Listing, MathOp, Span, Value,
+};
+pub use bus::{Bus, Device, Devices, Interrupts, Mapped};
+pub use computer::{Computer, ComputerDebug, RunOutcome};
+pub use cpu::{CpuFault, CPU};
+pub use debugger::Debugger;
+pub use image::{Image, Segment};
+pub use object::{link, Export, Object, Relocation, Section, Target};
+pub use robin::{
+    check, compile as compile_robin, pipe, pipe_listed, pipe_object, AssignOp, BinaryOp, BlockType,
+    CompileOptions, CompileOutput, Diagnostic, Error as RobinError, Expression,
+    Keyword as RobinKeyword, Severity, Spanned, Statement, Token as RobinToken, TopLevelSyntax,
+    UnaryOp,
+};
+pub use stdio::{ComputerIO, ConsoleDevice, Input, StdinReader};
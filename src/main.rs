@@ -1,9 +1,19 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 
-use std::fs;
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand};
-use computer::{compile_asm, pipe as robin_pipe, Computer, ComputerDebug, ComputerIO, CPU};
+use computer::{
+    compile_asm_file, compile_asm_file_listed, compile_asm_object_file,
+    compile_robin as robin_compile, decode, parse_symbol_map, symbol_map, ASMError, CompileOptions,
+    Computer, ComputerDebug, ComputerIO, Debugger, Image, Input, Labels, Listing, Object,
+    RunOutcome, StdinReader, CPU,
+};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -20,6 +30,37 @@ enum SubCommand {
         /// print the computer's memory at each stage of execution
         #[clap(short, long)]
         debug: bool,
+        /// stop after executing this many instructions
+        #[clap(long, conflicts_with = "debug")]
+        max_cycles: Option<u64>,
+        /// file to read console input from instead of stdin
+        #[clap(long)]
+        input: Option<String>,
+        /// file to write console output to instead of stdout
+        #[clap(long)]
+        output: Option<String>,
+        /// symbol map to name addresses with in the `--debug` dump
+        #[clap(long, requires = "debug")]
+        symbols: Option<String>,
+    },
+    /// step through a program interactively
+    Debug {
+        /// file to load bytecode from, or assembly if it ends in `.asm`
+        filename: String,
+        /// file to read the program's console input from; it gets no input otherwise
+        #[clap(long)]
+        input: Option<String>,
+        /// directory to look for `.include`d files in, after the including file's own
+        #[clap(short = 'I', long = "include-path")]
+        include_paths: Vec<PathBuf>,
+        /// symbol map to name addresses with, for bytecode
+        #[clap(long)]
+        symbols: Option<String>,
+    },
+    /// print the instructions in a bytecode program
+    Disasm {
+        /// file to load bytecode from
+        filename: String,
     },
     /// compile an assembly program to bytecode
     CompileAsm {
@@ -27,68 +68,367 @@ enum SubCommand {
         source: String,
         /// file to output bytecode
         destination: String,
+        /// directory to look for `.include`d files in, after the including file's own
+        #[clap(short = 'I', long = "include-path")]
+        include_paths: Vec<PathBuf>,
+        /// output an object for `link` instead of bytecode
+        #[clap(short = 'c', long)]
+        object: bool,
+        #[command(flatten)]
+        outputs: Outputs,
     },
     /// compile Robin language to assembly
     CompileRobin {
         /// file to load Robin from
         source: String,
-        /// file to save ASM to
+        /// file to save bytecode to
+        destination: String,
+        /// output an object for `link` instead of bytecode
+        #[clap(short = 'c', long)]
+        object: bool,
+        /// what to write; `bin` goes to the destination, and the others next to it, with what they
+        /// are as the extension
+        #[clap(long, value_enum, value_delimiter = ',', default_value = "bin")]
+        emit: Vec<Emit>,
+        #[command(flatten)]
+        outputs: Outputs,
+    },
+    /// combine objects into a bytecode program
+    Link {
+        /// file to output bytecode
         destination: String,
+        /// object files to combine; the program starts at the first one's code
+        #[clap(required = true)]
+        objects: Vec<String>,
+        /// address to put the program at, in hex
+        #[clap(long, default_value = "8000", value_parser = parse_address)]
+        base: u16,
+        /// file to write the address of each exported symbol to
+        #[clap(long)]
+        symbols: Option<String>,
     },
 }
 
-const PROGRAM_LOCATION: u16 = 0x8000;
+/// Something `compile-robin` can write
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Emit {
+    /// the tokens, one to a line after the line and column it's at
+    Tokens,
+    /// the syntax tree
+    Ast,
+    /// the assembly
+    Asm,
+    /// the bytecode, or the object with `--object`
+    Bin,
+}
+
+/// Files to describe a compiled program with
+#[derive(Clone, clap::Args, Debug)]
+struct Outputs {
+    /// file to write the address of each label to
+    #[clap(long, conflicts_with = "object")]
+    symbols: Option<String>,
+    /// file to write the address, words and source of each statement to
+    #[clap(long, conflicts_with = "object")]
+    listing: Option<String>,
+}
+
+impl Outputs {
+    const fn wanted(&self) -> bool {
+        self.symbols.is_some() || self.listing.is_some()
+    }
+
+    fn write(&self, labels: &Labels, listing: &Listing) {
+        if let Some(symbols) = &self.symbols {
+            fs::write(symbols, symbol_map(labels)).unwrap();
+        }
+        if let Some(filename) = &self.listing {
+            fs::write(filename, listing.to_string()).unwrap();
+        }
+    }
+}
+
+fn read_words(filename: &str) -> Vec<u16> {
+    fs::read(filename)
+        .unwrap()
+        .chunks(2)
+        .map(|chunk| {
+            (u16::from(chunk.first().copied().unwrap_or_default()) << 8)
+                | u16::from(chunk.get(1).copied().unwrap_or_default())
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_words(filename: &str, words: &[u16]) {
+    fs::write(
+        filename,
+        words
+            .iter()
+            .flat_map(|&b| [(b >> 8) as u8, b as u8])
+            .collect::<Vec<u8>>(),
+    )
+    .unwrap();
+}
+
+fn read_image(filename: &str) -> Image {
+    Image::from_words(&read_words(filename)).unwrap_or_else(|| {
+        eprintln!("{filename} is cut short");
+        std::process::exit(1)
+    })
+}
+
+/// the exit code of `run` when the program runs out of cycles
+const BUDGET_EXIT_CODE: i32 = 254;
+/// the exit code of `run` when the program faults
+const FAULT_EXIT_CODE: i32 = 255;
+
+/// the exit code of `run` for a program that halted with `value` in its exit code register. Only
+/// the low 8 bits of an exit code survive, so values past the ones left over from
+/// `BUDGET_EXIT_CODE` and `FAULT_EXIT_CODE` become the highest one left rather than wrapping,
+/// which could turn a failure into `0`.
+fn exit_code(value: u16) -> i32 {
+    i32::from(value).min(BUDGET_EXIT_CODE - 1)
+}
+
+fn parse_address(address: &str) -> Result<u16, String> {
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    u16::from_str_radix(digits, 16).map_err(|err| format!("`{address}` isn't a hex address: {err}"))
+}
 
 fn main() {
     let args = Args::parse();
-    println!("{args:?}");
     match args.subcommand {
-        SubCommand::Run { debug, filename } => {
-            let read_file: Vec<u16> = fs::read(filename)
-                .unwrap()
-                .chunks(2)
-                .map(|chunk| {
-                    ((chunk.get(0).copied().unwrap_or_default() as u16) << 8)
-                        | chunk.get(1).copied().unwrap_or_default() as u16
+        SubCommand::Run {
+            debug,
+            filename,
+            max_cycles,
+            input,
+            output,
+            symbols,
+        } => {
+            let image = read_image(&filename);
+            let reader: Box<dyn Input> = match input {
+                Some(input) => Box::new(BufReader::new(fs::File::open(input).unwrap())),
+                None => Box::new(StdinReader::new()),
+            };
+            let writer: Box<dyn Write> = match output {
+                Some(output) => Box::new(BufWriter::new(fs::File::create(output).unwrap())),
+                None => Box::new(std::io::stdout()),
+            };
+            let mut cpu = CPU::new();
+            if let Some(symbols) = symbols {
+                cpu.set_symbols(read_symbols(&symbols));
+            }
+            let mut comp = ComputerIO::with_streams(cpu, reader, writer);
+            image.load(&mut comp);
+            let result = if debug {
+                comp.debug_until_yield().map(|()| {
+                    if comp.is_halted() {
+                        RunOutcome::Halted
+                    } else {
+                        RunOutcome::Yielded
+                    }
                 })
-                .collect();
-            let mut comp = ComputerIO::new(CPU::new());
-            comp.insert_data(PROGRAM_LOCATION, &read_file);
-            comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_LOCATION);
-            if debug {
-                comp.debug_until_yield();
             } else {
-                comp.until_yield();
+                comp.run_for(max_cycles.unwrap_or(u64::MAX))
+            };
+            match result {
+                Ok(RunOutcome::Yielded) => {}
+                Ok(RunOutcome::Halted) => {
+                    std::process::exit(exit_code(comp.get_mem(CPU::EXIT_CODE_REGISTER)));
+                }
+                Ok(RunOutcome::BudgetExhausted) => {
+                    eprintln!("Ran out of cycles after {}", comp.cycles());
+                    std::process::exit(BUDGET_EXIT_CODE);
+                }
+                Err(fault) => {
+                    eprintln!("{fault}");
+                    std::process::exit(FAULT_EXIT_CODE);
+                }
             }
         }
+        SubCommand::Debug {
+            filename,
+            input,
+            include_paths,
+            symbols,
+        } => debug(&filename, input, &include_paths, symbols.as_deref()),
+        SubCommand::Disasm { filename } => disasm(&filename),
         SubCommand::CompileAsm {
             source,
             destination,
-        } => {
-            let read_file = fs::read_to_string(source).unwrap();
-            let machine_code = compile_asm(&read_file).unwrap();
-            fs::write(
-                destination,
-                machine_code
-                    .into_iter()
-                    .flat_map(|b| [(b >> 8) as u8, b as u8])
-                    .collect::<Vec<u8>>(),
-            )
-            .unwrap();
-        }
+            include_paths,
+            object,
+            outputs,
+        } => compile_asm(&source, &destination, &include_paths, object, &outputs),
         SubCommand::CompileRobin {
             source,
             destination,
-        } => {
-            let read_file = fs::read_to_string(source).unwrap();
-            let asm = robin_pipe(&read_file).unwrap();
-            fs::write(
-                destination,
-                asm.into_iter()
-                    .flat_map(|b| [(b >> 8) as u8, b as u8])
-                    .collect::<Vec<u8>>(),
-            )
-            .unwrap();
+            object,
+            emit,
+            outputs,
+        } => compile_robin(&source, &destination, object, &emit, &outputs),
+        SubCommand::Link {
+            destination,
+            objects,
+            base,
+            symbols,
+        } => link(&destination, &objects, base, symbols.as_deref()),
+    }
+}
+
+fn compile_asm(
+    source: &str,
+    destination: &str,
+    include_paths: &[PathBuf],
+    object: bool,
+    outputs: &Outputs,
+) {
+    let path = Path::new(source);
+    let words = if object {
+        compile_asm_object_file(path, include_paths).map(|object| object.to_words())
+    } else if outputs.wanted() {
+        compile_asm_file_listed(path, include_paths).map(|(image, labels, listing)| {
+            outputs.write(&labels, &listing);
+            image.to_words()
+        })
+    } else {
+        compile_asm_file(path, include_paths).map(|(image, _)| image.to_words())
+    };
+    write_words(
+        destination,
+        &words.unwrap_or_else(|err| report(&err, source)),
+    );
+}
+
+fn compile_robin(source: &str, destination: &str, object: bool, emit: &[Emit], outputs: &Outputs) {
+    let read_file = fs::read_to_string(source).unwrap();
+    let bin = emit.contains(&Emit::Bin);
+    let options = CompileOptions {
+        tokens: emit.contains(&Emit::Tokens),
+        ast: emit.contains(&Emit::Ast),
+        asm: emit.contains(&Emit::Asm),
+        machine_code: bin && !object,
+        symbols: outputs.wanted(),
+        listing: outputs.wanted(),
+        object: bin && object,
+    };
+    let output = robin_compile(&read_file, &options).unwrap_or_else(|err| {
+        eprintln!("{}", err.render(source, &read_file));
+        std::process::exit(1)
+    });
+    for warning in &output.warnings {
+        eprintln!("{}\n", warning.render(source, &read_file));
+    }
+    let beside = |extension| Path::new(destination).with_extension(extension);
+    if let Some(tokens) = output.tokens {
+        let mut lines = String::new();
+        for token in tokens {
+            let _ = writeln!(
+                lines,
+                "{}:{} {}",
+                token.span.line, token.span.column, token.node
+            );
+        }
+        fs::write(beside("tokens"), lines).unwrap();
+    }
+    if let Some(ast) = output.ast {
+        fs::write(beside("ast"), format!("{ast:#?}\n")).unwrap();
+    }
+    if let Some(asm) = output.asm {
+        fs::write(beside("asm"), asm).unwrap();
+    }
+    if let Some(words) = output.machine_code {
+        write_words(destination, &words);
+    }
+    if let Some(object) = output.object {
+        write_words(destination, &object.to_words());
+    }
+    if let (Some(labels), Some(listing)) = (&output.symbols, &output.listing) {
+        outputs.write(labels, listing);
+    }
+}
+
+fn read_symbols(filename: &str) -> Labels {
+    parse_symbol_map(&fs::read_to_string(filename).unwrap()).unwrap_or_else(|| {
+        eprintln!("{filename} isn't a symbol map");
+        std::process::exit(1)
+    })
+}
+
+/// link the object files `objects` at `base`, writing the program to `destination`
+fn link(destination: &str, objects: &[String], base: u16, symbols: Option<&str>) {
+    let objects: Vec<Object> = objects
+        .iter()
+        .map(|filename| {
+            Object::from_words(&read_words(filename)).unwrap_or_else(|| {
+                eprintln!("{filename} isn't an object file");
+                std::process::exit(1)
+            })
+        })
+        .collect();
+    let (image, labels) = computer::link(&objects, base).unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1)
+    });
+    if let Some(symbols) = symbols {
+        fs::write(symbols, symbol_map(&labels)).unwrap();
+    }
+    write_words(destination, &image.to_words());
+}
+
+/// print each instruction in `filename` with its address and words
+fn disasm(filename: &str) {
+    for segment in read_image(filename).segments {
+        let code = segment.words;
+        let mut idx = 0;
+        while idx < code.len() {
+            let (instruction, len) = decode(&code[idx..]);
+            let words: Vec<String> = code[idx..idx + len]
+                .iter()
+                .map(|word| format!("{word:0>4X}"))
+                .collect();
+            println!(
+                "{:0>4X}: {:<19} {instruction}",
+                usize::from(segment.origin) + idx,
+                words.join(" ")
+            );
+            idx += len;
         }
     }
 }
+
+/// print an assembler error in `filename` and exit
+fn report(err: &ASMError, filename: &str) -> ! {
+    let src = fs::read_to_string(filename).unwrap_or_default();
+    eprintln!("{}", err.render(filename, &src));
+    std::process::exit(1)
+}
+
+fn debug(filename: &str, input: Option<String>, include_paths: &[PathBuf], symbols: Option<&str>) {
+    let is_asm = Path::new(filename)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("asm"));
+    let (image, labels) = if is_asm {
+        compile_asm_file(Path::new(filename), include_paths)
+            .unwrap_or_else(|err| report(&err, filename))
+    } else {
+        (
+            read_image(filename),
+            symbols.map(read_symbols).unwrap_or_default(),
+        )
+    };
+    let reader: Box<dyn Input> = match input {
+        Some(input) => Box::new(BufReader::new(fs::File::open(input).unwrap())),
+        None => Box::new(&[][..]),
+    };
+    let mut debugger = Debugger::new(
+        ComputerIO::with_streams(CPU::new(), reader, std::io::stdout()),
+        labels,
+    );
+    image.load(debugger.computer_mut());
+    debugger
+        .repl(std::io::stdin().lock(), std::io::stdout())
+        .unwrap();
+}
@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{
+    asm::{ASMError, ErrorKind, Labels},
+    Image, Segment,
+};
+
+/// A section of an object, which the linker puts somewhere
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: Rc<str>,
+    /// the section has to start at a multiple of this many words
+    pub align: u16,
+    pub words: Vec<u16>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A word that needs the address of something adding to it once it's been placed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// where the word is in its section
+    pub offset: u16,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// the start of another section in the same object
+    Section(usize),
+    /// a symbol exported by some object
+    Symbol(Rc<str>),
+}
+
+/// A symbol other objects can use; `section` is `None` for a constant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: Rc<str>,
+    pub section: Option<usize>,
+    pub value: u16,
+}
+
+/// An assembled program that hasn't been given addresses yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub exports: Vec<Export>,
+}
+
+impl Object {
+    /// the start of an encoded object
+    pub const MAGIC: [u16; 2] = [0x4F42, 0x4A31];
+
+    /// encode the object: `MAGIC`, then the sections, each with its name, alignment, words and
+    /// relocations, then the exports. Names are a length followed by a word for each character.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_words(&self) -> Vec<u16> {
+        let mut words = Self::MAGIC.to_vec();
+        let name = |words: &mut Vec<u16>, name: &str| {
+            words.push(name.chars().count() as u16);
+            words.extend(name.chars().map(|char| char as u16));
+        };
+        words.push(self.sections.len() as u16);
+        for section in &self.sections {
+            name(&mut words, &section.name);
+            words.extend([section.align, section.words.len() as u16]);
+            words.extend(&section.words);
+            words.push(section.relocations.len() as u16);
+            for relocation in &section.relocations {
+                words.push(relocation.offset);
+                match &relocation.target {
+                    Target::Section(idx) => words.extend([0, *idx as u16]),
+                    Target::Symbol(symbol) => {
+                        words.push(1);
+                        name(&mut words, symbol);
+                    }
+                }
+            }
+        }
+        words.push(self.exports.len() as u16);
+        for export in &self.exports {
+            name(&mut words, &export.name);
+            words.extend([
+                export.section.map_or(u16::MAX, |idx| idx as u16),
+                export.value,
+            ]);
+        }
+        words
+    }
+
+    /// decode an object written by `to_words`, or `None` if it isn't one or refers to sections
+    /// or words it doesn't have
+    #[must_use]
+    pub fn from_words(words: &[u16]) -> Option<Self> {
+        let mut reader = Reader(words.strip_prefix(&Self::MAGIC[..])?);
+        let mut sections = Vec::new();
+        let count = reader.word()?;
+        for _ in 0..count {
+            let name = reader.name()?;
+            let align = reader.word()?;
+            let len = reader.word()?;
+            let words = reader.words(len)?.to_vec();
+            let mut relocations = Vec::new();
+            for _ in 0..reader.word()? {
+                let offset = reader.word().filter(|&offset| offset < len)?;
+                let target = match reader.word()? {
+                    0 => Target::Section(reader.word().filter(|&idx| idx < count)?.into()),
+                    1 => Target::Symbol(reader.name()?),
+                    _ => return None,
+                };
+                relocations.push(Relocation { offset, target });
+            }
+            sections.push(Section {
+                name,
+                align,
+                words,
+                relocations,
+            });
+        }
+        let mut exports = Vec::new();
+        for _ in 0..reader.word()? {
+            let name = reader.name()?;
+            let section = match reader.word()? {
+                u16::MAX => None,
+                idx if idx < count => Some(idx.into()),
+                _ => return None,
+            };
+            let value = reader.word()?;
+            exports.push(Export {
+                name,
+                section,
+                value,
+            });
+        }
+        Some(Self { sections, exports })
+    }
+}
+
+/// Reads an encoded object from the front
+struct Reader<'a>(&'a [u16]);
+
+impl<'a> Reader<'a> {
+    fn words(&mut self, len: u16) -> Option<&'a [u16]> {
+        let (words, rest) = self.0.split_at_checked(len.into())?;
+        self.0 = rest;
+        Some(words)
+    }
+
+    fn word(&mut self) -> Option<u16> {
+        self.words(1).map(|words| words[0])
+    }
+
+    fn name(&mut self) -> Option<Rc<str>> {
+        let len = self.word()?;
+        let name: Option<String> = self
+            .words(len)?
+            .iter()
+            .map(|&char| char::from_u32(char.into()))
+            .collect();
+        name.map(Rc::from)
+    }
+}
+
+/// put `objects` one after another from `base`, returning the program and each export's address
+///
+/// The `code` sections go first, so the program starts at the first one, then the other sections
+/// follow in the order they first appear.
+/// # Errors
+/// if `base` is a register, an export is defined twice, a symbol isn't exported by any object, or
+/// the program doesn't fit in memory
+pub fn link(objects: &[Object], base: u16) -> Result<(Image, Labels), ASMError> {
+    let error = |kind| ASMError::new(kind, None);
+    if base < 0x10 {
+        return Err(error(ErrorKind::LowOrigin(base)));
+    }
+    let mut names: Vec<&str> = vec!["code"];
+    for section in objects.iter().flat_map(|object| &object.sections) {
+        if !names.contains(&&*section.name) {
+            names.push(&section.name);
+        }
+    }
+    let mut addresses: Vec<Vec<u16>> = objects
+        .iter()
+        .map(|object| vec![0; object.sections.len()])
+        .collect();
+    let mut words = Vec::new();
+    for name in names {
+        for (object, addresses) in objects.iter().zip(&mut addresses) {
+            for (section, address) in object.sections.iter().zip(addresses) {
+                if &*section.name != name {
+                    continue;
+                }
+                let start =
+                    (usize::from(base) + words.len()).next_multiple_of(section.align.max(1).into());
+                *address = u16::try_from(start).map_err(|_| error(ErrorKind::OutOfMemory))?;
+                words.resize(start - usize::from(base), 0);
+                words.extend(&section.words);
+                if usize::from(base) + words.len() > 0x10000 {
+                    return Err(error(ErrorKind::OutOfMemory));
+                }
+            }
+        }
+    }
+
+    let mut symbols = BTreeMap::new();
+    for (object, addresses) in objects.iter().zip(&addresses) {
+        for export in &object.exports {
+            let value = export.section.map_or(export.value, |idx| {
+                addresses[idx].wrapping_add(export.value)
+            });
+            if symbols.insert(export.name.clone(), value).is_some() {
+                return Err(error(ErrorKind::DuplicateLabel(export.name.clone())));
+            }
+        }
+    }
+
+    for (object, addresses) in objects.iter().zip(&addresses) {
+        for (section, address) in object.sections.iter().zip(addresses) {
+            for relocation in &section.relocations {
+                let target = match &relocation.target {
+                    Target::Section(idx) => addresses[*idx],
+                    Target::Symbol(symbol) => *symbols
+                        .get(symbol)
+                        .ok_or_else(|| error(ErrorKind::UndefinedLabel(symbol.clone())))?,
+                };
+                let word = &mut words[usize::from(address - base) + usize::from(relocation.offset)];
+                *word = word.wrapping_add(target);
+            }
+        }
+    }
+
+    let entry = objects
+        .iter()
+        .zip(&addresses)
+        .find_map(|(object, addresses)| {
+            object
+                .sections
+                .iter()
+                .position(|section| &*section.name == "code")
+                .map(|idx| addresses[idx])
+        })
+        .unwrap_or(base);
+    let image = Image {
+        entry,
+        segments: vec![Segment {
+            origin: base,
+            words,
+        }],
+    };
+    Ok((image, symbols))
+}
@@ -1,43 +1,264 @@
-use crate::asm;
+use std::fmt::Display;
+
+use crate::{
+    asm::{self, Span},
+    Object,
+};
 
 mod compiler;
 mod lexer;
+mod lints;
 mod parser;
+mod temps;
 mod types;
 
+pub use types::{
+    AssignOp, BinaryOp, BlockType, Expression, Keyword, Spanned, Statement, Token, TopLevelSyntax,
+    UnaryOp,
+};
+
+/// How bad a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Something wrong with, or suspicious about, Robin source, and where it is if that's known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    fn error(message: impl Display, span: Option<Span>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    fn warning(message: impl Display, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.to_string(),
+            span: Some(span),
+        }
+    }
+
+    /// show the diagnostic with the offending source underlined, like rustc does; `src` is the
+    /// source that was compiled, named `file`
+    #[must_use]
+    pub fn render(&self, file: &str, src: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        asm::render(
+            &format!("{severity}: {}", self.message),
+            self.span,
+            file,
+            src,
+        )
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(span) = self.span {
+            write!(f, "{}:{}: ", span.line, span.column)?;
+        }
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Which artifacts `compile` makes, besides the warnings it always returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CompileOptions {
+    pub tokens: bool,
+    pub ast: bool,
+    /// the assembly the program compiles to
+    pub asm: bool,
+    /// the program assembled to load at `0x8000`
+    pub machine_code: bool,
+    /// the address of each label in the machine code
+    pub symbols: bool,
+    /// the address and words of each statement in the machine code
+    pub listing: bool,
+    /// the program assembled for `link` to place
+    pub object: bool,
+}
+
+impl CompileOptions {
+    /// every artifact
+    #[must_use]
+    pub const fn all() -> Self {
+        Self {
+            tokens: true,
+            ast: true,
+            asm: true,
+            machine_code: true,
+            symbols: true,
+            listing: true,
+            object: true,
+        }
+    }
+}
+
+/// What `compile` made: each artifact `CompileOptions` asked for, and any warnings
+#[derive(Debug, Clone, Default)]
+pub struct CompileOutput {
+    pub tokens: Option<Vec<Spanned<Token>>>,
+    pub ast: Option<Vec<Spanned<TopLevelSyntax>>>,
+    pub asm: Option<String>,
+    pub machine_code: Option<Vec<u16>>,
+    pub symbols: Option<asm::Labels>,
+    pub listing: Option<asm::Listing>,
+    pub object: Option<Object>,
+    pub warnings: Vec<Diagnostic>,
+}
+
 #[derive(Debug)]
 pub enum Error {
-    Parser(parser::ParseError),
-    Lexer(lexer::LexError),
-    Compiler(compiler::Error),
+    /// everything wrong with the Robin source, and any warnings, in the order they're in the source
+    Robin(Vec<Diagnostic>),
+    Asm(asm::ASMError),
+}
+
+impl Error {
+    /// show every diagnostic like `Diagnostic::render` does
+    #[must_use]
+    pub fn render(&self, file: &str, src: &str) -> String {
+        match self {
+            Self::Robin(diagnostics) => diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.render(file, src))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            Self::Asm(err) => err.render(file, src),
+        }
+    }
 }
 
-impl From<parser::ParseError> for Error {
-    fn from(value: parser::ParseError) -> Self {
-        Self::Parser(value)
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Robin(diagnostics) => {
+                let lines: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            Self::Asm(err) => write!(f, "{err}"),
+        }
     }
 }
 
-impl From<lexer::LexError> for Error {
-    fn from(value: lexer::LexError) -> Self {
-        Self::Lexer(value)
+impl From<Vec<Diagnostic>> for Error {
+    fn from(value: Vec<Diagnostic>) -> Self {
+        Self::Robin(value)
     }
 }
 
-impl From<compiler::Error> for Error {
-    fn from(value: compiler::Error) -> Self {
-        Self::Compiler(value)
+impl From<asm::ASMError> for Error {
+    fn from(value: asm::ASMError) -> Self {
+        Self::Asm(value)
     }
 }
 
 /// # Errors
 /// If parsing, lexing, or compiling Robin fails
 pub fn pipe(src: &str) -> Result<Vec<u16>, Error> {
-    let syntax = parser::parse(lexer::lex(src)?).map_err(Error::from)?;
-    println!("{syntax:?}");
-    let syntax = compiler::compile(syntax).map_err(Error::from)?;
-    for line in &syntax {
-        println!("{line}");
+    Ok(asm::interpret_syntax(front_end(src)?.0)?)
+}
+
+/// like `pipe`, but also return the address of each label and a listing of the program
+/// # Errors
+/// If parsing, lexing, or compiling Robin fails
+pub fn pipe_listed(src: &str) -> Result<(Vec<u16>, asm::Labels, asm::Listing), Error> {
+    Ok(asm::interpret_syntax_listed(front_end(src)?.0)?)
+}
+
+/// like `pipe`, but leave placing the program to the linker
+/// # Errors
+/// If parsing, lexing, or compiling Robin fails
+pub fn pipe_object(src: &str) -> Result<Object, Error> {
+    Ok(asm::interpret_syntax_object(front_end(src)?.0)?)
+}
+
+/// every error and warning in `src`, in the order they're in the source, without assembling it
+#[must_use]
+pub fn check(src: &str) -> Vec<Diagnostic> {
+    match front_end(src) {
+        Ok((_, warnings)) => warnings,
+        Err(diagnostics) => diagnostics,
     }
-    Ok(asm::interpret_syntax(syntax))
+}
+
+/// compile `src`, making the artifacts `options` asks for
+/// # Errors
+/// every error and warning, if lexing, parsing or compiling Robin fails, or the assembler's error
+pub fn compile(src: &str, options: &CompileOptions) -> Result<CompileOutput, Error> {
+    let mut output = CompileOutput::default();
+    let (tokens, spans) = lexer::lex(src)?;
+    if options.tokens {
+        let tokens = tokens.iter().cloned().zip(&spans);
+        output.tokens = Some(tokens.map(|(node, &span)| Spanned { node, span }).collect());
+    }
+    let ast = parser::parse(tokens, spans)?;
+    if options.ast {
+        output.ast = Some(ast.clone());
+    }
+    let (syntax, warnings) = lower(ast)?;
+    output.warnings = warnings;
+    if options.asm {
+        let lines: Vec<String> = syntax.iter().map(ToString::to_string).collect();
+        output.asm = Some(lines.join("\n") + "\n");
+    }
+    if options.object {
+        output.object = Some(asm::interpret_syntax_object(syntax.clone())?);
+    }
+    if options.machine_code || options.symbols || options.listing {
+        let (machine_code, symbols, listing) = asm::interpret_syntax_listed(syntax)?;
+        output.machine_code = options.machine_code.then_some(machine_code);
+        output.symbols = options.symbols.then_some(symbols);
+        output.listing = options.listing.then_some(listing);
+    }
+    Ok(output)
+}
+
+/// compile `src` to assembly, with any warnings, or return every error and warning
+fn front_end(src: &str) -> Result<(Vec<asm::Syntax>, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let (tokens, spans) = lexer::lex(src)?;
+    lower(parser::parse(tokens, spans)?)
+}
+
+/// compile a parsed program to assembly, with any warnings, or return every error and warning
+fn lower(
+    syntax: Vec<Spanned<TopLevelSyntax>>,
+) -> Result<(Vec<asm::Syntax>, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let mut warnings = lints::lint(&syntax);
+    sort(&mut warnings);
+    match compiler::compile(syntax) {
+        Ok(syntax) => Ok((syntax, warnings)),
+        Err(mut errors) => {
+            errors.extend(warnings);
+            sort(&mut errors);
+            Err(errors)
+        }
+    }
+}
+
+/// put `diagnostics` in the order they're in the source, with ones that aren't anywhere last
+fn sort(diagnostics: &mut [Diagnostic]) {
+    diagnostics.sort_by_key(|diagnostic| {
+        diagnostic
+            .span
+            .map_or((usize::MAX, 0), |span| (span.line, span.column))
+    });
 }
@@ -1,34 +1,135 @@
-use std::{collections::BTreeMap, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, fmt::Display, rc::Rc};
 
 use crate::{
-    asm::{Instruction, Item, Syntax, Value},
+    asm::{stack, Instruction, Item, MathOp, Syntax, Value},
     robin::types::UnaryOp,
     utils::{get_hash, Either},
+    CPU,
 };
 
-use super::types::{AssignOp, BinaryOp, BlockType, Expression, Statement, TopLevelSyntax};
+use super::{
+    temps::{Operand, Temps},
+    types::{AssignOp, BinaryOp, BlockType, Expression, Spanned, Statement, TopLevelSyntax},
+    Diagnostic,
+};
+
+/// the register holding the address of the current function's stack frame
+const FRAME_POINTER: u16 = 0xD;
+/// the register the address of a variable in a stack frame is worked out in, to write to it
+const ADDRESS_REGISTER: u16 = 0xC;
+/// the register a variable in a stack frame is read into, to update it
+const UPDATE_REGISTER: u16 = 0xB;
+/// the register functions return their result in
+const RETURN_REGISTER: u16 = 0x1;
+
+/// Where a variable is kept
+#[derive(Debug, Clone)]
+enum Variable {
+    /// a word at a fixed address, like a global
+    Static(Value),
+    /// a word in the current function's stack frame, this far from the frame pointer
+    Frame(u16),
+}
+
+impl Variable {
+    /// the code to work out the variable's address, and where the address is
+    fn address(&self, register: u16) -> (Vec<Either<Syntax, Statement>>, Item) {
+        match self {
+            Self::Static(value) => (Vec::new(), Item::Literal(value.clone())),
+            Self::Frame(offset) => (
+                vec![Either::Left(Syntax::Instruction(Instruction::MathTernary(
+                    MathOp::Add,
+                    Item::Literal(Value::Given(*offset)),
+                    Item::Address(Value::Given(FRAME_POINTER)),
+                    Value::Given(register),
+                )))],
+                Item::Address(Value::Given(register)),
+            ),
+        }
+    }
+
+    /// the code to read the variable, and where its value is
+    fn load(&self, register: u16) -> (Vec<Either<Syntax, Statement>>, Value) {
+        match self.address(register) {
+            (code, Item::Literal(address)) => (code, address),
+            (mut code, Item::Address(address)) => {
+                code.push(Either::Left(Syntax::Instruction(Instruction::Ptrread(
+                    address,
+                    Value::Given(register),
+                ))));
+                (code, Value::Given(register))
+            }
+        }
+    }
+
+    /// the code to set the variable to `src`
+    fn store(&self, src: Item) -> Vec<Either<Syntax, Statement>> {
+        match self.address(ADDRESS_REGISTER) {
+            (mut code, Item::Literal(address)) => {
+                code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+                    src, address,
+                ))));
+                code
+            }
+            (mut code, Item::Address(address)) => {
+                code.push(Either::Left(Syntax::Instruction(Instruction::Ptrwrite(
+                    src, address,
+                ))));
+                code
+            }
+        }
+    }
+
+    /// the code to apply `math_op` to the variable and `src`, keeping the result in the variable
+    fn update(&self, math_op: MathOp, src: Item) -> Vec<Either<Syntax, Statement>> {
+        match self {
+            Self::Static(value) => vec![Either::Left(Syntax::Instruction(
+                Instruction::MathBinary(math_op, src, value.clone()),
+            ))],
+            Self::Frame(_) => {
+                let (mut code, value) = self.load(UPDATE_REGISTER);
+                code.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
+                    math_op,
+                    src,
+                    value.clone(),
+                ))));
+                code.extend(self.store(Item::Address(value)));
+                code
+            }
+        }
+    }
+}
+
+/// The labels `continue` and `break` jump to in the loop they're in
+#[derive(Debug)]
+struct Loop {
+    next: Rc<str>,
+    end: Rc<str>,
+}
 
 struct Scope<'a> {
     constants: &'a BTreeMap<Rc<str>, Value>,
     globals: &'a BTreeMap<Rc<str>, Value>,
     functions: &'a BTreeMap<Rc<str>, Vec<Rc<str>>>,
-    parameters: &'a BTreeMap<Rc<str>, Value>,
-    locals: &'a BTreeMap<Rc<str>, Value>,
+    parameters: &'a BTreeMap<Rc<str>, Variable>,
+    locals: &'a BTreeMap<Rc<str>, Variable>,
+    /// the errors in statements so far, which are skipped so the rest can be checked
+    errors: &'a RefCell<Vec<Diagnostic>>,
 }
 
-impl<'a> Scope<'a> {
-    pub fn get(&self, ident: &str) -> Option<Value> {
+impl Scope<'_> {
+    pub fn get(&self, ident: &str) -> Option<Variable> {
         if let Some(local) = self.locals.get(ident) {
             return Some(local.clone());
-        };
+        }
         if let Some(param) = self.parameters.get(ident) {
             return Some(param.clone());
-        };
+        }
         if let Some(global) = self.globals.get(ident) {
-            return Some(global.clone());
-        };
+            return Some(Variable::Static(global.clone()));
+        }
         if self.functions.get(ident).is_some() {
-            return Some(Value::Label(ident.into()));
+            return Some(Variable::Static(Value::Label(ident.into())));
         }
         None
     }
@@ -47,21 +148,42 @@ pub enum Error {
     CompilationFailed(String),
     InvalidIdentifier(Rc<str>),
     InvalidSyntax(TopLevelSyntax),
-    InvalidStatement(Statement),
-    InvalidExpression(Expression),
 }
 
-pub fn compile(src: Vec<TopLevelSyntax>) -> Result<Vec<Syntax>, Error> {
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CompilationFailed(reason) => write!(f, "{reason}"),
+            Self::InvalidIdentifier(ident) => write!(f, "`{ident}` isn't defined"),
+            Self::InvalidSyntax(TopLevelSyntax::Constant(name, _)) => {
+                write!(f, "constant `{name}` has to be a number")
+            }
+            Self::InvalidSyntax(TopLevelSyntax::Global(name, _)) => {
+                write!(f, "global `{name}` has to be a string or an array")
+            }
+            Self::InvalidSyntax(TopLevelSyntax::Function(name, ..)) => {
+                write!(f, "function `{name}` can't be compiled")
+            }
+        }
+    }
+}
+
+/// compile a program to assembly, carrying on after an error at the next statement or function
+/// # Errors
+/// every error there is, where it's known
+pub fn compile(src: Vec<Spanned<TopLevelSyntax>>) -> Result<Vec<Syntax>, Vec<Diagnostic>> {
+    let errors = RefCell::new(Vec::new());
+    let error = |err: Error, span| errors.borrow_mut().push(Diagnostic::error(err, span));
     let mut function_signatures = BTreeMap::new();
     let mut function_bodies = BTreeMap::new();
     let mut statics = BTreeMap::new();
     let mut constants = BTreeMap::new();
     let mut statics_syntax = Vec::new();
-    for syn in src {
+    for Spanned { node: syn, span } in src {
         match syn {
             TopLevelSyntax::Function(name, args, body) => {
                 function_signatures.insert(name.clone(), args.clone());
-                function_bodies.insert(name, (args, body));
+                function_bodies.insert(name, (args, body, span));
             }
             TopLevelSyntax::Global(name, Expression::String(str)) => {
                 let label: Rc<str> = format!("_global_{name}").into();
@@ -79,25 +201,30 @@ pub fn compile(src: Vec<TopLevelSyntax>) -> Result<Vec<Syntax>, Error> {
             TopLevelSyntax::Constant(name, Expression::Int(int)) => {
                 constants.insert(name, Value::Given(int));
             }
-            _ => return Err(Error::InvalidSyntax(syn)),
+            _ => error(Error::InvalidSyntax(syn), Some(span)),
         }
     }
     let mut output = Vec::new();
-    let Some((main_args, main_body)) = function_bodies.remove("main") else {return Err(Error::CompilationFailed(String::from("Missing `main` function")))};
-    if !main_args.is_empty() {
-        return Err(Error::CompilationFailed(String::from(
-            "Main function can't accept arguments",
-        )));
+    match function_bodies.remove("main") {
+        Some((main_args, _, span)) if !main_args.is_empty() => error(
+            Error::CompilationFailed(String::from("`main` can't take arguments")),
+            Some(span),
+        ),
+        Some((main_args, main_body, _)) => output.extend(compile_fn(
+            &"main".into(),
+            &main_args,
+            main_body,
+            &statics,
+            &constants,
+            &function_signatures,
+            &errors,
+        )),
+        None => error(
+            Error::CompilationFailed(String::from("there's no `main` function")),
+            None,
+        ),
     }
-    output.extend(compile_fn(
-        &"main".into(),
-        &main_args,
-        main_body,
-        &statics,
-        &constants,
-        &function_signatures,
-    )?);
-    for (name, (args, body)) in function_bodies {
+    for (name, (args, body, _)) in function_bodies {
         output.extend(compile_fn(
             &name,
             &args,
@@ -105,80 +232,147 @@ pub fn compile(src: Vec<TopLevelSyntax>) -> Result<Vec<Syntax>, Error> {
             &statics,
             &constants,
             &function_signatures,
-        )?);
+            &errors,
+        ));
+    }
+    let errors = errors.into_inner();
+    if !errors.is_empty() {
+        return Err(errors);
     }
-    println!("{output:?}");
     let mut output = output
         .into_iter()
         .map(Either::left)
         .collect::<Option<Vec<_>>>()
-        .ok_or(Error::CompilationFailed(String::from(
-            "Not all statements were parsed",
-        )))?;
+        .ok_or_else(|| {
+            vec![Diagnostic::error(
+                Error::CompilationFailed(String::from("Not all statements were parsed")),
+                None,
+            )]
+        })?;
     output.extend(statics_syntax);
     Ok(output)
 }
 
-#[allow(clippy::unnecessary_wraps)]
+#[allow(clippy::cast_possible_truncation)]
 fn compile_fn(
     name: &Rc<str>,
     args: &[Rc<str>],
-    body: Vec<Statement>,
+    body: Vec<Spanned<Statement>>,
     statics: &BTreeMap<Rc<str>, Value>,
     constants: &BTreeMap<Rc<str>, Value>,
     function_signatures: &BTreeMap<Rc<str>, Vec<Rc<str>>>,
-) -> Result<Vec<Either<Syntax, Statement>>, Error> {
+    errors: &RefCell<Vec<Diagnostic>>,
+) -> Vec<Either<Syntax, Statement>> {
     let mut out = Vec::new();
-    let mut args_map = BTreeMap::new();
-    for arg in args {
-        let arg_label: Rc<str> = format!("_fn_{name}_arg_{arg}").into();
-        out.push(Either::Left(Syntax::Label(arg_label.clone())));
-        args_map.insert(arg.clone(), Value::Label(arg_label));
-        out.push(Either::Left(Syntax::Reserve(1)));
-    }
+    // the arguments are pushed in order, then the return address, then the caller's frame pointer,
+    // which the frame pointer points at
+    let args_map = args
+        .iter()
+        .enumerate()
+        .map(|(idx, arg)| (arg.clone(), Variable::Frame((args.len() - idx + 1) as u16)))
+        .collect();
     let mut locals = BTreeMap::new();
-    let mut locals_initial = BTreeMap::new();
-    for statement in &body {
-        if let Statement::Declaration(var, value) = statement {
-            locals.insert(
-                var.clone(),
-                Value::Label(format!("_fn_{name}_local_{var}").into()),
-            );
-            locals_initial.insert(var.clone(), value.clone());
-        }
-    }
+    declare_locals(&body, &mut locals);
     let scope = Scope {
         globals: statics,
         parameters: &args_map,
         locals: &locals,
         functions: function_signatures,
         constants,
+        errors,
     };
     out.push(Either::Left(Syntax::Label(format!("_fn_{name}").into())));
-    let mut rolling_hash = get_hash(&body);
+    out.extend(
+        stack::push(Item::Address(Value::Given(FRAME_POINTER)))
+            .into_iter()
+            .map(Either::Left),
+    );
+    out.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+        Item::Address(Value::Given(stack::STACK_POINTER)),
+        Value::Given(FRAME_POINTER),
+    ))));
+    if !locals.is_empty() {
+        out.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
+            MathOp::Sub,
+            Item::Literal(Value::Given(locals.len() as u16)),
+            Value::Given(stack::STACK_POINTER),
+        ))));
+    }
+    let hash = get_hash((name, &body));
+    out.extend(compile_body(body, &scope, name, None, hash));
+    out.extend(epilogue(name));
+    out
+}
+
+/// give each variable declared in `body`, or in a block in it, a word below the frame pointer
+fn declare_locals(body: &[Spanned<Statement>], locals: &mut BTreeMap<Rc<str>, Variable>) {
+    for statement in body {
+        match &statement.node {
+            Statement::Declaration(var, _) if !locals.contains_key(var) => {
+                let offset = u16::try_from(locals.len() + 1).unwrap_or(u16::MAX);
+                locals.insert(var.clone(), Variable::Frame(offset.wrapping_neg()));
+            }
+            Statement::Block(_, _, body) | Statement::Loop(body) => declare_locals(body, locals),
+            Statement::IfElse(_, body, otherwise) => {
+                declare_locals(body, locals);
+                declare_locals(otherwise, locals);
+            }
+            Statement::For(init, _, step, body) => {
+                declare_locals(std::slice::from_ref(&**init), locals);
+                declare_locals(body, locals);
+                declare_locals(std::slice::from_ref(&**step), locals);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// the code to leave `func`, putting the stack back how the caller left it. Nothing calls `main`,
+/// so leaving it ends the program.
+fn epilogue(func: &str) -> Vec<Either<Syntax, Statement>> {
+    if func == "main" {
+        return vec![Either::Left(Syntax::Instruction(Instruction::Halt))];
+    }
+    let mut code = vec![Syntax::Instruction(Instruction::Mov(
+        Item::Address(Value::Given(FRAME_POINTER)),
+        Value::Given(stack::STACK_POINTER),
+    ))];
+    code.extend(stack::pop(Value::Given(FRAME_POINTER)));
+    code.extend(stack::ret());
+    code.into_iter().map(Either::Left).collect()
+}
+
+/// the code for each statement of `body` in turn, leaving out any that fail after keeping their
+/// errors in `scope`
+fn compile_body(
+    body: Vec<Spanned<Statement>>,
+    scope: &Scope,
+    func: &str,
+    innermost: Option<&Loop>,
+    hash: u64,
+) -> Vec<Either<Syntax, Statement>> {
+    let mut code = Vec::new();
+    // each statement's labels are named after a hash of it and everything before it in the block,
+    // so they're different even if the statements are the same
+    let mut rolling_hash = hash;
     for stmt in body {
         rolling_hash = get_hash((&stmt, rolling_hash));
-        out.extend(compile_statement(stmt, &scope, name, rolling_hash)?);
-    }
-    out.push(Either::Left(Syntax::Literal(0x0E40)));
-    out.push(Either::Left(Syntax::Label(
-        format!("_fn_{name}_ret_to").into(),
-    )));
-    out.push(Either::Left(Syntax::Literal(0xFFFF)));
-    for (local, initial) in locals_initial {
-        out.push(Either::Left(Syntax::Label(
-            format!("_fn_{name}_local_{local}").into(),
-        )));
-        match initial {
-            Some(Expression::Int(int)) => out.push(Either::Left(Syntax::Literal(int))),
-            _ => out.push(Either::Left(Syntax::Reserve(1))),
+        match compile_statement(stmt.node, scope, func, innermost, rolling_hash) {
+            Ok(statement) => code.extend(statement),
+            Err(err) => scope
+                .errors
+                .borrow_mut()
+                .push(Diagnostic::error(err, Some(stmt.span))),
         }
     }
-    out.push(Either::Left(Syntax::Label(
-        format!("_fn_{name}_ret").into(),
-    )));
-    out.push(Either::Left(Syntax::Reserve(1)));
-    Ok(out)
+    code
+}
+
+/// a jump to `label`
+const fn jump(label: Rc<str>) -> Either<Syntax, Statement> {
+    Either::Left(Syntax::Instruction(Instruction::Jmp(Item::Literal(
+        Value::Label(label),
+    ))))
 }
 
 #[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
@@ -186,27 +380,44 @@ fn compile_statement(
     stmt: Statement,
     scope: &Scope,
     func: &str,
+    innermost: Option<&Loop>,
     hash: u64,
 ) -> Result<Vec<Either<Syntax, Statement>>, Error> {
     match stmt {
         Statement::FunctionCall(name, args) if &*name == "yield" && args.is_empty() => {
             Ok(vec![Either::Left(Syntax::Instruction(Instruction::Yield))])
         }
+        Statement::FunctionCall(name, args) if &*name == "halt" && args.len() <= 1 => {
+            let mut code = Vec::new();
+            if let Some(exit_code) = args.into_iter().next() {
+                let (syn, value) = item_from(exit_code, scope, hash)?;
+                code.extend(syn);
+                code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+                    value,
+                    Value::Given(CPU::EXIT_CODE_REGISTER),
+                ))));
+            }
+            code.push(Either::Left(Syntax::Instruction(Instruction::Halt)));
+            Ok(code)
+        }
         Statement::FunctionCall(func, args) => {
-            compile_fn_call(func, args, scope, hash).map(|vec| vec.0)
+            compile_fn_call(func, args, scope, &mut Temps::default(), hash).map(|vec| vec.0)
         }
         Statement::Assignment(lhs, AssignOp::Eq, rhs) => {
-            let (mut code, src) = value_from(rhs, scope, 1, hash)?;
-            let Some(dst) = scope.get(&lhs) else { return Err(Error::InvalidIdentifier(lhs))};
-            code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
-                src, dst,
-            ))));
-            Ok(code.into_iter().collect())
+            let (mut code, src) = item_from(rhs, scope, hash)?;
+            let Some(dst) = scope.get(&lhs) else {
+                return Err(Error::InvalidIdentifier(lhs));
+            };
+            code.extend(dst.store(src));
+            Ok(code)
         }
         Statement::StarAssignment(lhs, rhs) => {
-            let (mut code, dst) = value_from(lhs, scope, 1, hash)?;
-            let (rest, src) = value_from(rhs, scope, 5, hash)?;
+            let mut temps = Temps::default();
+            let (mut code, dst) = expression(lhs, scope, &mut temps, hash)?;
+            let (rest, src) = expression(rhs, scope, &mut temps, hash)?;
             code.extend(rest);
+            let dst = temps.item(dst, &mut code);
+            let src = temps.item(src, &mut code);
             match (src, dst) {
                 (src, Item::Address(dst)) => {
                     code.push(Either::Left(Syntax::Instruction(Instruction::Ptrwrite(
@@ -227,82 +438,166 @@ fn compile_statement(
         }
         Statement::Assignment(lhs, op, rhs) if crate::asm::MathOp::try_from(op).is_ok() => {
             let math_op = crate::asm::MathOp::try_from(op).unwrap();
-            let (mut code, src) = value_from(rhs, scope, 1, hash)?;
-            let Some(dst) = scope.get(&lhs) else { return Err(Error::InvalidIdentifier(lhs)) };
-            code.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
-                math_op, src, dst,
-            ))));
+            let (mut code, src) = item_from(rhs, scope, hash)?;
+            let Some(dst) = scope.get(&lhs) else {
+                return Err(Error::InvalidIdentifier(lhs));
+            };
+            code.extend(dst.update(math_op, src));
             Ok(code)
         }
-        Statement::Block(block_type, condition, body) => {
-            let hash: Rc<str> = format!(
-                "_{}_{:x}",
-                block_type.as_ref(),
-                get_hash((&condition, &body))
-            )
-            .into();
-            let tail_hash: Rc<str> = format!("{hash}_tail").into();
-            let mut output = Vec::new();
-            let jcmp_hash = get_hash((&condition, &hash));
-            if block_type == BlockType::While {
-                output.push(Either::Left(Syntax::Instruction(Instruction::Jmp(
-                    Item::Literal(Value::Label(tail_hash.clone())),
-                ))));
-            } else if block_type == BlockType::If {
-                // !JMP #tail
-                output.extend(compile_jcmp(
-                    Expression::UnaryOp(UnaryOp::Not, Box::new(condition.clone())),
-                    Item::Literal(Value::Label(tail_hash.clone())),
-                    scope,
-                    jcmp_hash,
-                )?);
-            }
-            output.push(Either::Left(Syntax::Label(hash.clone())));
-            for stmt in body {
-                let hash = get_hash((&hash, &stmt));
-                output.extend(compile_statement(stmt, scope, func, hash)?);
-            }
-            output.push(Either::Left(Syntax::Label(tail_hash)));
-            if block_type == BlockType::While {
-                output.extend(compile_jcmp(
-                    condition,
-                    Item::Literal(Value::Label(hash)),
-                    scope,
-                    jcmp_hash,
-                )?);
-            }
+        Statement::Block(BlockType::While, condition, body) => {
+            let label: Rc<str> = format!("_while_{hash:x}").into();
+            let tail: Rc<str> = format!("{label}_tail").into();
+            let end: Rc<str> = format!("{label}_end").into();
+            let this_loop = Loop {
+                next: tail.clone(),
+                end: end.clone(),
+            };
+            let mut output = vec![
+                jump(tail.clone()),
+                Either::Left(Syntax::Label(label.clone())),
+            ];
+            let body_hash = get_hash(&label);
+            output.extend(compile_body(body, scope, func, Some(&this_loop), body_hash));
+            output.push(Either::Left(Syntax::Label(tail)));
+            let jcmp_hash = get_hash((&condition, hash));
+            output.extend(compile_jcmp(
+                condition,
+                Item::Literal(Value::Label(label)),
+                scope,
+                jcmp_hash,
+            )?);
+            output.push(Either::Left(Syntax::Label(end)));
             Ok(output)
         }
-        Statement::Declaration(var, expr) => {
-            if expr.is_none()
-                || expr
-                    .as_ref()
-                    .is_some_and(|expr| try_as_const(expr.clone(), scope).is_some())
-            {
-                return Ok(Vec::new());
+        Statement::Block(BlockType::If, condition, body) => compile_statement(
+            Statement::IfElse(condition, body, Vec::new()),
+            scope,
+            func,
+            innermost,
+            hash,
+        ),
+        Statement::IfElse(condition, body, otherwise) => {
+            let label: Rc<str> = format!("_if_{hash:x}").into();
+            let else_label: Rc<str> = format!("{label}_else").into();
+            let end: Rc<str> = format!("{label}_end").into();
+            let jcmp_hash = get_hash((&condition, hash));
+            // !JMP #else
+            let mut output = compile_jcmp(
+                Expression::UnaryOp(UnaryOp::Not, Box::new(condition)),
+                Item::Literal(Value::Label(else_label.clone())),
+                scope,
+                jcmp_hash,
+            )?;
+            let body_hash = get_hash(&label);
+            output.extend(compile_body(body, scope, func, innermost, body_hash));
+            if !otherwise.is_empty() {
+                output.push(jump(end.clone()));
             }
-            let (mut code, value) = value_from(expr.unwrap(), scope, 1, hash)?;
-            code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
-                value,
-                scope.get(&var).unwrap(),
-            ))));
+            output.push(Either::Left(Syntax::Label(else_label.clone())));
+            let else_hash = get_hash(&else_label);
+            output.extend(compile_body(otherwise, scope, func, innermost, else_hash));
+            output.push(Either::Left(Syntax::Label(end)));
+            Ok(output)
+        }
+        Statement::For(init, condition, step, body) => {
+            let label: Rc<str> = format!("_for_{hash:x}").into();
+            let step_label: Rc<str> = format!("{label}_step").into();
+            let tail: Rc<str> = format!("{label}_tail").into();
+            let end: Rc<str> = format!("{label}_end").into();
+            let this_loop = Loop {
+                next: step_label.clone(),
+                end: end.clone(),
+            };
+            let mut output =
+                compile_statement(init.node, scope, func, innermost, get_hash((hash, 0)))?;
+            output.push(jump(tail.clone()));
+            output.push(Either::Left(Syntax::Label(label.clone())));
+            let body_hash = get_hash(&label);
+            output.extend(compile_body(body, scope, func, Some(&this_loop), body_hash));
+            output.push(Either::Left(Syntax::Label(step_label)));
+            output.extend(compile_statement(
+                step.node,
+                scope,
+                func,
+                innermost,
+                get_hash((hash, 1)),
+            )?);
+            output.push(Either::Left(Syntax::Label(tail)));
+            let jcmp_hash = get_hash((&condition, hash));
+            output.extend(compile_jcmp(
+                condition,
+                Item::Literal(Value::Label(label)),
+                scope,
+                jcmp_hash,
+            )?);
+            output.push(Either::Left(Syntax::Label(end)));
+            Ok(output)
+        }
+        Statement::Loop(body) => {
+            let label: Rc<str> = format!("_loop_{hash:x}").into();
+            let end: Rc<str> = format!("{label}_end").into();
+            let this_loop = Loop {
+                next: label.clone(),
+                end: end.clone(),
+            };
+            let mut output = vec![Either::Left(Syntax::Label(label.clone()))];
+            let body_hash = get_hash(&label);
+            output.extend(compile_body(body, scope, func, Some(&this_loop), body_hash));
+            output.push(jump(label));
+            output.push(Either::Left(Syntax::Label(end)));
+            Ok(output)
+        }
+        Statement::Break | Statement::Continue => {
+            let is_break = matches!(stmt, Statement::Break);
+            let keyword = if is_break { "break" } else { "continue" };
+            let Some(innermost) = innermost else {
+                return Err(Error::CompilationFailed(format!(
+                    "`{keyword}` outside of a loop"
+                )));
+            };
+            let target = if is_break {
+                &innermost.end
+            } else {
+                &innermost.next
+            };
+            Ok(vec![jump(target.clone())])
+        }
+        Statement::Declaration(_, None) => Ok(Vec::new()),
+        Statement::Declaration(var, Some(expr)) => {
+            let (mut code, value) = match try_as_const(expr.clone(), scope) {
+                Some(value) => (Vec::new(), Item::Literal(value)),
+                None => item_from(expr, scope, hash)?,
+            };
+            code.extend(scope.get(&var).unwrap().store(value));
             Ok(code)
         }
-        Statement::Return(Some(value)) => {
-            let (mut code, item) = value_from(value, scope, 1, hash)?;
-            code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
-                item,
-                Value::Label(format!("_fn_{func}_ret").into()),
-            ))));
-            code.push(Either::Left(Syntax::Instruction(Instruction::Jmp(
-                Item::Address(Value::Label(format!("_fn_{func}_ret_to").into())),
-            ))));
+        Statement::Return(value) => {
+            let mut code = Vec::new();
+            // returning from `main` ends the program, with the value as its exit code
+            let is_main = func == "main";
+            if let Some(value) = value {
+                let (syn, item) = item_from(value, scope, hash)?;
+                code.extend(syn);
+                let dst = Value::Given(if is_main {
+                    CPU::EXIT_CODE_REGISTER
+                } else {
+                    RETURN_REGISTER
+                });
+                if item != Item::Address(dst.clone()) {
+                    code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+                        item, dst,
+                    ))));
+                }
+            }
+            code.extend(epilogue(func));
             Ok(code)
         }
-        other => Ok(vec![Either::Right(other)]),
+        other @ Statement::Assignment(..) => Ok(vec![Either::Right(other)]),
     }
 }
 
+#[allow(clippy::too_many_lines)]
 fn compile_jcmp(
     cond: Expression,
     jmp: Item,
@@ -310,60 +605,71 @@ fn compile_jcmp(
     hash: u64,
 ) -> Result<Vec<Either<Syntax, Statement>>, Error> {
     match cond {
-        Expression::UnaryOp(UnaryOp::Not, inner) if matches!(&*inner, Expression::BinaryOp(..)) => {
-            let Expression::BinaryOp(lhs, op, rhs) = *inner else { unreachable!() };
-            match op {
-                BinaryOp::And => compile_jcmp(
-                    Expression::BinaryOp(
-                        Box::new(Expression::UnaryOp(UnaryOp::Not, lhs)),
-                        BinaryOp::Or,
-                        Box::new(Expression::UnaryOp(UnaryOp::Not, rhs)),
-                    ),
-                    jmp,
-                    scope,
-                    hash,
+        Expression::UnaryOp(UnaryOp::Not, inner)
+            if matches!(
+                &*inner,
+                Expression::BinaryOp(_, BinaryOp::And | BinaryOp::Or, _)
+            ) =>
+        {
+            let Expression::BinaryOp(lhs, op, rhs) = *inner else {
+                unreachable!()
+            };
+            let op = if op == BinaryOp::And {
+                BinaryOp::Or
+            } else {
+                BinaryOp::And
+            };
+            compile_jcmp(
+                Expression::BinaryOp(
+                    Box::new(Expression::UnaryOp(UnaryOp::Not, lhs)),
+                    op,
+                    Box::new(Expression::UnaryOp(UnaryOp::Not, rhs)),
                 ),
-                op => {
-                    let cmp_op = crate::asm::CmpOp::try_from(op).map_err(|_| {
-                        Error::CompilationFailed(format!(
-                            "Couldn't turn `{op:?}` into a comparison"
-                        ))
-                    })?;
-                    compile_jcmp(
-                        Expression::BinaryOp(lhs, cmp_op.inverse().into(), rhs),
-                        jmp,
-                        scope,
-                        hash,
-                    )
-                }
-            }
+                jmp,
+                scope,
+                hash,
+            )
+        }
+        Expression::UnaryOp(UnaryOp::Not, inner) if matches!(&*inner, Expression::BinaryOp(_, op, _) if crate::asm::CmpOp::try_from(*op).is_ok()) =>
+        {
+            let Expression::BinaryOp(lhs, op, rhs) = *inner else {
+                unreachable!()
+            };
+            let cmp_op = crate::asm::CmpOp::try_from(op).unwrap();
+            compile_jcmp(
+                Expression::BinaryOp(lhs, cmp_op.inverse().into(), rhs),
+                jmp,
+                scope,
+                hash,
+            )
         }
         Expression::UnaryOp(UnaryOp::Not, inner)
             if matches!(&*inner, Expression::UnaryOp(UnaryOp::Not, _)) =>
         {
-            let Expression::UnaryOp(UnaryOp::Not, inner) = *inner else { unreachable!() };
+            let Expression::UnaryOp(UnaryOp::Not, inner) = *inner else {
+                unreachable!()
+            };
             compile_jcmp(*inner, jmp, scope, hash)
         }
-        Expression::BinaryOp(lhs, op, rhs)
-            if crate::asm::CmpOp::try_from(op).is_ok()
-                && value_from(*lhs.clone(), scope, 1, hash).is_ok()
-                && value_from(*rhs.clone(), scope, 3, hash).is_ok() =>
-        {
-            let lhs = value_from(*lhs, scope, 1, hash)?;
-            let rhs = value_from(*rhs, scope, 3, hash)?;
+        Expression::BinaryOp(lhs, op, rhs) if crate::asm::CmpOp::try_from(op).is_ok() => {
+            let mut temps = Temps::default();
+            let (mut syn, lhs) = expression(*lhs, scope, &mut temps, hash)?;
+            let (rest, rhs) = expression(*rhs, scope, &mut temps, hash)?;
+            syn.extend(rest);
+            let (lhs, lhs_temp) = in_memory(lhs, &mut temps, &mut syn);
+            let rhs = temps.item(rhs, &mut syn);
+            if let Some(id) = lhs_temp {
+                temps.release(id);
+            }
             let cmp_op = crate::asm::CmpOp::try_from(op).unwrap();
-            let mut syn = lhs.0;
-            syn.extend(rhs.0);
-            let Item::Address(lhs) = lhs.1 else { return Err(Error::CompilationFailed(String::from("Can't compare literal on lhs")))};
-            let rhs = rhs.1;
             syn.push(Either::Left(Syntax::Instruction(Instruction::JmpCmp(
                 cmp_op, lhs, rhs, jmp,
             ))));
             Ok(syn)
         }
         Expression::BinaryOp(lhs, BinaryOp::And, rhs) => {
-            let lhs_hash = get_hash((hash, &lhs));
-            let rhs_hash = get_hash((hash, &rhs));
+            let lhs_hash = get_hash((hash, 0u8, &lhs));
+            let rhs_hash = get_hash((hash, 1u8, &rhs));
             let else_hash: Rc<str> = format!("_else_{hash:x}").into();
             let mut syn = Vec::new();
             syn.extend(compile_jcmp(
@@ -377,99 +683,350 @@ fn compile_jcmp(
             Ok(syn)
         }
         Expression::BinaryOp(lhs, BinaryOp::Or, rhs) => {
-            let lhs_hash = get_hash((hash, &lhs));
-            let rhs_hash = get_hash((hash, &rhs));
+            let lhs_hash = get_hash((hash, 0u8, &lhs));
+            let rhs_hash = get_hash((hash, 1u8, &rhs));
             let mut syn = Vec::new();
             syn.extend(compile_jcmp(*lhs, jmp.clone(), scope, lhs_hash)?);
             syn.extend(compile_jcmp(*rhs, jmp, scope, rhs_hash)?);
             Ok(syn)
         }
-        cond => Err(Error::InvalidExpression(cond)),
+        // anything else is true if it isn't zero
+        Expression::UnaryOp(UnaryOp::Not, inner) => {
+            let (mut syn, value) = value_in_memory(*inner, scope, hash)?;
+            syn.push(Either::Left(Syntax::Instruction(Instruction::Jcmpz(
+                true, value, jmp,
+            ))));
+            Ok(syn)
+        }
+        cond => {
+            let (mut syn, value) = value_in_memory(cond, scope, hash)?;
+            syn.push(Either::Left(Syntax::Instruction(Instruction::Jcmpz(
+                false, value, jmp,
+            ))));
+            Ok(syn)
+        }
     }
 }
 
-fn value_from(
+/// the code to work out `expr` for a statement, and the item it ends up as
+fn item_from(
     expr: Expression,
     scope: &Scope,
-    register: u16,
     hash: u64,
 ) -> Result<(Vec<Either<Syntax, Statement>>, Item), Error> {
-    match expr {
-        Expression::Int(i) => Ok((Vec::new(), Item::Literal(Value::Given(i)))),
-        Expression::UnaryOp(UnaryOp::Deref, inner) if matches!(&*inner, Expression::Int(_)) => {
-            let Expression::Int(int) = *inner else { unreachable!() };
-            Ok((Vec::new(), Item::Address(Value::Given(int))))
+    let mut temps = Temps::default();
+    let (mut code, operand) = expression(expr, scope, &mut temps, hash)?;
+    let item = temps.item(operand, &mut code);
+    Ok((code, item))
+}
+
+/// like `item_from`, but the value ends up somewhere in memory
+fn value_in_memory(
+    expr: Expression,
+    scope: &Scope,
+    hash: u64,
+) -> Result<(Vec<Either<Syntax, Statement>>, Value), Error> {
+    let mut temps = Temps::default();
+    let (mut code, operand) = expression(expr, scope, &mut temps, hash)?;
+    let (value, _) = in_memory(operand, &mut temps, &mut code);
+    Ok((code, value))
+}
+
+/// the address `operand`'s value is at, moving it into a temporary if it's a literal. Returns the
+/// temporary too, if there is one, to be released once the value's been used.
+fn in_memory(
+    operand: Operand,
+    temps: &mut Temps,
+    code: &mut Vec<Either<Syntax, Statement>>,
+) -> (Value, Option<usize>) {
+    match operand {
+        Operand::Item(Item::Address(address)) => (address, None),
+        Operand::Item(item @ Item::Literal(_)) => {
+            let (temp, register) = temps.alloc(code);
+            code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+                item,
+                Value::Given(register),
+            ))));
+            let Operand::Temp(id) = temp else {
+                unreachable!()
+            };
+            (Value::Given(register), Some(id))
         }
-        Expression::UnaryOp(op @ (UnaryOp::Deref | UnaryOp::Address), inner)
-            if matches!(&*inner, Expression::Ident(_)) =>
-        {
-            let Expression::Ident(var) = *inner else { unreachable!() };
-            let var_value = scope.get(&var);
-            let Some(var) = var_value else { return Err(Error::InvalidIdentifier(var))};
-            if op == UnaryOp::Deref {
-                Ok((
-                    vec![Either::Left(Syntax::Instruction(Instruction::Ptrread(
-                        var,
+        Operand::Temp(id) => (Value::Given(temps.register(id, code)), Some(id)),
+    }
+}
+
+/// the code to work out `expr`, and where its value ends up
+#[allow(clippy::too_many_lines)]
+fn expression(
+    expr: Expression,
+    scope: &Scope,
+    temps: &mut Temps,
+    hash: u64,
+) -> Result<(Vec<Either<Syntax, Statement>>, Operand), Error> {
+    if let Some(value) = try_as_const(expr.clone(), scope) {
+        return Ok((Vec::new(), Operand::Item(Item::Literal(value))));
+    }
+    let mut code = Vec::new();
+    let operand = match expr {
+        Expression::Ident(ident) => {
+            let Some(var) = scope.get(&ident) else {
+                return Err(Error::InvalidIdentifier(ident));
+            };
+            match var {
+                Variable::Static(value) => Operand::Item(Item::Address(value)),
+                Variable::Frame(_) => {
+                    let (temp, register) = temps.alloc(&mut code);
+                    code.extend(var.load(register).0);
+                    temp
+                }
+            }
+        }
+        Expression::UnaryOp(UnaryOp::Address, inner) => {
+            let Expression::Ident(ident) = *inner else {
+                return Err(Error::CompilationFailed(String::from(
+                    "only variables have addresses",
+                )));
+            };
+            let Some(var) = scope.get(&ident) else {
+                return Err(Error::InvalidIdentifier(ident));
+            };
+            match var {
+                Variable::Static(value) => Operand::Item(Item::Literal(value)),
+                Variable::Frame(_) => {
+                    let (temp, register) = temps.alloc(&mut code);
+                    code.extend(var.address(register).0);
+                    temp
+                }
+            }
+        }
+        Expression::UnaryOp(UnaryOp::Deref, inner) => {
+            let (syn, pointer) = expression(*inner, scope, temps, hash)?;
+            code.extend(syn);
+            match pointer {
+                // the pointer is known, so the value's at a known address
+                Operand::Item(Item::Literal(address)) => Operand::Item(Item::Address(address)),
+                Operand::Item(Item::Address(pointer)) => {
+                    let (temp, register) = temps.alloc(&mut code);
+                    code.push(Either::Left(Syntax::Instruction(Instruction::Ptrread(
+                        pointer,
                         Value::Given(register),
-                    )))],
-                    Item::Address(Value::Given(register)),
-                ))
+                    ))));
+                    temp
+                }
+                Operand::Temp(id) => {
+                    let register = Value::Given(temps.register(id, &mut code));
+                    code.push(Either::Left(Syntax::Instruction(Instruction::Ptrread(
+                        register.clone(),
+                        register,
+                    ))));
+                    Operand::Temp(id)
+                }
+            }
+        }
+        Expression::UnaryOp(UnaryOp::Not, inner) => {
+            let (syn, operand) = expression(*inner, scope, temps, hash)?;
+            code.extend(syn);
+            compare(
+                crate::asm::CmpOp::Eq,
+                operand,
+                Operand::Item(Item::Literal(Value::Given(0))),
+                temps,
+                &mut code,
+            )
+        }
+        Expression::BinaryOp(lhs, op @ (BinaryOp::And | BinaryOp::Or), rhs) => {
+            // the right hand side might not be worked out, so it can't move any temporaries
+            temps.spill_all(&mut code);
+            let (syn, lhs) = expression(*lhs, scope, temps, hash)?;
+            code.extend(syn);
+            let zero = || Operand::Item(Item::Literal(Value::Given(0)));
+            let lhs = compare(crate::asm::CmpOp::Ne, lhs, zero(), temps, &mut code);
+            let Operand::Temp(lhs) = lhs else {
+                unreachable!()
+            };
+            let register = temps.register(lhs, &mut code);
+            temps.release(lhs);
+            let end = temps.label(if op == BinaryOp::And { "and" } else { "or" }, hash);
+            code.push(Either::Left(Syntax::Instruction(Instruction::Jcmpz(
+                op == BinaryOp::And,
+                Value::Given(register),
+                Item::Literal(Value::Label(end.clone())),
+            ))));
+            let (syn, rhs) = expression(*rhs, scope, temps, hash)?;
+            code.extend(syn);
+            let (rhs, rhs_temp) = in_memory(rhs, temps, &mut code);
+            if let Some(id) = rhs_temp {
+                temps.release(id);
+            }
+            code.push(Either::Left(Syntax::Instruction(Instruction::Cmp(
+                crate::asm::CmpOp::Ne,
+                rhs,
+                Item::Literal(Value::Given(0)),
+                Value::Given(register),
+            ))));
+            code.push(Either::Left(Syntax::Label(end)));
+            temps.claim(register)
+        }
+        Expression::BinaryOp(lhs, op, rhs) => {
+            let (syn, lhs) = expression(*lhs, scope, temps, hash)?;
+            code.extend(syn);
+            let (syn, rhs) = expression(*rhs, scope, temps, hash)?;
+            code.extend(syn);
+            if let Ok(cmp_op) = crate::asm::CmpOp::try_from(op) {
+                compare(cmp_op, lhs, rhs, temps, &mut code)
+            } else if op == BinaryOp::Xor {
+                let zero = || Operand::Item(Item::Literal(Value::Given(0)));
+                let lhs = compare(crate::asm::CmpOp::Ne, lhs, zero(), temps, &mut code);
+                let rhs = compare(crate::asm::CmpOp::Ne, rhs, zero(), temps, &mut code);
+                math(MathOp::Xor, lhs, rhs, temps, &mut code)
             } else {
-                Ok((Vec::new(), Item::Literal(var)))
+                let math_op = MathOp::try_from(op).map_err(|_| {
+                    Error::CompilationFailed(format!("Couldn't turn `{op:?}` into math"))
+                })?;
+                math(math_op, lhs, rhs, temps, &mut code)
             }
         }
-        Expression::Ident(ident) => scope.get_constant(&ident).map_or_else(
-            || {
-                scope.get(&ident).map_or_else(
-                    || Err(Error::InvalidIdentifier(ident)),
-                    |val| Ok((Vec::new(), Item::Address(val))),
-                )
-            },
-            |val| Ok((Vec::new(), Item::Literal(val))),
-        ),
-        Expression::FunctionCall(func, args) => compile_fn_call(func, args, scope, hash),
-        expr => Err(Error::CompilationFailed(format!(
-            "Couldn't get a value from expression `{expr:?}`"
-        ))),
+        Expression::FunctionCall(func, args) => {
+            let (syn, result) = compile_fn_call(func, args, scope, temps, hash)?;
+            code.extend(syn);
+            result
+        }
+        Expression::String(_) | Expression::Array(_) => {
+            return Err(Error::CompilationFailed(String::from(
+                "strings and arrays can only be globals",
+            )))
+        }
+        Expression::Int(_) => unreachable!("numbers are constant"),
+    };
+    Ok((code, operand))
+}
+
+/// the code to work out `lhs math_op rhs` into a temporary
+fn math(
+    math_op: MathOp,
+    lhs: Operand,
+    rhs: Operand,
+    temps: &mut Temps,
+    code: &mut Vec<Either<Syntax, Statement>>,
+) -> Operand {
+    match (lhs, rhs) {
+        (Operand::Temp(id), rhs) => {
+            let register = temps.register(id, code);
+            let rhs = temps.item(rhs, code);
+            code.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
+                math_op,
+                rhs,
+                Value::Given(register),
+            ))));
+            Operand::Temp(id)
+        }
+        (Operand::Item(lhs), Operand::Temp(id)) => {
+            let register = Value::Given(temps.register(id, code));
+            code.push(Either::Left(Syntax::Instruction(Instruction::MathTernary(
+                math_op,
+                lhs,
+                Item::Address(register.clone()),
+                register,
+            ))));
+            Operand::Temp(id)
+        }
+        (Operand::Item(lhs), Operand::Item(rhs)) => {
+            let (temp, register) = temps.alloc(code);
+            let register = Value::Given(register);
+            // there's no form for two literals
+            if matches!((&lhs, &rhs), (Item::Literal(_), Item::Literal(_))) {
+                code.push(Either::Left(Syntax::Instruction(Instruction::Mov(
+                    lhs,
+                    register.clone(),
+                ))));
+                code.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
+                    math_op, rhs, register,
+                ))));
+            } else {
+                code.push(Either::Left(Syntax::Instruction(Instruction::MathTernary(
+                    math_op, lhs, rhs, register,
+                ))));
+            }
+            temp
+        }
     }
 }
 
+/// the code to work out whether `lhs cmp_op rhs` into a temporary, as `1` or `0`
+fn compare(
+    cmp_op: crate::asm::CmpOp,
+    lhs: Operand,
+    rhs: Operand,
+    temps: &mut Temps,
+    code: &mut Vec<Either<Syntax, Statement>>,
+) -> Operand {
+    let (lhs, lhs_temp) = in_memory(lhs, temps, code);
+    let rhs_temp = match &rhs {
+        Operand::Temp(id) => Some(*id),
+        Operand::Item(_) => None,
+    };
+    let rhs = match rhs {
+        Operand::Temp(id) => Item::Address(Value::Given(temps.register(id, code))),
+        Operand::Item(item) => item,
+    };
+    // the result goes in one of the temporaries if there is one
+    let (temp, register) = match (lhs_temp, rhs_temp) {
+        (Some(id), other) | (None, other @ Some(id)) => {
+            if let (Some(_), Some(other)) = (lhs_temp, other) {
+                temps.release(other);
+            }
+            (Operand::Temp(id), temps.register(id, code))
+        }
+        (None, None) => temps.alloc(code),
+    };
+    code.push(Either::Left(Syntax::Instruction(Instruction::Cmp(
+        cmp_op,
+        lhs,
+        rhs,
+        Value::Given(register),
+    ))));
+    temp
+}
+
+#[allow(clippy::cast_possible_truncation)]
 fn compile_fn_call(
     func: Rc<str>,
     args: Vec<Expression>,
     scope: &Scope,
+    temps: &mut Temps,
     hash: u64,
-) -> Result<(Vec<Either<Syntax, Statement>>, Item), Error> {
-    let Some(parameters) = scope.get_fn(&func) else { return Err(Error::InvalidIdentifier(func))};
+) -> Result<(Vec<Either<Syntax, Statement>>, Operand), Error> {
+    let Some(parameters) = scope.get_fn(&func) else {
+        return Err(Error::InvalidIdentifier(func));
+    };
     if parameters.len() != args.len() {
         return Err(Error::CompilationFailed(format!(
-            "Expected {} argument(s) for {func}; got {}",
+            "`{func}` takes {} argument(s), but was given {}",
             parameters.len(),
             args.len()
         )));
     }
+    // the function doesn't keep any registers
     let mut function_call = Vec::new();
-    for (expr, arg) in args.into_iter().zip(parameters) {
-        let (syn, value) = value_from(expr, scope, 1, hash)?;
+    temps.spill_all(&mut function_call);
+    for expr in args {
+        let (syn, value) = expression(expr, scope, temps, hash)?;
         function_call.extend(syn);
-        function_call.push(Either::Left(Syntax::Instruction(Instruction::Mov(
-            value,
-            Value::Label(format!("_fn_{func}_arg_{arg}").into()),
+        let value = temps.item(value, &mut function_call);
+        function_call.extend(stack::push(value).into_iter().map(Either::Left));
+    }
+    let ret_hash = temps.label(&format!("call_{func}_ret"), hash);
+    let target = Item::Literal(Value::Label(format!("_fn_{func}").into()));
+    function_call.extend(stack::call(target, ret_hash).into_iter().map(Either::Left));
+    if !parameters.is_empty() {
+        function_call.push(Either::Left(Syntax::Instruction(Instruction::MathBinary(
+            MathOp::Add,
+            Item::Literal(Value::Given(parameters.len() as u16)),
+            Value::Given(stack::STACK_POINTER),
         ))));
     }
-    let ret_hash: Rc<str> = format!("_call_{func}_ret_{hash:x}").into();
-    function_call.push(Either::Left(Syntax::Instruction(Instruction::Mov(
-        Item::Literal(Value::Label(ret_hash.clone())),
-        Value::Label(format!("_fn_{func}_ret_to").into()),
-    ))));
-    function_call.push(Either::Left(Syntax::Instruction(Instruction::Jmp(
-        Item::Literal(Value::Label(format!("_fn_{func}").into())),
-    ))));
-    function_call.push(Either::Left(Syntax::Label(ret_hash)));
-    Ok((
-        function_call,
-        Item::Address(Value::Label(format!("_fn_{func}_ret").into())),
-    ))
+    Ok((function_call, temps.claim(RETURN_REGISTER)))
 }
 
 fn try_as_const(expr: Expression, scope: &Scope) -> Option<Value> {
@@ -477,8 +1034,12 @@ fn try_as_const(expr: Expression, scope: &Scope) -> Option<Value> {
         Expression::Int(int) => Some(Value::Given(int)),
         Expression::Ident(ident) => scope.get_constant(&ident),
         Expression::BinaryOp(lhs, op, rhs) => {
-            let Some(Value::Given(lhs)) = try_as_const(*lhs, scope) else { return None };
-            let Some(Value::Given(rhs)) = try_as_const(*rhs, scope) else { return None };
+            let Some(Value::Given(lhs)) = try_as_const(*lhs, scope) else {
+                return None;
+            };
+            let Some(Value::Given(rhs)) = try_as_const(*rhs, scope) else {
+                return None;
+            };
             Some(Value::Given(match op {
                 BinaryOp::Add => lhs.wrapping_add(rhs),
                 BinaryOp::Sub => lhs.wrapping_sub(rhs),
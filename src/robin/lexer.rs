@@ -1,29 +1,103 @@
-use std::{fmt::Display, iter::Peekable};
+use std::{fmt::Display, iter::Peekable, str::Chars};
 
-use super::types::{Keyword, Token};
+use crate::asm::Span;
+
+use super::{
+    types::{Keyword, Token},
+    Diagnostic,
+};
+
+/// tokens, and where each one is
+pub type Lexed = (Vec<Token>, Vec<Span>);
 
 #[derive(Debug)]
 pub enum LexError {
     UnexpectedChar(char),
+    UnterminatedString,
     UnexpectedEOF,
 }
 
 impl Display for LexError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::UnexpectedChar(c) => write!(f, "Unexpected character {c:?}"),
-            Self::UnexpectedEOF => write!(f, "Unexpected end of file"),
+            Self::UnexpectedChar(c) => write!(f, "unexpected character {c:?}"),
+            Self::UnterminatedString => write!(f, "this string never ends"),
+            Self::UnexpectedEOF => write!(f, "unexpected end of file"),
         }
     }
 }
 
-pub fn lex(src: &str) -> Result<Vec<Token>, LexError> {
-    let mut chars = src.chars().peekable();
+/// Walks through the source a character at a time, keeping track of the line and column
+struct Source<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Source<'_> {
+    fn next(&mut self) -> Option<char> {
+        let char = self.chars.next()?;
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(char)
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// a zero-length span at the next character
+    const fn here(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            len: 0,
+            file: 0,
+        }
+    }
+
+    /// the span from `start` up to the next character, or just the first character if that's on a
+    /// later line
+    const fn since(&self, start: Span) -> Span {
+        Span {
+            len: if self.line == start.line {
+                self.column - start.column
+            } else {
+                1
+            },
+            ..start
+        }
+    }
+}
+
+/// split `src` into tokens, skipping characters that can't start one
+/// # Errors
+/// an error for each character that can't start a token, or string that doesn't end
+pub fn lex(src: &str) -> Result<Lexed, Vec<Diagnostic>> {
+    let mut chars = Source {
+        chars: src.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut errors = Vec::new();
     while chars.peek().is_some() {
-        lex_inner(&mut chars, &mut tokens)?;
+        let start = chars.here();
+        match lex_inner(&mut chars, &mut tokens) {
+            Ok(()) => spans.resize(tokens.len(), chars.since(start)),
+            Err(err) => errors.push(Diagnostic::error(err, Some(chars.since(start)))),
+        }
+    }
+    if errors.is_empty() {
+        Ok((tokens, spans))
+    } else {
+        Err(errors)
     }
-    Ok(tokens)
 }
 
 macro_rules! multi_character_pattern {
@@ -38,10 +112,7 @@ macro_rules! multi_character_pattern {
     };
 }
 
-fn lex_inner<I: Iterator<Item = char>>(
-    chars: &mut Peekable<I>,
-    tokens: &mut Vec<Token>,
-) -> Result<(), LexError> {
+fn lex_inner(chars: &mut Source, tokens: &mut Vec<Token>) -> Result<(), LexError> {
     tokens.push(match chars.next() {
         Some('=') => multi_character_pattern!(chars Token::Eq; {'=' => Token::Eqeq}),
         Some('<') => {
@@ -90,11 +161,12 @@ fn lex_inner<I: Iterator<Item = char>>(
         }
         Some('"') => {
             let mut str_buf = String::new();
-            for c in chars.by_ref() {
-                if c == '"' {
-                    break;
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => str_buf.push(c),
+                    None => return Err(LexError::UnterminatedString),
                 }
-                str_buf.push(c);
             }
             Token::String(str_buf.into())
         }
@@ -0,0 +1,112 @@
+use std::{collections::BTreeSet, rc::Rc};
+
+use super::{
+    types::{Expression, Spanned, Statement, TopLevelSyntax},
+    Diagnostic,
+};
+
+/// warnings about things that are allowed but probably mistakes: variables that are never read,
+/// and statements after a `return`, `break` or `continue` that can never run
+pub fn lint(syntax: &[Spanned<TopLevelSyntax>]) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    for item in syntax {
+        let TopLevelSyntax::Function(_, _, body) = &item.node else {
+            continue;
+        };
+        let mut all_bodies = Vec::new();
+        bodies(body, &mut all_bodies);
+
+        let mut read = BTreeSet::new();
+        for statement in all_bodies.iter().copied().flatten() {
+            for expr in expressions(&statement.node) {
+                reads(expr, &mut read);
+            }
+        }
+        let mut declared = BTreeSet::new();
+        for statement in all_bodies.iter().copied().flatten() {
+            if let Statement::Declaration(var, _) = &statement.node {
+                if declared.insert(var.clone()) && !read.contains(var) && !var.starts_with('_') {
+                    warnings.push(Diagnostic::warning(
+                        format!("`{var}` is never read"),
+                        statement.span,
+                    ));
+                }
+            }
+        }
+
+        for body in all_bodies {
+            let leaves = body.iter().position(|statement| {
+                matches!(
+                    statement.node,
+                    Statement::Return(_) | Statement::Break | Statement::Continue
+                )
+            });
+            if let Some(unreachable) = leaves.and_then(|idx| body.get(idx + 1)) {
+                warnings.push(Diagnostic::warning(
+                    "this statement can never run",
+                    unreachable.span,
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// `body` and every block in it, with a `for` loop's first and last parts as blocks of their own
+fn bodies<'a>(body: &'a [Spanned<Statement>], out: &mut Vec<&'a [Spanned<Statement>]>) {
+    out.push(body);
+    for statement in body {
+        match &statement.node {
+            Statement::Block(_, _, body) | Statement::Loop(body) => bodies(body, out),
+            Statement::IfElse(_, body, otherwise) => {
+                bodies(body, out);
+                bodies(otherwise, out);
+            }
+            Statement::For(init, _, step, body) => {
+                bodies(std::slice::from_ref(&**init), out);
+                bodies(body, out);
+                bodies(std::slice::from_ref(&**step), out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// the expressions `statement` works out itself, leaving out the ones in its blocks
+fn expressions(statement: &Statement) -> Vec<&Expression> {
+    match statement {
+        Statement::Declaration(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Assignment(_, _, expr)
+        | Statement::Block(_, expr, _)
+        | Statement::IfElse(expr, ..)
+        | Statement::For(_, expr, ..) => vec![expr],
+        Statement::StarAssignment(lhs, rhs) => vec![lhs, rhs],
+        Statement::FunctionCall(_, args) => args.iter().collect(),
+        Statement::Declaration(_, None)
+        | Statement::Return(None)
+        | Statement::Loop(_)
+        | Statement::Break
+        | Statement::Continue => Vec::new(),
+    }
+}
+
+/// add every variable `expr` reads to `read`
+fn reads(expr: &Expression, read: &mut BTreeSet<Rc<str>>) {
+    match expr {
+        Expression::Ident(ident) => {
+            read.insert(ident.clone());
+        }
+        Expression::BinaryOp(lhs, _, rhs) => {
+            reads(lhs, read);
+            reads(rhs, read);
+        }
+        Expression::UnaryOp(_, inner) => reads(inner, read),
+        Expression::FunctionCall(_, args) => {
+            for arg in args {
+                reads(arg, read);
+            }
+        }
+        Expression::Array(_) | Expression::String(_) | Expression::Int(_) => {}
+    }
+}
@@ -1,12 +1,17 @@
-use std::{fmt::Display, iter::Peekable};
+use std::{fmt::Display, iter::Peekable, vec::IntoIter};
 
-use super::types::{
-    AssignOp, BlockType, Expression, Keyword, Statement, Token, TopLevelSyntax, UnaryOp,
+use crate::asm::Span;
+
+use super::{
+    types::{
+        AssignOp, BlockType, Expression, Keyword, Spanned, Statement, Token, TopLevelSyntax,
+        UnaryOp,
+    },
+    Diagnostic,
 };
 
 #[derive(Debug)]
 pub enum ParseError {
-    UnexpectedToken(Token),
     UnexpectedTokenExpected(Token, Vec<Token>),
     UnexpectedTokenExpectedStr(Token, String),
     UnexpectedEOF,
@@ -14,32 +19,126 @@ pub enum ParseError {
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the tokens that stand for any identifier, number or string are shown as what they are
+        let expected = |tok: &Token| match tok {
+            Token::Ident(_) => String::from("an identifier"),
+            Token::Int(_) => String::from("a number"),
+            Token::String(_) => String::from("a string"),
+            tok => format!("`{tok}`"),
+        };
         match self {
-            Self::UnexpectedEOF => write!(f, "Unexpected end of file"),
-            Self::UnexpectedToken(tok) => write!(f, "Unexpected token {tok:?}"),
+            Self::UnexpectedEOF => write!(f, "unexpected end of file"),
+            Self::UnexpectedTokenExpected(tok, exp) if exp.len() == 1 => {
+                write!(f, "expected {}, found `{tok}`", expected(&exp[0]))
+            }
             Self::UnexpectedTokenExpected(tok, exp) => {
-                write!(f, "Unexpected token {tok:?}; Expected one of {exp:?}")
+                let exp: Vec<String> = exp.iter().map(expected).collect();
+                write!(f, "expected one of {}, found `{tok}`", exp.join(", "))
             }
             Self::UnexpectedTokenExpectedStr(tok, exp) => {
-                write!(f, "Unexpected token {tok:?}; Expected {exp}")
+                write!(f, "expected {exp}, found `{tok}`")
             }
         }
     }
 }
 
-pub fn parse(src: Vec<Token>) -> Result<Vec<TopLevelSyntax>, ParseError> {
-    let mut toks = src.into_iter().peekable();
+/// The tokens being parsed, which keep track of where they are for errors
+struct Tokens {
+    remaining: Peekable<std::iter::Zip<IntoIter<Token>, IntoIter<Span>>>,
+    /// the last token taken, and where it is
+    last: Option<Token>,
+    taken: Span,
+    /// the last token taken or peeked at, which is the one an error is about
+    seen: Span,
+    errors: Vec<Diagnostic>,
+}
+
+impl Tokens {
+    fn next(&mut self) -> Option<Token> {
+        let (token, span) = self.remaining.next()?;
+        self.last = Some(token.clone());
+        self.taken = span;
+        self.seen = span;
+        Some(token)
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        let (token, span) = self.remaining.peek()?;
+        self.seen = *span;
+        Some(token)
+    }
+
+    /// where the next token is, or the last one if there aren't any more
+    fn here(&mut self) -> Span {
+        self.remaining.peek().map_or(self.taken, |&(_, span)| span)
+    }
+
+    /// `node`, spanning from `start` to the last token taken
+    const fn spanned<T>(&self, node: T, start: Span) -> Spanned<T> {
+        Spanned {
+            node,
+            span: start.to(self.taken),
+        }
+    }
+
+    /// keep `err` to report it, about the token just looked at
+    fn error(&mut self, err: &ParseError) {
+        self.errors.push(Diagnostic::error(err, Some(self.seen)));
+    }
+
+    /// skip to the next token `stop` is true for, or the end
+    fn skip_until(&mut self, stop: impl Fn(&Token) -> bool) {
+        while self.peek().is_some_and(|token| !stop(token)) {
+            self.next();
+        }
+    }
+}
+
+/// parse the whole program, carrying on after an error at the next top level item or statement
+/// # Errors
+/// every syntax error there is
+pub fn parse(
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+) -> Result<Vec<Spanned<TopLevelSyntax>>, Vec<Diagnostic>> {
+    let start = Span {
+        line: 1,
+        column: 1,
+        len: 0,
+        file: 0,
+    };
+    let mut src = Tokens {
+        remaining: tokens.into_iter().zip(spans).peekable(),
+        last: None,
+        taken: start,
+        seen: start,
+        errors: Vec::new(),
+    };
     let mut top_level = Vec::new();
-    while toks.peek().is_some() {
-        top_level.extend(inner_parse_top_level(&mut toks)?);
+    while src.peek().is_some() {
+        let start = src.here();
+        match inner_parse_top_level(&mut src) {
+            Ok(syntax) => top_level.extend(syntax.into_iter().map(|node| src.spanned(node, start))),
+            Err(err) => {
+                src.error(&err);
+                src.skip_until(|token| {
+                    matches!(
+                        token,
+                        Token::Keyword(Keyword::Fn | Keyword::Const | Keyword::Global)
+                    )
+                });
+            }
+        }
+    }
+    if src.errors.is_empty() {
+        Ok(top_level)
+    } else {
+        Err(src.errors)
     }
-    Ok(top_level)
 }
 
 #[allow(clippy::too_many_lines)]
-fn inner_parse_top_level<I: Iterator<Item = Token>>(
-    src: &mut Peekable<I>,
-) -> Result<Vec<TopLevelSyntax>, ParseError> {
+fn inner_parse_top_level(src: &mut Tokens) -> Result<Vec<TopLevelSyntax>, ParseError> {
     match src.next() {
         Some(Token::Keyword(Keyword::Fn)) => {
             let fn_name = match src.next() {
@@ -47,7 +146,7 @@ fn inner_parse_top_level<I: Iterator<Item = Token>>(
                 Some(other) => {
                     return Err(ParseError::UnexpectedTokenExpectedStr(
                         other,
-                        "function identifier".to_string(),
+                        "a function name".to_string(),
                     ))
                 }
                 None => return Err(ParseError::UnexpectedEOF),
@@ -61,7 +160,7 @@ fn inner_parse_top_level<I: Iterator<Item = Token>>(
                     ))
                 }
                 None => return Err(ParseError::UnexpectedEOF),
-            };
+            }
             let args = match src.next() {
                 Some(Token::RParen) => Vec::new(),
                 Some(Token::Ident(arg)) => {
@@ -83,7 +182,7 @@ fn inner_parse_top_level<I: Iterator<Item = Token>>(
                             Some(other) => {
                                 return Err(ParseError::UnexpectedTokenExpectedStr(
                                     other,
-                                    "identifier".to_string(),
+                                    "an identifier".to_string(),
                                 ))
                             }
                             None => return Err(ParseError::UnexpectedEOF),
@@ -182,57 +281,78 @@ fn inner_parse_top_level<I: Iterator<Item = Token>>(
     }
 }
 
-fn inner_parse_block<I: Iterator<Item = Token>>(
-    src: &mut Peekable<I>,
-) -> Result<Vec<Statement>, ParseError> {
+fn inner_parse_block(src: &mut Tokens) -> Result<Vec<Spanned<Statement>>, ParseError> {
     match src.next() {
         Some(Token::LSquirrely) => {}
         Some(other) => {
             return Err(ParseError::UnexpectedTokenExpectedStr(
                 other,
-                "block".into(),
+                "a block".into(),
             ))
         }
         None => return Err(ParseError::UnexpectedEOF),
     }
     let mut body = Vec::new();
-    while src.peek() != Some(&Token::RSquirrely) {
-        body.push(inner_parse_statement(src)?);
-        match src.next() {
-            Some(Token::SemiColon) => {}
-            Some(tok) => {
-                return Err(ParseError::UnexpectedTokenExpected(
-                    tok,
-                    vec![Token::SemiColon],
-                ))
-            }
+    loop {
+        let start = match src.peek() {
+            Some(Token::RSquirrely) => break,
+            Some(_) => src.here(),
             None => return Err(ParseError::UnexpectedEOF),
+        };
+        let err = match inner_parse_statement(src) {
+            Ok(statement) => {
+                body.push(src.spanned(statement, start));
+                match src.peek() {
+                    Some(Token::SemiColon) => {
+                        src.next();
+                        continue;
+                    }
+                    Some(tok) => {
+                        ParseError::UnexpectedTokenExpected(tok.clone(), vec![Token::SemiColon])
+                    }
+                    None => return Err(ParseError::UnexpectedEOF),
+                }
+            }
+            Err(err) => err,
+        };
+        // carry on after the end of the statement, or at the end of the block, which might have
+        // been what was wrong
+        src.error(&err);
+        if src.seen == src.taken {
+            match src.last {
+                Some(Token::SemiColon) => continue,
+                Some(Token::RSquirrely) => return Ok(body),
+                _ => {}
+            }
+        }
+        src.skip_until(|token| matches!(token, Token::SemiColon | Token::RSquirrely));
+        if src.peek() == Some(&Token::SemiColon) {
+            src.next();
         }
     }
     src.next();
     Ok(body)
 }
 
-fn inner_parse_statement<I: Iterator<Item = Token>>(
-    src: &mut Peekable<I>,
-) -> Result<Statement, ParseError> {
+#[allow(clippy::too_many_lines)]
+fn inner_parse_statement(src: &mut Tokens) -> Result<Statement, ParseError> {
     match src.next() {
         Some(Token::Ident(ident)) => match src.next() {
             Some(tok) if AssignOp::try_from(tok.clone()).is_ok() => Ok(Statement::Assignment(
                 ident,
                 AssignOp::try_from(tok).unwrap(),
-                inner_parse_expr(src)?,
+                inner_parse_expr_greedy(src, 0)?,
             )),
             Some(Token::LParen) => {
                 if src.peek() == Some(&Token::RParen) {
                     src.next();
                     return Ok(Statement::FunctionCall(ident, Vec::new()));
                 }
-                let mut args = vec![inner_parse_expr_greedy(src, 4)?];
+                let mut args = vec![inner_parse_expr_greedy(src, 0)?];
                 loop {
                     match src.next() {
                         Some(Token::Comma) => {
-                            args.push(inner_parse_expr_greedy(src, 4)?);
+                            args.push(inner_parse_expr_greedy(src, 0)?);
                         }
                         Some(Token::RParen) => break,
                         Some(other) => {
@@ -299,20 +419,55 @@ fn inner_parse_statement<I: Iterator<Item = Token>>(
             let cond = inner_parse_expr_greedy(src, 0)?;
             let body = inner_parse_block(src)?;
             let block_type = BlockType::try_from(kw).unwrap();
-            Ok(Statement::Block(block_type, cond, body))
+            if block_type != BlockType::If || src.peek() != Some(&Token::Keyword(Keyword::Else)) {
+                return Ok(Statement::Block(block_type, cond, body));
+            }
+            src.next();
+            let otherwise = if src.peek() == Some(&Token::Keyword(Keyword::If)) {
+                let start = src.here();
+                let statement = inner_parse_statement(src)?;
+                vec![src.spanned(statement, start)]
+            } else {
+                inner_parse_block(src)?
+            };
+            Ok(Statement::IfElse(cond, body, otherwise))
+        }
+        Some(Token::Keyword(Keyword::For)) => {
+            expect(src, Token::LParen)?;
+            let start = src.here();
+            let init = inner_parse_statement(src)?;
+            let init = src.spanned(init, start);
+            expect(src, Token::SemiColon)?;
+            let cond = inner_parse_expr_greedy(src, 0)?;
+            expect(src, Token::SemiColon)?;
+            let start = src.here();
+            let step = inner_parse_statement(src)?;
+            let step = src.spanned(step, start);
+            expect(src, Token::RParen)?;
+            let body = inner_parse_block(src)?;
+            Ok(Statement::For(Box::new(init), cond, Box::new(step), body))
         }
+        Some(Token::Keyword(Keyword::Loop)) => Ok(Statement::Loop(inner_parse_block(src)?)),
+        Some(Token::Keyword(Keyword::Break)) => Ok(Statement::Break),
+        Some(Token::Keyword(Keyword::Continue)) => Ok(Statement::Continue),
         Some(other) => Err(ParseError::UnexpectedTokenExpectedStr(
             other,
-            "statement".to_string(),
+            "a statement".to_string(),
         )),
         None => Err(ParseError::UnexpectedEOF),
     }
 }
 
-fn inner_parse_expr_greedy<I: Iterator<Item = Token>>(
-    src: &mut Peekable<I>,
-    priority: u8,
-) -> Result<Expression, ParseError> {
+/// take `token` off the front of `src`, or fail if it's something else
+fn expect(src: &mut Tokens, token: Token) -> Result<(), ParseError> {
+    match src.next() {
+        Some(tok) if tok == token => Ok(()),
+        Some(tok) => Err(ParseError::UnexpectedTokenExpected(tok, vec![token])),
+        None => Err(ParseError::UnexpectedEOF),
+    }
+}
+
+fn inner_parse_expr_greedy(src: &mut Tokens, priority: u8) -> Result<Expression, ParseError> {
     if priority >= 5 {
         return inner_parse_expr(src);
     }
@@ -335,19 +490,36 @@ fn inner_parse_expr_greedy<I: Iterator<Item = Token>>(
                     Box::new(inner_parse_expr_greedy(src, priority + 1)?),
                 );
             }
-            Some(Token::Tack | Token::Plus) if priority == 2 => {}
-            Some(Token::Star) if priority == 3 => {}
+            Some(Token::Tack | Token::Plus) if priority == 2 => {
+                start = Expression::BinaryOp(
+                    Box::new(start),
+                    src.next().unwrap().try_into().unwrap(),
+                    Box::new(inner_parse_expr_greedy(src, priority + 1)?),
+                );
+            }
+            Some(Token::Star) if priority == 3 => {
+                start = Expression::BinaryOp(
+                    Box::new(start),
+                    src.next().unwrap().try_into().unwrap(),
+                    Box::new(inner_parse_expr_greedy(src, priority + 1)?),
+                );
+            }
             Some(Token::BitAnd | Token::BitOr | Token::BitXor | Token::Shl | Token::Shr)
-                if priority == 4 => {}
+                if priority == 4 =>
+            {
+                start = Expression::BinaryOp(
+                    Box::new(start),
+                    src.next().unwrap().try_into().unwrap(),
+                    Box::new(inner_parse_expr_greedy(src, priority + 1)?),
+                );
+            }
             Some(_) | None => break,
         }
     }
     Ok(start)
 }
 
-fn inner_parse_expr<I: Iterator<Item = Token>>(
-    src: &mut Peekable<I>,
-) -> Result<Expression, ParseError> {
+fn inner_parse_expr(src: &mut Tokens) -> Result<Expression, ParseError> {
     match src.next() {
         Some(tok) if UnaryOp::try_from(tok.clone()).is_ok() => Ok(Expression::UnaryOp(
             UnaryOp::try_from(tok).unwrap(),
@@ -370,7 +542,9 @@ fn inner_parse_expr<I: Iterator<Item = Token>>(
             }
             src.next();
             let mut args = Vec::new();
-            if src.peek() != Some(&Token::RParen) {
+            if src.peek() == Some(&Token::RParen) {
+                src.next();
+            } else {
                 loop {
                     args.push(inner_parse_expr_greedy(src, 0)?);
                     match src.next() {
@@ -391,7 +565,7 @@ fn inner_parse_expr<I: Iterator<Item = Token>>(
         Some(Token::Int(i)) => Ok(Expression::Int(i)),
         Some(other) => Err(ParseError::UnexpectedTokenExpectedStr(
             other,
-            "expression".to_string(),
+            "an expression".to_string(),
         )),
         None => Err(ParseError::UnexpectedEOF),
     }
@@ -0,0 +1,131 @@
+use std::rc::Rc;
+
+use crate::{
+    asm::{stack, Item, Syntax, Value},
+    utils::Either,
+};
+
+use super::types::Statement;
+
+/// the registers expressions are worked out in. The others are kept for the calling convention and
+/// for updating variables in stack frames.
+const REGISTERS: [u16; 10] = [0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA];
+
+/// The value of part of an expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    /// a literal or a word in memory, which doesn't need a register
+    Item(Item),
+    /// a temporary from `Temps`
+    Temp(usize),
+}
+
+/// Hands out registers to keep the parts of an expression in while it's worked out.
+///
+/// When they run out, the oldest temporary is pushed onto the stack and popped off again when it's
+/// needed. Expressions are trees, so by then everything newer has been used, and it's on top.
+#[derive(Debug, Default)]
+pub struct Temps {
+    /// the temporaries still needed, oldest first, and the register each is in, or `None` if it's
+    /// on the stack
+    live: Vec<(usize, Option<u16>)>,
+    /// how many temporaries there have been
+    count: usize,
+    /// how many labels there have been
+    labels: usize,
+}
+
+impl Temps {
+    /// a new temporary, and the register it's in
+    pub fn alloc(&mut self, code: &mut Vec<Either<Syntax, Statement>>) -> (Operand, u16) {
+        let register = self
+            .free()
+            .or_else(|| self.spill(code))
+            .expect("every register is a temporary");
+        (self.claim(register), register)
+    }
+
+    /// make a temporary of `register`, which has to be free
+    pub fn claim(&mut self, register: u16) -> Operand {
+        debug_assert!(self.free_registers().any(|free| free == register));
+        self.count += 1;
+        self.live.push((self.count, Some(register)));
+        Operand::Temp(self.count)
+    }
+
+    /// the register temporary `id` is in, popping it off the stack if it had been pushed
+    pub fn register(&mut self, id: usize, code: &mut Vec<Either<Syntax, Statement>>) -> u16 {
+        let idx = self
+            .live
+            .iter()
+            .position(|&(live, _)| live == id)
+            .expect("the temporary has already been used");
+        if let Some(register) = self.live[idx].1 {
+            return register;
+        }
+        debug_assert!(self.live[idx + 1..]
+            .iter()
+            .all(|(_, register)| register.is_some()));
+        let register = self.free().expect("newer temporaries have been used");
+        code.extend(
+            stack::pop(Value::Given(register))
+                .into_iter()
+                .map(Either::Left),
+        );
+        self.live[idx].1 = Some(register);
+        register
+    }
+
+    /// `operand` as an item, letting its register go if it's a temporary, since it's being used
+    pub fn item(&mut self, operand: Operand, code: &mut Vec<Either<Syntax, Statement>>) -> Item {
+        match operand {
+            Operand::Item(item) => item,
+            Operand::Temp(id) => {
+                let register = self.register(id, code);
+                self.release(id);
+                Item::Address(Value::Given(register))
+            }
+        }
+    }
+
+    /// stop keeping temporary `id`
+    pub fn release(&mut self, id: usize) {
+        self.live.retain(|&(live, _)| live != id);
+    }
+
+    /// push every temporary still in a register, because calls don't keep them and both ways
+    /// round a branch have to leave them in the same place
+    pub fn spill_all(&mut self, code: &mut Vec<Either<Syntax, Statement>>) {
+        while self.spill(code).is_some() {}
+    }
+
+    /// a label no other expression in the statement uses
+    pub fn label(&mut self, kind: &str, hash: u64) -> Rc<str> {
+        self.labels += 1;
+        format!("_{kind}_{hash:x}_{}", self.labels).into()
+    }
+
+    fn free_registers(&self) -> impl Iterator<Item = u16> + '_ {
+        REGISTERS
+            .into_iter()
+            .filter(|&register| self.live.iter().all(|&(_, used)| used != Some(register)))
+    }
+
+    fn free(&self) -> Option<u16> {
+        self.free_registers().next()
+    }
+
+    /// push the oldest temporary still in a register, returning the register
+    fn spill(&mut self, code: &mut Vec<Either<Syntax, Statement>>) -> Option<u16> {
+        let register = self
+            .live
+            .iter_mut()
+            .find_map(|(_, register)| register.take())?;
+        code.extend(
+            stack::push(Item::Address(Value::Given(register)))
+                .into_iter()
+                .map(Either::Left),
+        );
+        Some(register)
+    }
+}
@@ -1,8 +1,8 @@
-use std::rc::Rc;
+use std::{fmt::Display, rc::Rc};
 
 use strum::{AsRefStr, EnumString};
 
-use crate::asm::{CmpOp, MathOp};
+use crate::asm::{CmpOp, MathOp, Span};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
@@ -47,21 +47,80 @@ pub enum Token {
     RSquare,
 }
 
-#[derive(EnumString, Debug, PartialEq, Eq, Clone, Copy)]
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::Ident(ident) => return write!(f, "{ident}"),
+            Self::String(string) => return write!(f, "{string:?}"),
+            Self::Int(int) => return write!(f, "{int}"),
+            Self::Keyword(keyword) => keyword.as_ref(),
+            Self::Eq => "=",
+            Self::Plus => "+",
+            Self::PlusEq => "+=",
+            Self::Tack => "-",
+            Self::TackEq => "-=",
+            Self::Star => "*",
+            Self::StarEq => "*=",
+            Self::Eqeq => "==",
+            Self::BangEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::Bang => "!",
+            Self::And => "&&",
+            Self::AndEq => "&=",
+            Self::BitAnd => "&",
+            Self::Or => "||",
+            Self::OrEq => "|=",
+            Self::BitOr => "|",
+            Self::Xor => "^^",
+            Self::XorEq => "^=",
+            Self::BitXor => "^",
+            Self::Shl => "<<",
+            Self::ShlEq => "<<=",
+            Self::Shr => ">>",
+            Self::ShrEq => ">>=",
+            Self::Comma => ",",
+            Self::SemiColon => ";",
+            Self::LSquirrely => "{",
+            Self::RSquirrely => "}",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::LSquare => "[",
+            Self::RSquare => "]",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+#[derive(EnumString, AsRefStr, Debug, PartialEq, Eq, Clone, Copy)]
 #[strum(serialize_all = "lowercase")]
 pub enum Keyword {
     Const,
     Global,
     Fn,
     If,
+    Else,
+    For,
+    Loop,
+    Break,
+    Continue,
     Return,
     Var,
     While,
 }
 
+/// A piece of syntax and where it is in the source
+#[derive(Debug, Clone, Hash)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub enum TopLevelSyntax {
-    Function(Rc<str>, Vec<Rc<str>>, Vec<Statement>),
+    Function(Rc<str>, Vec<Rc<str>>, Vec<Spanned<Statement>>),
     Constant(Rc<str>, Expression),
     Global(Rc<str>, Expression),
 }
@@ -73,7 +132,19 @@ pub enum Statement {
     Assignment(Rc<str>, AssignOp, Expression),
     StarAssignment(Expression, Expression),
     FunctionCall(Rc<str>, Vec<Expression>),
-    Block(BlockType, Expression, Vec<Statement>),
+    Block(BlockType, Expression, Vec<Spanned<Self>>),
+    /// an `if` with an `else`, which is just another `if` for `else if`
+    IfElse(Expression, Vec<Spanned<Self>>, Vec<Spanned<Self>>),
+    /// `for (init; condition; step) {body}`
+    For(
+        Box<Spanned<Self>>,
+        Expression,
+        Box<Spanned<Self>>,
+        Vec<Spanned<Self>>,
+    ),
+    Loop(Vec<Spanned<Self>>),
+    Break,
+    Continue,
 }
 
 #[derive(Debug, Clone, Hash)]
@@ -82,9 +153,9 @@ pub enum Expression {
     Array(Vec<u16>),
     String(Rc<str>),
     Int(u16),
-    BinaryOp(Box<Expression>, BinaryOp, Box<Expression>),
-    UnaryOp(UnaryOp, Box<Expression>),
-    FunctionCall(Rc<str>, Vec<Expression>),
+    BinaryOp(Box<Self>, BinaryOp, Box<Self>),
+    UnaryOp(UnaryOp, Box<Self>),
+    FunctionCall(Rc<str>, Vec<Self>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsRefStr)]
@@ -171,6 +242,23 @@ impl TryFrom<Token> for BinaryOp {
     }
 }
 
+impl TryFrom<BinaryOp> for MathOp {
+    type Error = BinaryOp;
+    fn try_from(value: BinaryOp) -> Result<Self, Self::Error> {
+        match value {
+            BinaryOp::Add => Ok(Self::Add),
+            BinaryOp::Sub => Ok(Self::Sub),
+            BinaryOp::Mul => Ok(Self::Mul),
+            BinaryOp::BitAnd => Ok(Self::And),
+            BinaryOp::BitOr => Ok(Self::Or),
+            BinaryOp::BitXor => Ok(Self::Xor),
+            BinaryOp::Shl => Ok(Self::Shl),
+            BinaryOp::Shr => Ok(Self::Shr),
+            value => Err(value),
+        }
+    }
+}
+
 impl TryFrom<BinaryOp> for crate::asm::CmpOp {
     type Error = BinaryOp;
     fn try_from(value: BinaryOp) -> Result<Self, Self::Error> {
@@ -1,37 +1,363 @@
-use std::fmt::Debug;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    fs::File,
+    io::{BufRead, BufReader, Read, Stdout, Write},
+    ops::RangeInclusive,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
 
-use crate::{Computer, ComputerDebug};
+use crate::{Bus, Computer, ComputerDebug, CpuFault, Device, Interrupts, Mapped, RunOutcome};
 
-#[derive(Default)]
-pub struct ComputerIO<CPU: Computer>(CPU);
+/// Console input that can be checked without blocking
+pub trait Input: BufRead {
+    /// `true` if reading wouldn't block, because there's input waiting or it has ended
+    fn poll(&mut self) -> bool;
+}
+
+impl Input for &[u8] {
+    fn poll(&mut self) -> bool {
+        true
+    }
+}
+
+impl Input for BufReader<File> {
+    fn poll(&mut self) -> bool {
+        true
+    }
+}
+
+impl<I: Input + ?Sized> Input for Box<I> {
+    fn poll(&mut self) -> bool {
+        (**self).poll()
+    }
+}
+
+/// Reads stdin on a background thread so it can be polled. The thread isn't started until the
+/// first time the input is read or polled, so one that's never used leaves stdin alone.
+pub struct StdinReader {
+    receiver: Option<Receiver<Vec<u8>>>,
+    buffer: Vec<u8>,
+    position: usize,
+    ended: bool,
+}
+
+impl Default for StdinReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StdinReader {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            receiver: None,
+            buffer: Vec::new(),
+            position: 0,
+            ended: false,
+        }
+    }
+
+    /// the chunks read by the background thread, starting it if it hasn't been already
+    fn receiver(&mut self) -> &Receiver<Vec<u8>> {
+        self.receiver.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || loop {
+                let mut buffer = vec![0; 1024];
+                match std::io::stdin().read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => {
+                        buffer.truncate(len);
+                        if sender.send(buffer).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+            receiver
+        })
+    }
+
+    /// whether the background thread has been started
+    #[must_use]
+    pub const fn is_started(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    fn receive(&mut self, chunk: Result<Vec<u8>, ()>) {
+        match chunk {
+            Ok(chunk) => {
+                self.buffer = chunk;
+                self.position = 0;
+            }
+            Err(()) => self.ended = true,
+        }
+    }
+}
+
+impl Read for StdinReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for StdinReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.position == self.buffer.len() && !self.ended {
+            let chunk = self.receiver().recv().map_err(|_| ());
+            self.receive(chunk);
+        }
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.buffer.len());
+    }
+}
+
+impl Input for StdinReader {
+    fn poll(&mut self) -> bool {
+        if self.position < self.buffer.len() || self.ended {
+            return true;
+        }
+        match self.receiver().try_recv() {
+            Ok(chunk) => self.receive(Ok(chunk)),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => self.receive(Err(())),
+        }
+        true
+    }
+}
+
+/// Console I/O, driven by the signal register and `r0`.
+///
+/// - signal `1`: print a line. The program yields once per character in `r0`, then with `r0` set
+///   to `0x0000` to print the line.
+/// - signal `2`: read a line. Each yield gets the next character in `r0`, then `0x0000` once the
+///   line is used up.
+/// - signal `3`: read a character into `r0`, or `0x0000` if the input has ended.
+/// - signal `4`: print the character in `r0`.
+/// - signal `5`: set `r0` to `0x0001` if a character can be read without waiting, else `0x0000`.
+/// - signal `6`: like `1`, but without the newline.
+///
+/// The signal is cleared once the string or character is finished.
+pub struct ConsoleDevice<R: Input = StdinReader, W: Write = Stdout> {
+    signal: u16,
+    reader: R,
+    writer: W,
+    output: String,
+    input: Option<VecDeque<u16>>,
+}
+
+impl Default for ConsoleDevice {
+    fn default() -> Self {
+        Self::new(StdinReader::new(), std::io::stdout())
+    }
+}
+
+impl<R: Input, W: Write> ConsoleDevice<R, W> {
+    pub const fn new(reader: R, writer: W) -> Self {
+        Self {
+            signal: 0,
+            reader,
+            writer,
+            output: String::new(),
+            input: None,
+        }
+    }
+
+    /// read one UTF-8 character, or `None` at the end of the input
+    fn read_char(&mut self) -> Option<char> {
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes[..1]).ok()?;
+        let len = match bytes[0].leading_ones() {
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            _ => 1,
+        };
+        self.reader.read_exact(&mut bytes[1..len]).ok()?;
+        Some(
+            std::str::from_utf8(&bytes[..len])
+                .ok()
+                .and_then(|str| str.chars().next())
+                .unwrap_or('_'),
+        )
+    }
+
+    /// write `text` to the output. The program can't do anything about an output that's gone,
+    /// like a closed pipe, so errors are ignored.
+    fn print(&mut self, text: &str) {
+        let _ = self
+            .writer
+            .write_all(text.as_bytes())
+            .and_then(|()| self.writer.flush());
+    }
 
-impl<CPU: ComputerDebug> Debug for ComputerIO<CPU> {
+    pub const fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub const fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub const fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    pub const fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+impl<R: Input + 'static, W: Write + 'static> Device for ConsoleDevice<R, W> {
+    fn read(&self, _offset: u16) -> u16 {
+        self.signal
+    }
+
+    fn write(&mut self, _offset: u16, value: u16, _interrupts: &mut Interrupts) {
+        self.signal = value;
+    }
+
+    fn on_yield(&mut self, registers: &mut [u16], _interrupts: &mut Interrupts) -> bool {
+        match self.signal {
+            1 | 6 => {
+                if registers[0] == 0 {
+                    if self.signal == 1 {
+                        self.output.push('\n');
+                    }
+                    let output = std::mem::take(&mut self.output);
+                    self.print(&output);
+                    self.signal = 0;
+                } else {
+                    self.output
+                        .push(u32::from(registers[0]).try_into().unwrap_or('_'));
+                }
+                true
+            }
+            2 => {
+                let input = self.input.get_or_insert_with(|| {
+                    // a read error ends the line like the end of the input does
+                    let mut line = Vec::new();
+                    let _ = self.reader.read_until(b'\n', &mut line);
+                    String::from_utf8_lossy(&line)
+                        .chars()
+                        .map(u32::from)
+                        .map(u16::try_from)
+                        .map(Result::unwrap_or_default)
+                        .take_while(|&x| x > 0)
+                        .collect()
+                });
+                if let Some(char) = input.pop_front() {
+                    registers[0] = char;
+                } else {
+                    registers[0] = 0;
+                    self.input = None;
+                    self.signal = 0;
+                }
+                true
+            }
+            3 => {
+                registers[0] = self.read_char().map_or(0, |char| {
+                    u16::try_from(u32::from(char)).unwrap_or_else(|_| u16::from(b'_'))
+                });
+                self.signal = 0;
+                true
+            }
+            4 => {
+                let char = char::from_u32(registers[0].into()).unwrap_or('_');
+                self.print(&char.to_string());
+                self.signal = 0;
+                true
+            }
+            5 => {
+                registers[0] = self.reader.poll().into();
+                self.signal = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A `Computer` with a `ConsoleDevice` on `SIGNAL_REGISTER`, reading from `R` and writing to `W`
+pub struct ComputerIO<CPU: Mapped, R: Input + 'static = StdinReader, W: Write + 'static = Stdout>(
+    Bus<CPU>,
+    std::marker::PhantomData<ConsoleDevice<R, W>>,
+);
+
+impl<CPU: ComputerDebug + Mapped, R: Input, W: Write> Debug for ComputerIO<CPU, R, W> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self.0)
     }
 }
 
-impl<CPU: Computer> ComputerIO<CPU> {
+impl<CPU: Mapped + Default> Default for ComputerIO<CPU> {
+    fn default() -> Self {
+        Self::new(CPU::default())
+    }
+}
+
+impl<CPU: Mapped> ComputerIO<CPU> {
+    /// use stdin and stdout for the console
+    pub fn new(comp: CPU) -> Self {
+        Self::with_streams(comp, StdinReader::new(), std::io::stdout())
+    }
+}
+
+impl<CPU: Mapped, R: Input, W: Write> ComputerIO<CPU, R, W> {
     pub const SIGNAL_REGISTER: u16 = 0x0012;
 
-    pub const fn new(comp: CPU) -> Self {
-        Self(comp)
+    pub fn with_streams(comp: CPU, reader: R, writer: W) -> Self {
+        let mut bus = Bus::new(comp);
+        bus.map(
+            Self::SIGNAL_REGISTER..=Self::SIGNAL_REGISTER,
+            ConsoleDevice::new(reader, writer),
+        );
+        Self(bus, std::marker::PhantomData)
+    }
+
+    /// map another device alongside the console
+    pub fn map(&mut self, range: RangeInclusive<u16>, device: impl Device) {
+        self.0.map(range, device);
+    }
+
+    /// # Panics
+    /// if another device has been mapped over `SIGNAL_REGISTER`
+    pub fn console(&self) -> &ConsoleDevice<R, W> {
+        self.0.device(Self::SIGNAL_REGISTER).unwrap()
     }
 
-    pub fn program_input(&mut self, input: impl Iterator<Item = u16>) {
+    /// # Panics
+    /// if another device has been mapped over `SIGNAL_REGISTER`
+    pub fn console_mut(&mut self) -> &mut ConsoleDevice<R, W> {
+        self.0.device_mut(Self::SIGNAL_REGISTER).unwrap()
+    }
+
+    /// # Errors
+    /// if the CPU faults while reading the input
+    pub fn program_input(&mut self, input: impl Iterator<Item = u16>) -> Result<(), CpuFault> {
         // input all the data
         for k in input {
             self.0.set_mem(0x0000, k);
-            self.0.until_yield();
+            if self.0.run_for(u64::MAX)? != RunOutcome::Yielded {
+                return Ok(());
+            }
         }
 
         // add null terminator
         self.0.set_mem(0x0000, 0x0000);
-        // self.0.until_yield();
+        Ok(())
     }
 }
 
-impl<CPU: Computer> Computer for ComputerIO<CPU> {
+impl<CPU: Mapped, R: Input, W: Write> Computer for ComputerIO<CPU, R, W> {
     fn get_mem(&self, idx: u16) -> u16 {
         self.0.get_mem(idx)
     }
@@ -44,88 +370,44 @@ impl<CPU: Computer> Computer for ComputerIO<CPU> {
         self.0.set_mem(idx, value);
     }
 
-    fn until_yield(&mut self) {
-        loop {
-            self.0.until_yield();
-            match self.0.get_mem(Self::SIGNAL_REGISTER) {
-                1 => {
-                    let mut value = String::new();
-                    loop {
-                        if self.0.get_mem(0) == 0 {
-                            break;
-                        }
-                        value.push(u32::from(self.0.get_mem(0)).try_into().unwrap_or('_'));
-                        self.0.until_yield();
-                    }
-                    self.0.set_mem(Self::SIGNAL_REGISTER, 0);
-                    println!("{value}");
-                }
-                2 => {
-                    let mut value = String::new();
-                    std::io::stdin().read_line(&mut value).unwrap();
-                    self.program_input(
-                        value
-                            .chars()
-                            .map(u32::from)
-                            .map(u16::try_from)
-                            .map(Result::unwrap_or_default)
-                            .take_while(|&x| x > 0),
-                    );
-                    self.0.set_mem(Self::SIGNAL_REGISTER, 0);
-                }
-                _ => return,
-            }
-        }
+    fn cycles(&self) -> u64 {
+        self.0.cycles()
+    }
+
+    fn is_halted(&self) -> bool {
+        self.0.is_halted()
+    }
+
+    /// If the budget runs out partway through printing, the partial line is kept and printed once
+    /// the program finishes it on a later call.
+    fn run_for(&mut self, max_cycles: u64) -> Result<RunOutcome, CpuFault> {
+        self.0.run_for(max_cycles)
     }
 }
 
-impl<CPU: ComputerDebug> ComputerIO<CPU> {
-    pub fn program_input_debug(&mut self, input: impl Iterator<Item = u16>) {
+impl<CPU: ComputerDebug + Mapped, R: Input, W: Write> ComputerIO<CPU, R, W> {
+    /// # Errors
+    /// if the CPU faults while reading the input
+    pub fn program_input_debug(
+        &mut self,
+        input: impl Iterator<Item = u16>,
+    ) -> Result<(), CpuFault> {
         // input all the data
         for k in input {
             self.0.set_mem(0x0000, k);
-            self.0.debug_until_yield();
+            self.0.debug_until_yield()?;
             println!("{k:0>4X}\n{self:?}");
         }
 
         // add null terminator
         println!("Adding Null Terminator");
         self.0.set_mem(0x0000, 0x0000);
-        // self.0.debug_until_yield();
+        Ok(())
     }
 }
 
-impl<CPU: ComputerDebug> ComputerDebug for ComputerIO<CPU> {
-    fn debug_until_yield(&mut self) {
-        loop {
-            self.0.debug_until_yield();
-            match self.0.get_mem(Self::SIGNAL_REGISTER) {
-                1 => {
-                    let mut value = String::new();
-                    loop {
-                        if self.0.get_mem(0) == 0 {
-                            break;
-                        }
-                        value.push(u32::from(self.0.get_mem(0)).try_into().unwrap_or('_'));
-                        self.0.debug_until_yield();
-                    }
-                    self.0.set_mem(Self::SIGNAL_REGISTER, 0);
-                    println!("{value}");
-                }
-                2 => {
-                    let mut value = String::new();
-                    std::io::stdin().read_line(&mut value).unwrap();
-                    self.program_input_debug(
-                        value
-                            .chars()
-                            .map(u32::from)
-                            .flat_map(u16::try_from)
-                            .take_while(|&x| x != 0),
-                    );
-                    self.0.set_mem(Self::SIGNAL_REGISTER, 0);
-                }
-                _ => return,
-            }
-        }
+impl<CPU: ComputerDebug + Mapped, R: Input, W: Write> ComputerDebug for ComputerIO<CPU, R, W> {
+    fn debug_until_yield(&mut self) -> Result<(), CpuFault> {
+        self.0.debug_until_yield()
     }
 }
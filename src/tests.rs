@@ -1,4 +1,11 @@
-use crate::{Computer, CPU};
+use crate::{
+    asm::{CmpOp, Instruction, Item, MathOp, Value},
+    check, compile_asm, compile_asm_file, compile_asm_file_listed, compile_asm_image,
+    compile_asm_object, compile_asm_with_labels, compile_robin, decode, link, parse_symbol_map,
+    pipe, stack, symbol_map, ASMError, Bus, CompileOptions, Computer, ComputerIO, CpuFault,
+    Debugger, Device, ErrorKind, Export, Image, Interrupts, Mapped, Object, Relocation,
+    RobinKeyword, RobinToken, RunOutcome, Section, Segment, Span, Target, TopLevelSyntax, CPU,
+};
 
 const PROGRAM_POINTER: u16 = 0x8000;
 
@@ -32,21 +39,1636 @@ fn test_add_values() {
         ],
     );
 
-    comp.until_yield();
+    comp.until_yield().unwrap();
 
     // make sure it loaded registers correctly
     assert_eq!(comp.get_mem(0x6000), 0x8000);
     assert_eq!(comp.get_mem(0x0000), 0x0001);
 
-    comp.until_yield();
+    comp.until_yield().unwrap();
 
     // make sure it swapped registers correctly
     assert_eq!(comp.get_mem(0x6000), 0x0001);
     assert_eq!(comp.get_mem(0x0000), 0x8000);
 
-    comp.until_yield();
+    comp.until_yield().unwrap();
 
     // make sure it executes a conditional jump correctly
     assert_eq!(comp.get_mem(0x6000), 0x8000);
     assert_eq!(comp.get_mem(0x0000), 0x0001);
 }
+
+const MATH_OPS: [MathOp; 8] = [
+    MathOp::Add,
+    MathOp::Sub,
+    MathOp::Mul,
+    MathOp::And,
+    MathOp::Or,
+    MathOp::Xor,
+    MathOp::Shl,
+    MathOp::Shr,
+];
+
+const fn addr(value: u16) -> Item {
+    Item::Address(Value::Given(value))
+}
+
+const fn lit(value: u16) -> Item {
+    Item::Literal(Value::Given(value))
+}
+
+fn apply_math_op(math_op: MathOp, lhs: u16, rhs: u16) -> u16 {
+    match math_op {
+        MathOp::Add => lhs.wrapping_add(rhs),
+        MathOp::Sub => lhs.wrapping_sub(rhs),
+        MathOp::Mul => lhs.wrapping_mul(rhs),
+        MathOp::And => lhs & rhs,
+        MathOp::Or => lhs | rhs,
+        MathOp::Xor => lhs ^ rhs,
+        MathOp::Shl => lhs.checked_shl(rhs.into()).unwrap_or_default(),
+        MathOp::Shr => lhs.checked_shr(rhs.into()).unwrap_or_default(),
+    }
+}
+
+/// run a single instruction followed by a yield, checking that the instruction pointer lands on the yield
+#[allow(clippy::cast_possible_truncation)]
+fn run_instruction(instruction: &Instruction, memory: &[(u16, u16)]) -> CPU {
+    let mut comp = CPU::new();
+    for &(idx, value) in memory {
+        comp.set_mem(idx, value);
+    }
+    let mut machine_code = instruction.to_machine_code();
+    let len = machine_code.len() as u16;
+    machine_code.push(CPU::YIELD_INSTRUCTION);
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    comp.until_yield().unwrap();
+    assert_eq!(
+        comp.get_mem(CPU::INSTRUCTION_PTR),
+        PROGRAM_POINTER + len + 1,
+        "`{instruction}` encoded as {:X?}",
+        &machine_code[..len as usize]
+    );
+    comp
+}
+
+fn value_of(comp: &CPU, item: &Item) -> u16 {
+    match item {
+        Item::Address(addr) => comp.get_mem(addr.to_number()),
+        Item::Literal(lit) => lit.to_number(),
+    }
+}
+
+#[test]
+fn test_math_ternary_modes() {
+    const LHS: u16 = 0x0123;
+    const RHS: u16 = 0x0006;
+    // (first operand, second operand, destination, expected second nibble)
+    let cases = [
+        // short forms
+        (addr(0x4), addr(0x5), 0x6002, 0x2),
+        (lit(0x3), addr(0x5), 0x6002, 0x3),
+        (addr(0x4), lit(0x6), 0x6002, 0x4),
+        // fourth nibble is first arg
+        (addr(0x4), addr(0x6001), 0x6002, 0xC),
+        (lit(0x3), addr(0x6001), 0x6002, 0xC),
+        (addr(0x4), lit(0x0010), 0x6002, 0xC),
+        // fourth nibble is second arg
+        (addr(0x6000), addr(0x5), 0x6002, 0xD),
+        (lit(LHS), addr(0x5), 0x6002, 0xD),
+        (addr(0x6000), lit(0x6), 0x6002, 0xD),
+        // fourth nibble is third arg
+        (addr(0x6000), addr(0x6001), 0x7, 0xE),
+        (lit(LHS), addr(0x6001), 0x7, 0xE),
+        (addr(0x6000), lit(0x0010), 0x7, 0xE),
+        // fourth nibble unused
+        (addr(0x6000), addr(0x6001), 0x6002, 0xF),
+        (lit(LHS), addr(0x6001), 0x6002, 0xF),
+        (addr(0x6000), lit(0x0010), 0x6002, 0xF),
+    ];
+    for math_op in MATH_OPS {
+        for (first, second, dst, form) in cases.clone() {
+            let instruction =
+                Instruction::MathTernary(math_op, first.clone(), second.clone(), Value::Given(dst));
+            assert_eq!(
+                instruction.to_machine_code()[0] >> 8 & 0xF,
+                form,
+                "`{instruction}` used the wrong encoding"
+            );
+            let comp = run_instruction(
+                &instruction,
+                &[(0x4, LHS), (0x6000, LHS), (0x5, RHS), (0x6001, RHS)],
+            );
+            let expected =
+                apply_math_op(math_op, value_of(&comp, &first), value_of(&comp, &second));
+            assert_eq!(comp.get_mem(dst), expected, "`{instruction}`");
+        }
+    }
+}
+
+#[test]
+fn test_math_binary_modes() {
+    const DST: u16 = 0x0123;
+    const SRC: u16 = 0x0006;
+    // (source, destination, expected second nibble)
+    let cases = [
+        (addr(0x5), 0x4, 0x0),
+        (lit(0x6), 0x4, 0x1),
+        (addr(0x5), 0x6000, 0xC),
+        (lit(0x6), 0x6000, 0xC),
+        (addr(0x6001), 0x4, 0xD),
+        (lit(0x0010), 0x4, 0xD),
+        (addr(0x6001), 0x6000, 0xE),
+        (lit(0x0010), 0x6000, 0xE),
+    ];
+    for math_op in MATH_OPS {
+        for (src, dst, form) in cases.clone() {
+            let instruction = Instruction::MathBinary(math_op, src.clone(), Value::Given(dst));
+            assert_eq!(
+                instruction.to_machine_code()[0] >> 8 & 0xF,
+                form,
+                "`{instruction}` used the wrong encoding"
+            );
+            let comp = run_instruction(
+                &instruction,
+                &[(0x4, DST), (0x6000, DST), (0x5, SRC), (0x6001, SRC)],
+            );
+            let expected = apply_math_op(math_op, DST, value_of(&comp, &src));
+            assert_eq!(comp.get_mem(dst), expected, "`{instruction}`");
+        }
+    }
+}
+
+#[test]
+fn test_asm_math_ternary() {
+    let machine_code = compile_asm(
+        "
+        MOV #7 &lhs;
+        ADD #5 &lhs &sum;
+        SUB &lhs #2 &diff;
+        MUL &lhs &sum r1;
+        SUB #100 &lhs r2;
+        YIELD;
+        :lhs #0;
+        :sum #0;
+        :diff #0;
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    comp.until_yield().unwrap();
+    let lhs = PROGRAM_POINTER + u16::try_from(machine_code.len()).unwrap() - 3;
+    assert_eq!(comp.get_mem(lhs), 7);
+    assert_eq!(comp.get_mem(lhs + 1), 12);
+    assert_eq!(comp.get_mem(lhs + 2), 5);
+    assert_eq!(comp.get_mem(0x1), 84);
+    assert_eq!(comp.get_mem(0x2), 0x100 - 7);
+}
+
+#[test]
+fn test_asm_math_ternary_rejects_two_literals() {
+    let err = compile_asm("ADD #5 #6 r1;").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::TwoLiterals);
+    assert_eq!(
+        err.to_string(),
+        "1:1: ternary math operations can't have two literals"
+    );
+}
+
+#[test]
+fn test_asm_errors() {
+    let err = compile_asm("YIELD;\n    MOV #1 #5;\n").unwrap_err();
+    assert_eq!(err.to_string(), "2:12: expected an address, found `#5`");
+    assert_eq!(
+        err.render("test.asm", "YIELD;\n    MOV #1 #5;\n"),
+        "error: expected an address, found `#5`\n --> test.asm:2:12\n  |\n2 |     MOV #1 #5;\n  |            ^^"
+    );
+    assert_eq!(
+        compile_asm("HALT;\nJMP #stop;").unwrap_err(),
+        ASMError::new(
+            ErrorKind::UndefinedLabel("stop".into()),
+            Some(Span {
+                line: 2,
+                column: 1,
+                len: 10,
+                file: 0,
+            })
+        )
+    );
+    assert_eq!(
+        compile_asm(":loop\n:loop HALT;").unwrap_err().to_string(),
+        "2:1: label `loop` is already defined"
+    );
+    assert_eq!(
+        compile_asm("YIELD;\nHALT").unwrap_err().to_string(),
+        "2:1: expected `;`, found the end of the input"
+    );
+    assert_eq!(
+        compile_asm("MOV #1 r0 %x;").unwrap_err().to_string(),
+        "1:11: unrecognized token `%x`"
+    );
+}
+
+#[test]
+fn test_asm_lexer() {
+    let machine_code = compile_asm(
+        r#"
+        #! comments run to the end of the line
+        MOV #0n10 r10; // decimal
+        MOV #%101 r15;
+        MOV #'a' r1;
+        MOV #-1 r2;
+        MOV #0x1F r3;
+        MOV #'\n' r4;
+        MOV #0n10+10 r5; // the same digits are hex unless they're marked
+        MOV #-0n10 r6;
+        MOV #0d10 r7;
+        :text "a; b\"\u{263A}\n"
+        "#,
+    )
+    .unwrap();
+    let expected = compile_asm(
+        "MOV #A rA; MOV #5 rF; MOV #61 r1; MOV #FFFF r2; MOV #1F r3; MOV #A r4; MOV #1A r5;
+        MOV #FFF6 r6; MOV #D10 r7;
+        #61; #3B; #20; #62; #22; #263A; #A; #0;",
+    )
+    .unwrap();
+    assert_eq!(machine_code, expected);
+    assert_eq!(
+        compile_asm("MOV #1 r16;").unwrap_err().to_string(),
+        "1:8: expected an address, found `r16`"
+    );
+    assert_eq!(
+        compile_asm("MOV #1 &rest;").unwrap_err().kind,
+        ErrorKind::UndefinedLabel("rest".into())
+    );
+    // names that would be read as registers or numbers can't be labels
+    assert_eq!(
+        compile_asm("MOV #1 &r1;").unwrap_err().to_string(),
+        "1:9: `r1` reads as a number or a register, so it can't be a name"
+    );
+    assert_eq!(
+        compile_asm(":r1 HALT;").unwrap_err().kind,
+        ErrorKind::ReservedName("r1".into())
+    );
+    assert_eq!(
+        compile_asm(":face HALT;").unwrap_err().kind,
+        ErrorKind::ReservedName("face".into())
+    );
+    assert_eq!(
+        compile_asm(".equ beef #1;").unwrap_err().kind,
+        ErrorKind::ReservedName("beef".into())
+    );
+    // other words that start with `r` are names
+    assert_eq!(
+        compile_asm(":read JMP #read; :radd HALT;").unwrap(),
+        [0x0E40, PROGRAM_POINTER, CPU::HALT_INSTRUCTION]
+    );
+    assert_eq!(
+        compile_asm(r#""tab\q""#).unwrap_err().to_string(),
+        "1:5: unknown escape sequence `\\q`"
+    );
+    assert_eq!(
+        compile_asm("\"open").unwrap_err().to_string(),
+        "1:1: expected `\"`, found the end of the input"
+    );
+}
+
+#[test]
+fn test_asm_sections() {
+    let (image, labels) = compile_asm_image(
+        "
+        .equ count #3;
+        .section data #A000;
+        :text \"hi\"
+        .section code;
+        MOV #count r1;
+        MOV #text r2;
+        .align #4;
+        :aligned HALT;
+        .org #9000;
+        :far HALT;
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        image,
+        Image {
+            entry: 0x8000,
+            segments: vec![
+                Segment {
+                    origin: 0x8000,
+                    words: vec![0x0131, 0x0E12, 0xA000, 0x0000, CPU::HALT_INSTRUCTION],
+                },
+                Segment {
+                    origin: 0x9000,
+                    words: vec![CPU::HALT_INSTRUCTION],
+                },
+                Segment {
+                    origin: 0xA000,
+                    words: vec![u16::from(b'h'), u16::from(b'i'), 0],
+                },
+            ],
+        }
+    );
+    assert_eq!(labels.get("text"), Some(&0xA000));
+    assert_eq!(labels.get("aligned"), Some(&0x8004));
+    assert_eq!(labels.get("far"), Some(&0x9000));
+    assert_eq!(labels.get("count"), None);
+    assert_eq!(Image::from_words(&image.to_words()), Some(image.clone()));
+    // a flat program is laid out from 0x8000
+    assert_eq!(
+        compile_asm("HALT; .org #8003; HALT;").unwrap(),
+        [CPU::HALT_INSTRUCTION, 0, 0, CPU::HALT_INSTRUCTION]
+    );
+    assert_eq!(
+        Image::from_words(&[CPU::HALT_INSTRUCTION]),
+        Some(Image::flat(vec![CPU::HALT_INSTRUCTION]))
+    );
+
+    let mut comp = CPU::new();
+    image.load(&mut comp);
+    assert_eq!(comp.get_mem(CPU::INSTRUCTION_PTR), 0x8000);
+    assert_eq!(comp.get_mem(0xA001), u16::from(b'i'));
+    assert_eq!(comp.run_for(100), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x1), 3);
+    assert_eq!(comp.get_mem(0x2), 0xA000);
+
+    let error = |src| compile_asm(src).unwrap_err().to_string();
+    assert_eq!(
+        error(".section data;"),
+        "1:1: section `data` needs an origin the first time it's used, like `.section data #A000;`"
+    );
+    assert_eq!(
+        error(".org #5;"),
+        "1:1: can't put code at `0005`, which is a register; use `0010` or later"
+    );
+    assert_eq!(
+        error("HALT; .org #8000; HALT;"),
+        "two segments both use `8000`"
+    );
+    assert_eq!(
+        error(".org #4000; HALT;"),
+        "a segment starts at `4000`, before the load address `8000`"
+    );
+    assert_eq!(
+        error(".org &9000;"),
+        "1:1: expected `.org #address;`, found `.org &9000;`"
+    );
+    assert_eq!(
+        error(".equ stop #1; :stop HALT;"),
+        "1:15: label `stop` is already defined"
+    );
+}
+
+#[test]
+fn test_asm_macros() {
+    let (machine_code, labels) = compile_asm_with_labels(
+        "
+        .macro count_down reg;
+        :loop
+        ADD #1 r4;
+        SUB #1 reg;
+        JNZ reg #loop;
+        .endm;
+        .macro count_from reg value;
+        MOV value reg;
+        count_down reg;
+        .endm;
+        MOV #3 r1;
+        count_down r1;
+        count_from r2 #5;
+        HALT;
+        ",
+    )
+    .unwrap();
+    // each expansion gets its own copy of the labels in the macro
+    assert_eq!(
+        labels.keys().map(AsRef::as_ref).collect::<Vec<&str>>(),
+        ["loop$1", "loop$3"]
+    );
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x4), 8);
+
+    let error = |src| compile_asm(src).unwrap_err().to_string();
+    assert_eq!(
+        error(".macro pair lhs rhs; MOV lhs rhs; .endm; pair r1;"),
+        "1:42: macro `pair` takes 2 arguments, but was given 1"
+    );
+    assert_eq!(
+        error(".macro forever; forever; .endm; forever;"),
+        "1:17: macro `forever` expands itself forever"
+    );
+    assert_eq!(
+        error(".macro open; HALT;"),
+        "1:1: expected `.endm;`, found the end of the input"
+    );
+
+    // parameters can be used inside operands and expressions
+    let (machine_code, labels) = compile_asm_with_labels(
+        "
+        .macro go target;
+        JMP #target;
+        .endm;
+        .macro store value dst;
+        MOV value &dst;
+        MOV value &dst+1;
+        .endm;
+        go start;
+        :x
+        #0; #0;
+        :start
+        store #7 x;
+        HALT;
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(labels["x"]), 7);
+    assert_eq!(comp.get_mem(labels["x"] + 1), 7);
+    // a parameter that would be read as a number can't silently become one
+    assert_eq!(
+        error(".macro st v a; MOV v &a; .endm;"),
+        "1:13: `a` reads as a number or a register, so it can't be a name"
+    );
+    assert_eq!(
+        error(".macro go t; JMP #t; .endm; go \"no\";"),
+        "1:32: expected a name, literal or address for `t`, which is used in an operand, found `\"no\"`"
+    );
+}
+
+#[test]
+fn test_asm_include() {
+    let dir = std::env::temp_dir().join(format!("computer_include_{}", std::process::id()));
+    let lib = dir.join("lib");
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(
+        lib.join("count.asm"),
+        ".macro count_up reg; ADD #1 reg; .endm;\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("body.asm"), "count_up r1;\ncount_up r1;\n").unwrap();
+    std::fs::write(
+        dir.join("main.asm"),
+        ".include \"count.asm\";\n.include \"body.asm\";\nHALT;\n",
+    )
+    .unwrap();
+    let (image, _) = compile_asm_file(&dir.join("main.asm"), &[lib]).unwrap();
+    let mut comp = CPU::new();
+    image.load(&mut comp);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x1), 2);
+
+    // without the include path, `count.asm` can't be found
+    let err = compile_asm_file(&dir.join("main.asm"), &[]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "1:1: can't include `count.asm`: it isn't in any of the include paths"
+    );
+
+    std::fs::write(dir.join("loop.asm"), "HALT;\n.include \"loop.asm\";\n").unwrap();
+    let err = compile_asm_file(&dir.join("loop.asm"), &[]).unwrap_err();
+    assert_eq!(err.to_string(), "2:1: `loop.asm` ends up including itself");
+
+    // errors in an included file point into that file
+    std::fs::write(dir.join("bad.asm"), "HALT;\nJMP;\n").unwrap();
+    std::fs::write(dir.join("outer.asm"), ".include \"bad.asm\";\n").unwrap();
+    let err = compile_asm_file(&dir.join("outer.asm"), &[]).unwrap_err();
+    let bad = dir.join("bad.asm").display().to_string();
+    assert_eq!(err.file.as_deref(), Some(bad.as_str()));
+    assert!(err.render("outer.asm", "").contains("2 | JMP;"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_asm_expressions() {
+    let (machine_code, labels) = compile_asm_with_labels(
+        r#"
+        .equ base #0x10;
+        .equ twice #base*2+1;
+        .macro skip;
+        JMP #past+0;
+        :past
+        .endm;
+        MOV #msg+1 r1;
+        PTRREAD r1 r2;
+        MOV #twice r3;
+        MOV #( base << 2 )|1 r4;
+        MOV #-1 r5;
+        MOV #$ r6;
+        MOV #end-msg r7;
+        skip;
+        skip;
+        HALT;
+        :msg
+        "hi"
+        :end
+        #msg;
+        "#,
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x2), u16::from(b'i'));
+    assert_eq!(comp.get_mem(0x3), 0x21);
+    assert_eq!(comp.get_mem(0x4), 0x41);
+    assert_eq!(comp.get_mem(0x5), 0xFFFF);
+    assert_eq!(comp.get_mem(0x6), 0x8009);
+    assert_eq!(comp.get_mem(0x7), 3);
+    // `end - msg` fits in the short form, and the labels after it are where it left them
+    assert_eq!(labels["msg"], 0x8011);
+    assert_eq!(labels["end"], 0x8014);
+    assert_eq!(comp.get_mem(labels["end"]), labels["msg"]);
+
+    let error = |src| compile_asm(src).unwrap_err().to_string();
+    assert_eq!(error("MOV #(1+2 r1;"), "1:11: expected `)`, found `r`");
+    assert_eq!(error("MOV #nope+1 r1;"), "1:1: undefined label `nope`");
+    assert_eq!(error("MOV #1+ r1;"), "1:5: unrecognized token `#1+`");
+
+    // how much to reserve can be worked out too, from anything defined before it
+    let (_, labels) = compile_asm_with_labels(
+        "
+        .equ size #2;
+        :start
+        RESERVE #size*2;
+        RESERVE #$-start;
+        :end
+        ",
+    )
+    .unwrap();
+    assert_eq!(labels["end"], 0x8008);
+    assert_eq!(
+        error("RESERVE #later; :later"),
+        "1:1: `later` has to be defined before it's used to say how many words to reserve"
+    );
+}
+
+#[test]
+fn test_link_objects() {
+    let main = compile_asm_object(
+        "
+        MOV #5 r1;
+        MOV #after r2;
+        JMP #double;
+        :after
+        MOV &value r3;
+        MOV &ptr r4;
+        PTRREAD r4 r5;
+        MOV #limit r6;
+        HALT;
+        .section data;
+        :value
+        #2A;
+        :ptr
+        #value;
+        ",
+    )
+    .unwrap();
+    let runtime = compile_asm_object(
+        "
+        .global double;
+        .global limit;
+        .equ limit #100;
+        :double
+        ADD r1 r1;
+        JMP r2;
+        ",
+    )
+    .unwrap();
+    assert_eq!(Object::from_words(&main.to_words()), Some(main.clone()));
+    let words = main.to_words();
+    for len in 0..words.len() {
+        assert_eq!(Object::from_words(&words[..len]), None);
+    }
+    // relocations and exports have to point inside the object
+    let object = |offset, target| Object {
+        sections: vec![Section {
+            name: "code".into(),
+            align: 1,
+            words: vec![0],
+            relocations: vec![Relocation { offset, target }],
+        }],
+        exports: vec![],
+    };
+    let valid = object(0, Target::Section(0));
+    assert_eq!(Object::from_words(&valid.to_words()), Some(valid));
+    assert_eq!(
+        Object::from_words(&object(1, Target::Section(0)).to_words()),
+        None
+    );
+    assert_eq!(
+        Object::from_words(&object(0, Target::Section(1)).to_words()),
+        None
+    );
+    let mut exported = object(0, Target::Section(0));
+    exported.exports.push(Export {
+        name: "start".into(),
+        section: Some(1),
+        value: 0,
+    });
+    assert_eq!(Object::from_words(&exported.to_words()), None);
+
+    let (image, symbols) = link(&[main.clone(), runtime.clone()], 0x9000).unwrap();
+    assert_eq!(image.entry, 0x9000);
+    assert_eq!(symbols["limit"], 0x100);
+    let mut comp = CPU::new();
+    image.load(&mut comp);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x1), 10);
+    assert_eq!(comp.get_mem(0x3), 0x2A);
+    assert_eq!(comp.get_mem(0x5), 0x2A);
+    assert_eq!(comp.get_mem(0x6), 0x100);
+    // the data goes after the code from both objects
+    assert!(comp.get_mem(0x4) > symbols["double"]);
+
+    let error = |objects: &[Object]| link(objects, 0x8000).unwrap_err().to_string();
+    assert_eq!(error(&[main]), "undefined label `double`");
+    assert_eq!(
+        error(&[runtime.clone(), runtime]),
+        "label `double` is already defined"
+    );
+    let error = |src| compile_asm_object(src).unwrap_err().to_string();
+    assert_eq!(
+        error(".org #9000;"),
+        "1:1: objects can't choose their own addresses; the linker places their sections"
+    );
+    assert_eq!(
+        error("MOV #elsewhere*2 r1;"),
+        "1:1: `(elsewhere*0002)` depends on where the program is linked, so it can't be worked out here"
+    );
+
+    // a relocatable operand keeps its own word even next to a given one that looks like it
+    let object = compile_asm_object("PTRREAD &1001 &later; HALT; :later #5;").unwrap();
+    let (image, _) = link(&[object], 0x8000).unwrap();
+    let mut comp = CPU::new();
+    image.load(&mut comp);
+    let words: Vec<u16> = (0x8000..0x8005).map(|idx| comp.get_mem(idx)).collect();
+    assert_eq!(
+        words,
+        [0xAF10, 0x1001, 0x8004, CPU::HALT_INSTRUCTION, 0x0005]
+    );
+}
+
+#[test]
+fn test_undefined_instructions_fault() {
+    let cases = [
+        // MOV/JMP modes 9-C
+        (0x0901, 1),
+        (0x0C12, 1),
+        // PTR modes 4-C
+        (0xA412, 1),
+        (0xAC12, 1),
+        // comparison modes 6-B
+        (0x4612, 1),
+        (0x9B12, 1),
+        // math modes 5-B
+        (0x1512, 1),
+        (0xFB12, 1),
+        // extensions selecting an undefined mode
+        (0x0D91, 2),
+        (0xAE41, 2),
+        (0x5C61, 2),
+        (0x3F50, 2),
+    ];
+    for (instruction, code) in cases {
+        let mut comp = CPU::new();
+        comp.insert_data(PROGRAM_POINTER, &[instruction, CPU::YIELD_INSTRUCTION]);
+        comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+        let fault = comp.until_yield().unwrap_err();
+        assert_eq!(fault.code(), code, "{instruction:0>4X}");
+        assert!(matches!(
+            fault,
+            CpuFault::IllegalOpcode { address: PROGRAM_POINTER, instruction: i }
+                | CpuFault::BadMode { address: PROGRAM_POINTER, instruction: i }
+                if i == instruction
+        ));
+        // the faulting instruction isn't skipped
+        assert_eq!(comp.get_mem(CPU::INSTRUCTION_PTR), PROGRAM_POINTER);
+        assert_eq!(comp.get_mem(CPU::FAULT_REGISTER), code);
+        assert_eq!(comp.try_tick(), Err(fault));
+    }
+}
+
+#[test]
+fn test_halted_cpu_faults() {
+    let mut comp = CPU::new();
+    comp.set_mem(CPU::INSTRUCTION_PTR, CPU::INSTRUCTION_PTR);
+    assert_eq!(comp.until_yield(), Ok(()));
+    assert!(comp.is_halted());
+    assert_eq!(comp.try_tick(), Err(CpuFault::Halted));
+    assert_eq!(comp.get_mem(CPU::FAULT_REGISTER), CpuFault::Halted.code());
+}
+
+#[test]
+fn test_cmp_short_literal_mode() {
+    for (value, expected) in [(3, 1), (4, 0)] {
+        let instruction =
+            Instruction::Cmp(CmpOp::Eq, Value::Given(0x4), lit(0x3), Value::Given(0x5));
+        assert_eq!(instruction.to_machine_code()[0] >> 8 & 0xF, 0x5);
+        let comp = run_instruction(&instruction, &[(0x4, value)]);
+        assert_eq!(comp.get_mem(0x5), expected);
+    }
+}
+
+#[test]
+fn test_run_for_budget() {
+    let mut comp = CPU::new();
+    comp.insert_data(
+        PROGRAM_POINTER,
+        &[
+            // ADD #1, r0
+            0x1110,
+            // JNE r0, #3, #PROGRAM_POINTER
+            0x5303,
+            PROGRAM_POINTER,
+            // YIELD
+            CPU::YIELD_INSTRUCTION,
+            // JMP #PROGRAM_POINTER
+            0x0E40,
+            PROGRAM_POINTER,
+        ],
+    );
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    assert_eq!(comp.run_for(4), Ok(RunOutcome::BudgetExhausted));
+    assert_eq!(comp.cycles(), 4);
+    assert_eq!(comp.get_mem(0x0000), 2);
+
+    assert_eq!(comp.run_for(10), Ok(RunOutcome::Yielded));
+    assert_eq!(comp.cycles(), 7);
+    assert_eq!(comp.get_mem(0x0000), 3);
+
+    // an infinite loop without yields still returns
+    comp.insert_data(PROGRAM_POINTER + 3, &[0x0E40, PROGRAM_POINTER + 3]);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::BudgetExhausted));
+    assert_eq!(comp.cycles(), 1007);
+
+    comp.set_mem(CPU::INSTRUCTION_PTR, CPU::INSTRUCTION_PTR);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.cycles(), 1007);
+}
+
+#[test]
+fn test_halt() {
+    let machine_code = compile_asm(
+        "
+        MOV #2A &14;
+        YIELD;
+        HALT;
+        MOV #1 r0;
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    assert_eq!(comp.run_for(100), Ok(RunOutcome::Yielded));
+    assert!(!comp.is_halted());
+    assert_eq!(comp.run_for(100), Ok(RunOutcome::Halted));
+    assert!(comp.is_halted());
+    assert_eq!(comp.get_mem(CPU::EXIT_CODE_REGISTER), 0x2A);
+    // until_yield returns instead of spinning
+    assert_eq!(comp.until_yield(), Ok(()));
+    assert_eq!(comp.try_tick(), Err(CpuFault::Halted));
+    assert_eq!(comp.get_mem(0x0000), 0);
+
+    comp.resume();
+    comp.tick();
+    assert_eq!(comp.get_mem(0x0000), 1);
+}
+
+#[test]
+fn test_robin_halt() {
+    let machine_code = pipe(
+        "
+        fn main() {
+            halt(7);
+            yield();
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(CPU::EXIT_CODE_REGISTER), 7);
+}
+
+fn interrupt_program() -> CPU {
+    let machine_code = compile_asm(
+        "
+        MOV #handler &20;
+        MOV #handler &23;
+        :loop
+        ADD #1 r0;
+        JMP #loop;
+        :handler
+        ADD #1 r1;
+        MOV &15 r2;
+        RETI;
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    comp
+}
+
+#[test]
+fn test_timer_interrupt() {
+    let mut comp = interrupt_program();
+    comp.set_mem(CPU::TIMER_PERIOD_REGISTER, 10);
+    comp.set_mem(CPU::INTERRUPT_ENABLE_REGISTER, 1 << CPU::TIMER_INTERRUPT);
+
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::BudgetExhausted));
+    // the 100th interrupt is raised by the last cycle, so it hasn't been handled yet
+    assert_eq!(comp.get_mem(0x1), 99);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_PENDING_REGISTER), 1);
+    // the handler only ran while interrupts were disabled
+    assert_eq!(comp.get_mem(0x2), 0);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_ENABLE_REGISTER), 1);
+    // the main loop kept running between interrupts
+    // 2 setup instructions, 3 per interrupt, and the rest alternate ADD and JMP
+    assert_eq!(comp.get_mem(0x0), (1000 - 2 - 99 * 3_u16).div_ceil(2));
+}
+
+/// raises the interrupt line written to it
+struct Alarm;
+
+impl Device for Alarm {
+    fn read(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn write(&mut self, _offset: u16, value: u16, interrupts: &mut Interrupts) {
+        interrupts.raise(value);
+    }
+}
+
+#[test]
+fn test_device_interrupt() {
+    let mut comp = interrupt_program();
+    comp.devices_mut().map(0x30..=0x30, Alarm);
+    assert_eq!(comp.run_for(10), Ok(RunOutcome::BudgetExhausted));
+    let return_to = comp.get_mem(CPU::INSTRUCTION_PTR);
+
+    // disabled lines stay pending, and lines that don't exist are ignored
+    comp.set_mem(0x30, 3);
+    comp.set_mem(0x30, 16);
+    comp.raise_interrupt(16);
+    assert_eq!(comp.run_for(10), Ok(RunOutcome::BudgetExhausted));
+    assert_eq!(comp.get_mem(0x1), 0);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_PENDING_REGISTER), 1 << 3);
+
+    comp.set_mem(CPU::INTERRUPT_ENABLE_REGISTER, 1 << 3);
+    comp.tick();
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_PENDING_REGISTER), 0);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_RETURN_REGISTER), return_to);
+    assert_eq!(comp.run_for(2), Ok(RunOutcome::BudgetExhausted));
+    assert_eq!(comp.get_mem(0x1), 1);
+    assert_eq!(comp.get_mem(CPU::INSTRUCTION_PTR), return_to);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_ENABLE_REGISTER), 1 << 3);
+}
+
+/// doubles `r0` when the program writes `1` to its command register, counts how many times, and
+/// raises interrupt line 1 each time
+#[derive(Default)]
+struct Doubler {
+    command: u16,
+    count: u16,
+}
+
+impl Device for Doubler {
+    fn read(&self, offset: u16) -> u16 {
+        [self.command, self.count][usize::from(offset)]
+    }
+
+    fn write(&mut self, offset: u16, value: u16, _interrupts: &mut Interrupts) {
+        *[&mut self.command, &mut self.count][usize::from(offset)] = value;
+    }
+
+    fn on_yield(&mut self, registers: &mut [u16], interrupts: &mut Interrupts) -> bool {
+        if self.command != 1 {
+            return false;
+        }
+        registers[0] *= 2;
+        self.command = 0;
+        self.count += 1;
+        interrupts.raise(1);
+        true
+    }
+}
+
+#[test]
+fn test_bus_device() {
+    let mut comp = Bus::new(CPU::new());
+    comp.map(0x30..=0x31, Doubler::default());
+    comp.insert_data(
+        PROGRAM_POINTER,
+        &compile_asm("MOV #15 r0; MOV #1 &30; YIELD; YIELD; HALT;").unwrap(),
+    );
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    // the first yield is handled by the device, the second goes back to the host
+    assert_eq!(comp.run_for(u64::MAX), Ok(RunOutcome::Yielded));
+    assert_eq!(comp.get_mem(0x0), 0x2A);
+    assert_eq!(comp.get_mem(0x30), 0);
+    assert_eq!(comp.get_mem(0x31), 1);
+    assert_eq!(comp.get_mem(CPU::INTERRUPT_PENDING_REGISTER), 1 << 1);
+
+    // host accesses go straight to the device
+    comp.set_mem(0x31, 7);
+    assert_eq!(comp.get_mem(0x31), 7);
+    assert_eq!(comp.inner().get_mem(0x31), 7);
+    assert_eq!(comp.run_for(u64::MAX), Ok(RunOutcome::Halted));
+}
+
+/// counts how many times it's read, and keeps the last thing written to it
+#[derive(Default)]
+struct Counter {
+    reads: std::cell::Cell<u16>,
+    written: u16,
+}
+
+impl Device for Counter {
+    fn read(&self, _offset: u16) -> u16 {
+        self.reads.set(self.reads.get() + 1);
+        self.reads.get()
+    }
+
+    fn write(&mut self, _offset: u16, value: u16, _interrupts: &mut Interrupts) {
+        self.written = value;
+    }
+}
+
+#[test]
+fn test_bus_program_accesses() {
+    // the program's own loads and stores reach the device as they happen, without yielding
+    let mut comp = Bus::new(CPU::new());
+    comp.map(0x30..=0x30, Counter::default());
+    comp.insert_data(
+        PROGRAM_POINTER,
+        &compile_asm("MOV &30 r1; MOV &30 r2; MOV #9 &30; ADD #1 &30; HALT;").unwrap(),
+    );
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(u64::MAX), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x1), 1);
+    assert_eq!(comp.get_mem(0x2), 2);
+    // `ADD` reads the device a third time, then writes back one more than that
+    let counter: &Counter = comp.device(0x30).unwrap();
+    assert_eq!(counter.written, 4);
+    assert_eq!(counter.reads.get(), 3);
+}
+
+#[test]
+fn test_console_streams() {
+    let program = compile_asm(
+        "MOV #text r1; MOV #2 &12;
+        :input YIELD; PTRWRITE r0 r1; ADD #1 r1; JNZ r0 #input;
+        MOV #text r1; MOV #1 &12;
+        :output PTRREAD r1 r0; ADD #1 r1; YIELD; JNZ r0 #output;
+        HALT;
+        :text RESERVE #20;",
+    )
+    .unwrap();
+    let mut comp = ComputerIO::with_streams(CPU::new(), &b"echo me\nnot me\n"[..], Vec::new());
+    comp.insert_data(PROGRAM_POINTER, &program);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    assert_eq!(comp.run_for(u64::MAX), Ok(RunOutcome::Halted));
+    assert_eq!(comp.console().writer(), b"echo me\n\n");
+}
+
+#[test]
+fn test_stdin_reader_lazy() {
+    // stdin is only read once the program asks for input
+    let comp = ComputerIO::new(CPU::new());
+    assert!(!comp.console().reader().is_started());
+    let comp: ComputerIO<CPU> = ComputerIO::default();
+    assert!(!comp.console().reader().is_started());
+}
+
+#[test]
+fn test_console_characters() {
+    let program = compile_asm(
+        ":loop MOV #5 &12; YIELD; ADD r0 r5;
+        MOV #3 &12; YIELD; JEZ r0 #stop;
+        MOV #4 &12; YIELD; JMP #loop;
+        :stop MOV #6 &12; MOV #21 r0; YIELD; MOV #0 r0; YIELD;
+        HALT;",
+    )
+    .unwrap();
+    let mut comp = ComputerIO::with_streams(CPU::new(), "h\u{e9}y".as_bytes(), Vec::new());
+    comp.insert_data(PROGRAM_POINTER, &program);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    assert_eq!(comp.run_for(u64::MAX), Ok(RunOutcome::Halted));
+    assert_eq!(comp.console().writer(), "h\u{e9}y!".as_bytes());
+    // the input never blocks, even once it's ended
+    assert_eq!(comp.get_mem(0x5), 4);
+}
+
+#[test]
+fn test_debugger() {
+    let (program, labels) =
+        compile_asm_with_labels("MOV #3 r1; :loop ADD #1 r0; SUB #1 r1; JNZ r1 #loop; HALT;")
+            .unwrap();
+    let mut debugger = Debugger::new(CPU::new(), labels.clone());
+    let comp = debugger.computer_mut();
+    comp.insert_data(PROGRAM_POINTER, &program);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+
+    let script = "b loop\nc\nc\n\nd loop\nw r0\nc\nset r1 1\nu r0\nm r0..r1\nc\nq\nc\n";
+    let mut output = Vec::new();
+    debugger.repl(script.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert_eq!(output.matches("breakpoint at 8001 (loop)").count(), 4);
+    assert!(output.contains("0000 changed from 0002 to 0003"));
+    assert!(output.contains("0000 0003 0001"));
+    assert!(output.contains("halted with exit code 0000"));
+    // the last `c` comes after `q`
+    assert!(output.ends_with("(debug) "));
+    assert_eq!(debugger.computer().get_mem(0x0), 3);
+    assert!(debugger.computer().is_halted());
+
+    // `list` shows the instructions before the current one too, decoding from the label before it
+    let mut debugger = Debugger::new(CPU::new(), labels);
+    let comp = debugger.computer_mut();
+    comp.insert_data(PROGRAM_POINTER, &program);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    let mut output = Vec::new();
+    debugger.repl(&b"s 3\nl 2\n"[..], &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let listed: Vec<&str> = output
+        .rsplit("(debug) ")
+        .nth(1)
+        .unwrap()
+        .lines()
+        .map(str::trim_end)
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ":loop",
+            "  8001: 1110                ADD #0001 &0000;",
+            "  8002: 2111                SUB #0001 &0001;",
+            "> 8003: 0D81 8001           JNZ &0001 #8001;",
+            "  8005: 0900                HALT;",
+        ]
+    );
+}
+
+/// a small xorshift generator, so the round trip tests are reproducible
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn pick<T: Clone>(&mut self, options: &[T]) -> T {
+        options[self.next() as usize % options.len()].clone()
+    }
+
+    /// half the time a register, so every extension form gets used
+    #[allow(clippy::cast_possible_truncation)]
+    fn value(&mut self) -> Value {
+        let value = self.next() as u16;
+        Value::Given(if self.next().is_multiple_of(2) {
+            value & 0xF
+        } else {
+            value
+        })
+    }
+
+    fn item(&mut self) -> Item {
+        if self.next().is_multiple_of(2) {
+            Item::Address(self.value())
+        } else {
+            Item::Literal(self.value())
+        }
+    }
+
+    fn instruction(&mut self) -> Instruction {
+        const CMP_OPS: [CmpOp; 6] = [
+            CmpOp::Eq,
+            CmpOp::Ne,
+            CmpOp::Lt,
+            CmpOp::Le,
+            CmpOp::Gt,
+            CmpOp::Ge,
+        ];
+        match self.next() % 13 {
+            0 => Instruction::Yield,
+            1 => Instruction::Halt,
+            2 => Instruction::Reti,
+            3 => Instruction::Mov(self.item(), self.value()),
+            4 => Instruction::Swp(self.value(), self.value()),
+            5 => Instruction::Jmp(self.item()),
+            6 => Instruction::Jcmpz(self.next().is_multiple_of(2), self.value(), self.item()),
+            7 => Instruction::Ptrread(self.value(), self.value()),
+            8 => Instruction::Ptrwrite(self.item(), self.value()),
+            9 => Instruction::MathBinary(self.pick(&MATH_OPS), self.item(), self.value()),
+            10 => {
+                let (src_a, src) = match self.next() % 3 {
+                    0 => (addr(0), addr(0)),
+                    1 => (lit(0), addr(0)),
+                    _ => (addr(0), lit(0)),
+                };
+                let with_value = |item: Item, value| match item {
+                    Item::Address(_) => Item::Address(value),
+                    Item::Literal(_) => Item::Literal(value),
+                };
+                Instruction::MathTernary(
+                    self.pick(&MATH_OPS),
+                    with_value(src_a, self.value()),
+                    with_value(src, self.value()),
+                    self.value(),
+                )
+            }
+            11 => Instruction::JmpCmp(self.pick(&CMP_OPS), self.value(), self.item(), self.item()),
+            _ => Instruction::Cmp(self.pick(&CMP_OPS), self.value(), self.item(), self.value()),
+        }
+    }
+}
+
+#[test]
+fn test_decode_round_trip() {
+    let mut rng = Rng(0x1234_5678);
+    for _ in 0..10_000 {
+        let instruction = rng.instruction();
+        let machine_code = instruction.to_machine_code();
+        let (decoded, len) = decode(&machine_code);
+        assert_eq!(decoded, instruction, "{machine_code:X?}");
+        assert_eq!(len, machine_code.len(), "{instruction}");
+    }
+}
+
+#[test]
+fn test_decode_program() {
+    let program = compile_asm(
+        "MOV #text r1; MOV #1 &12;
+        :output PTRREAD r1 r0; ADD #1 r1; YIELD; JNZ r0 #output;
+        CLT r0 #30 &6000; HALT;
+        :text \"hi\"",
+    )
+    .unwrap();
+    let mut idx = 0;
+    let mut listing = Vec::new();
+    while idx < program.len() {
+        let (instruction, len) = decode(&program[idx..]);
+        assert_eq!(instruction.to_machine_code(), program[idx..idx + len]);
+        listing.push(instruction.to_string());
+        idx += len;
+    }
+    assert_eq!(
+        listing,
+        [
+            "MOV #800D &0001;",
+            "MOV #0001 &0012;",
+            "PTRREAD &0001 &0000;",
+            "ADD #0001 &0001;",
+            "YIELD;",
+            "JNZ &0000 #8004;",
+            "CLT &0000 #0030 &6000;",
+            "HALT;",
+            // data can't be told apart from instructions
+            "MOV &0006 &0008;",
+            "MOV &0006 &0009;",
+            "MOV &0000 &0000;",
+        ]
+    );
+    // undefined instructions, and ones missing their extension words, are data
+    assert_eq!(decode(&[0x0901]), (Instruction::Data(0x0901), 1));
+    assert_eq!(decode(&[0x0F10, 0x8000]), (Instruction::Data(0x0F10), 1));
+}
+
+#[test]
+fn test_asm_listing() {
+    let dir = std::env::temp_dir().join(format!("computer_listing_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("main.asm"),
+        ":start\nMOV #1 r1;\n:count\nADD #1 r1;\nJLT r1 #3 #count;\nHALT;\n\"Hi\"\n",
+    )
+    .unwrap();
+    let (image, labels, listing) = compile_asm_file_listed(&dir.join("main.asm"), &[]).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let listing = listing.to_string();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[0], "8000                               1  :start");
+    assert_eq!(lines[1], "8000  0111                         2  MOV #1 r1;");
+    assert_eq!(
+        lines[4],
+        "8002  6313 8001                    5  JLT r1 #3 #count;"
+    );
+    // a string is listed once, with all its characters
+    assert_eq!(lines[6], "8005  0048 0069 0000               7  \"Hi\"");
+
+    let map = symbol_map(&labels);
+    assert_eq!(map, "8000 start\n8001 count\n");
+    assert_eq!(parse_symbol_map(&map), Some(labels.clone()));
+    assert_eq!(parse_symbol_map("8000"), None);
+
+    let mut comp = CPU::new();
+    image.load(&mut comp);
+    comp.set_symbols(labels);
+    assert_eq!(comp.run_for(2), Ok(RunOutcome::BudgetExhausted));
+    let dump = format!("{comp:?}");
+    assert!(dump.starts_with("IP 8002 (count+1)\n"), "{dump}");
+    assert!(dump.contains("  ; start@8000 count@8001"), "{dump}");
+}
+
+#[test]
+fn test_asm_relaxation() {
+    // a constant defined later still fits in a nibble once it's known
+    let (machine_code, labels) =
+        compile_asm_with_labels("MOV #size r1; :after HALT; .equ size #4;").unwrap();
+    assert_eq!(machine_code, [0x0141, CPU::HALT_INSTRUCTION]);
+    assert_eq!(labels["after"], 0x8001);
+
+    // the operand fits in a nibble when it takes a word, but not when it doesn't, so it keeps the
+    // word it grew to
+    let (machine_code, labels) =
+        compile_asm_with_labels(":first MOV #(second-first-2) r1; :second HALT;").unwrap();
+    assert_eq!(machine_code, [0x0E11, 0x0000, CPU::HALT_INSTRUCTION]);
+    assert_eq!(labels["second"], 0x8002);
+
+    // jumps over code that shrinks land on instructions, and the program runs as written
+    let src = "
+        MOV #count r1;
+        :top
+        JEZ r1 #done;
+        ADD #step r2;
+        SUB #1 r1;
+        MOV #(done-top) r3;
+        JMP #top;
+        :done
+        HALT;
+        .equ count #3;
+        .equ step #(done-top);
+    ";
+    let (machine_code, labels) = compile_asm_with_labels(src).unwrap();
+    let mut starts = Vec::new();
+    let mut idx = 0;
+    while idx < machine_code.len() {
+        starts.push(PROGRAM_POINTER + u16::try_from(idx).unwrap());
+        idx += decode(&machine_code[idx..]).1;
+    }
+    for address in labels.values() {
+        assert!(starts.contains(address), "{address:X} isn't an instruction");
+    }
+    let size = labels["done"] - labels["top"];
+    assert!(size <= 0xF);
+    assert_eq!(machine_code.len(), 9);
+    let mut comp = CPU::new();
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    comp.insert_data(PROGRAM_POINTER as usize, &machine_code);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x2), size * 3);
+    assert_eq!(comp.get_mem(0x3), size);
+
+    let err = compile_asm_with_labels(".equ one #two; .equ two #one+1; MOV #one r1;").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Unsettled);
+}
+
+#[test]
+fn test_asm_stack() {
+    let machine_code = compile_asm(
+        "
+        MOV #5 r1;
+        PUSH #7;
+        CALL #double;
+        POP r3;
+        HALT;
+        :double
+        PUSH r1;
+        ADD r1 r1;
+        CALL #increment;
+        POP r2;
+        RET;
+        :increment
+        ADD #1 r1;
+        RET;
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x1), 11);
+    assert_eq!(comp.get_mem(0x2), 5);
+    assert_eq!(comp.get_mem(0x3), 7);
+    // everything pushed was popped, and the stack started at the end of memory
+    assert_eq!(comp.get_mem(stack::STACK_POINTER), 0);
+    assert_eq!(comp.get_mem(0xFFFF), 7);
+
+    let err = compile_asm("POP #1;").unwrap_err();
+    assert_eq!(err.to_string(), "1:5: expected an address, found `#1`");
+
+    let machine_code = pipe(
+        "
+        fn add_one(x) {
+            x += 1;
+            return x;
+        }
+        fn add_two(x) {
+            var y = add_one(x);
+            y = add_one(y);
+            return y;
+        }
+        fn main() {
+            var result = add_two(5);
+            *2 = result;
+            return 3;
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(1000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x2), 7);
+    assert_eq!(comp.get_mem(CPU::EXIT_CODE_REGISTER), 3);
+    // only `main`'s frame is left: the frame pointer it saved, and `result`
+    assert_eq!(comp.get_mem(stack::STACK_POINTER), 0xFFFE);
+}
+
+#[test]
+fn test_robin_recursion() {
+    let machine_code = pipe(
+        "
+        fn factorial(n) {
+            if (n == 0) {
+                return 1;
+            };
+            var m = n;
+            m -= 1;
+            var result = factorial(m);
+            result *= n;
+            return result;
+        }
+        fn fibonacci(n) {
+            if (n < 2) {
+                return n;
+            };
+            var a = n;
+            a -= 1;
+            var b = n;
+            b -= 2;
+            var x = fibonacci(a);
+            var y = fibonacci(b);
+            x += y;
+            return x;
+        }
+        fn main() {
+            var f = factorial(5);
+            *2 = f;
+            *3 = fibonacci(10);
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100_000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(0x2), 120);
+    assert_eq!(comp.get_mem(0x3), 55);
+    // every call's frame was taken off the stack again
+    assert_eq!(comp.get_mem(stack::STACK_POINTER), 0xFFFE);
+}
+
+#[test]
+fn test_robin_expressions() {
+    let machine_code = pipe(
+        "
+        fn side_effect() {
+            *16393 = 1;
+            return 1;
+        }
+        fn factorial(n) {
+            if (n == 0) {
+                return 1;
+            };
+            return n * factorial(n - 1);
+        }
+        fn main() {
+            var a = 3;
+            var b = 4;
+            *16384 = a + b * 2;
+            *16385 = (a + b) * 2;
+            *16386 = (a < b) + (a == b) * 2 + (b >= a) * 4;
+            *16387 = a < b && b < 10;
+            *16388 = a > b || side_effect();
+            *16389 = a > b && side_effect();
+            *16390 = !(a ^^ b) | (a << 2) - (b >> 1);
+            *16391 = a + (b + (a + (b + (a + (b + (a + (b + (a + (b + (a + (b * a)))))))))));
+            *16392 = factorial(b) - factorial(a) * 2;
+            var c = &b;
+            *16394 = *c + (a & 6 | 8);
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100_000), Ok(RunOutcome::Halted));
+    let results: Vec<u16> = (0x4000..=0x400A)
+        .map(|address| comp.get_mem(address))
+        .collect();
+    // `side_effect` only runs for `||`, since the `&&` is already false
+    assert_eq!(results, [11, 14, 5, 1, 1, 0, 11, 50, 12, 1, 14]);
+    // only main's frame, with its three locals, is left on the stack
+    assert_eq!(comp.get_mem(stack::STACK_POINTER), 0xFFFC);
+}
+
+#[test]
+fn test_robin_control_flow() {
+    let machine_code = pipe(
+        "
+        fn classify(n) {
+            if (n < 10) {
+                return 1;
+            } else if (n < 100) {
+                return 2;
+            } else {
+                return 3;
+            };
+        }
+        fn main() {
+            *16384 = classify(5) * 100 + classify(50) * 10 + classify(500);
+            var total = 0;
+            for (var i = 0; i < 5; i += 1) {
+                for (var j = 0; j < 5; j += 1) {
+                    if (j > i) {
+                        break;
+                    };
+                    if (j == 1) {
+                        continue;
+                    };
+                    total += 1;
+                };
+            };
+            *16385 = total;
+            var count = 0;
+            loop {
+                count += 1;
+                var k = 0;
+                while (1) {
+                    k += 1;
+                    if (k == 3) {
+                        break;
+                    };
+                };
+                if (count > 10) {
+                    break;
+                };
+                count += k;
+            };
+            *16386 = count;
+            if (count == 0) {
+                *16387 = 1;
+            } else {
+                *16387 = 2;
+            };
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100_000), Ok(RunOutcome::Halted));
+    let results: Vec<u16> = (16384..=16387)
+        .map(|address| comp.get_mem(address))
+        .collect();
+    assert_eq!(results, [123, 11, 13, 2]);
+
+    let error = pipe("fn main() { break; }");
+    assert!(error.is_err());
+}
+
+#[test]
+fn test_robin_repeated_conditions() {
+    // both sides of `||` and `&&` are the same call, so they mustn't share labels
+    let machine_code = pipe(
+        "
+        fn f() {
+            return 1;
+        }
+        fn main() {
+            if (f() || f()) {
+                *16384 = 3;
+            };
+            if (f() && f()) {
+                *16385 = 4;
+            };
+        }
+        ",
+    )
+    .unwrap();
+    let mut comp = CPU::new();
+    comp.insert_data(PROGRAM_POINTER, &machine_code);
+    comp.set_mem(CPU::INSTRUCTION_PTR, PROGRAM_POINTER);
+    assert_eq!(comp.run_for(100_000), Ok(RunOutcome::Halted));
+    assert_eq!(comp.get_mem(16384), 3);
+    assert_eq!(comp.get_mem(16385), 4);
+}
+
+#[test]
+fn test_robin_diagnostics() {
+    // parsing carries on after each error, at the next statement
+    let err = pipe("fn main() {\n    var a = 1 + ;\n    var b = (2;\n}\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "2:17: expected an expression, found `;`\n3:15: expected `)`, found `;`"
+    );
+    let err = pipe("global s = \"abc").unwrap_err();
+    assert_eq!(err.to_string(), "1:12: this string never ends");
+
+    let src = "fn helper(x) {
+    var unused = 3;
+    return x;
+    x += 1;
+}
+fn main() {
+    var y = helper(1, 2);
+    z = y;
+    break;
+}
+";
+    let diagnostics: Vec<String> = check(src).iter().map(ToString::to_string).collect();
+    assert_eq!(
+        diagnostics,
+        [
+            "2:5: warning: `unused` is never read",
+            "4:5: warning: this statement can never run",
+            "7:5: `helper` takes 1 argument(s), but was given 2",
+            "8:5: `z` isn't defined",
+            "9:5: `break` outside of a loop",
+        ]
+    );
+    assert_eq!(
+        check(src)[0].render("test.rbn", src),
+        "warning: `unused` is never read
+ --> test.rbn:2:5
+  |
+2 |     var unused = 3;
+  |     ^^^^^^^^^^^^^^"
+    );
+    // warnings don't stop a program compiling
+    assert!(pipe("fn main() { var unused = 1; return 0; }").is_ok());
+}
+
+#[test]
+fn test_robin_artifacts() {
+    let src = "fn main() { var unused = 2; return 1 + 2; }";
+    let options = CompileOptions {
+        tokens: true,
+        asm: true,
+        machine_code: true,
+        ..CompileOptions::default()
+    };
+    let output = compile_robin(src, &options).unwrap();
+    let tokens = output.tokens.unwrap();
+    assert_eq!(tokens[0].node, RobinToken::Keyword(RobinKeyword::Fn));
+    assert_eq!((tokens[1].span.line, tokens[1].span.column), (1, 4));
+    assert!(output.asm.unwrap().contains(":_fn_main"));
+    assert_eq!(output.machine_code.unwrap(), pipe(src).unwrap());
+    // only what was asked for is kept
+    assert!(output.ast.is_none());
+    assert!(output.symbols.is_none());
+    assert!(output.listing.is_none());
+    assert!(output.object.is_none());
+    assert_eq!(output.warnings.len(), 1);
+
+    let output = compile_robin(src, &CompileOptions::all()).unwrap();
+    assert!(matches!(
+        &output.ast.unwrap()[0].node,
+        TopLevelSyntax::Function(name, ..) if &**name == "main"
+    ));
+    assert!(output.symbols.unwrap().contains_key("_fn_main"));
+    assert!(output.object.is_some());
+    assert!(compile_robin("fn main() { x = 1; }", &options).is_err());
+}
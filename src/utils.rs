@@ -1,9 +1,4 @@
-use std::{fmt::Debug, hash::{Hash, Hasher}};
-
-pub fn print_and_ret<T: Debug>(t: T) -> T {
-    println!("{t:?}");
-    t
-}
+use std::hash::{Hash, Hasher};
 
 pub fn get_hash<T: Hash>(t: T) -> u64 {
     let mut hasher = std::collections::hash_map::DefaultHasher::new();